@@ -48,6 +48,7 @@ pub enum UsageKind {
     RustHeap,
     UserMem,
     PageCache,
+    BufferCache,
     PageTable,
     Dma,
     Global,
@@ -57,6 +58,7 @@ const ALL_KINDS: &[UsageKind] = &[
     UsageKind::RustHeap,
     UsageKind::UserMem,
     UsageKind::PageCache,
+    UsageKind::BufferCache,
     UsageKind::PageTable,
     UsageKind::Dma,
     UsageKind::Global,
@@ -70,6 +72,11 @@ impl UsageStats {
         Self([0; ALL_KINDS.len()])
     }
 
+    /// Returns the number of bytes allocated for `kind`.
+    pub fn get(&self, kind: UsageKind) -> usize {
+        self.0[kind as usize]
+    }
+
     fn alloc(&mut self, kind: UsageKind, size: usize) {
         self.0[kind as usize] += size;
     }
@@ -88,6 +95,7 @@ impl fmt::Debug for UsageStats {
                     UsageKind::RustHeap => "Rust Heap",
                     UsageKind::UserMem => "User Memory",
                     UsageKind::PageCache => "Page Cache",
+                    UsageKind::BufferCache => "Buffer Cache",
                     UsageKind::PageTable => "Page Table",
                     UsageKind::Dma => "Dma",
                     UsageKind::Global => "Global",
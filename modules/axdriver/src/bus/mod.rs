@@ -2,3 +2,22 @@
 mod mmio;
 #[cfg(bus = "pci")]
 mod pci;
+
+#[cfg(any(feature = "virtio", feature = "ixgbe"))]
+use axhal::mem::{VirtAddr, phys_to_virt};
+
+#[cfg(bus = "pci")]
+pub use self::pci::map_bar_wc;
+
+/// Converts a physical MMIO address to the virtual address it is mapped at.
+///
+/// PCI memory BARs are mapped by `ioremap` during bus probing, other regions
+/// are expected to be in the linear mapping.
+#[cfg(any(feature = "virtio", feature = "ixgbe"))]
+pub(crate) fn mmio_phys_to_virt(paddr: usize) -> VirtAddr {
+    #[cfg(bus = "pci")]
+    if let Some(vaddr) = pci::bar_phys_to_virt(paddr) {
+        return vaddr;
+    }
+    phys_to_virt(paddr.into())
+}
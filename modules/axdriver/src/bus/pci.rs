@@ -1,12 +1,81 @@
+use alloc::collections::BTreeMap;
+
 use axdriver_pci::{
     BarInfo, Cam, Command, DeviceFunction, HeaderType, MemoryBarType, PciRangeAllocator, PciRoot,
 };
-use axhal::mem::phys_to_virt;
+use axhal::mem::{VirtAddr, phys_to_virt};
+use kspin::SpinNoIrq;
 
-use crate::{AllDevices, prelude::*};
+use crate::{AllDevices, DriverIf, prelude::*};
 
 const PCI_BAR_NUM: u8 = 6;
 
+/// A PCI memory BAR mapped into the kernel address space.
+struct BarMapping {
+    size: usize,
+    /// Virtual address of the device memory mapping made while probing.
+    vaddr: usize,
+    prefetchable: bool,
+    /// Virtual address of the write-combining mapping, if a driver asked for
+    /// one.
+    wc_vaddr: Option<usize>,
+}
+
+/// Kernel mappings of PCI memory BARs, by physical start address.
+static BAR_MAPPINGS: SpinNoIrq<BTreeMap<usize, BarMapping>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Maps a BAR as device memory, whether it is prefetchable or not, as a
+/// driver may still rely on its accesses being neither merged nor reordered.
+fn map_bar(paddr: usize, size: usize, prefetchable: bool) -> DevResult {
+    let mut mappings = BAR_MAPPINGS.lock();
+    if mappings.contains_key(&paddr) {
+        return Ok(());
+    }
+    let vaddr = crate_interface::call_interface!(DriverIf::ioremap(paddr, size, false))
+        .ok_or(DevError::NoMemory)?;
+    mappings.insert(
+        paddr,
+        BarMapping {
+            size,
+            vaddr,
+            prefetchable,
+            wc_vaddr: None,
+        },
+    );
+    Ok(())
+}
+
+/// Maps the prefetchable PCI memory BAR starting at `paddr` as
+/// write-combining memory, and returns its virtual address.
+///
+/// BARs are mapped as device memory while probing. Drivers opt into write
+/// combining with this for regions such as framebuffers, as with Linux
+/// `pci_iomap_wc`. Fails with [`DevError::InvalidParam`] if `paddr` is not
+/// the start of a prefetchable BAR.
+pub fn map_bar_wc(paddr: usize) -> DevResult<VirtAddr> {
+    let mut mappings = BAR_MAPPINGS.lock();
+    let bar = mappings
+        .get_mut(&paddr)
+        .filter(|bar| bar.prefetchable)
+        .ok_or(DevError::InvalidParam)?;
+    if let Some(vaddr) = bar.wc_vaddr {
+        return Ok(vaddr.into());
+    }
+    let vaddr = crate_interface::call_interface!(DriverIf::ioremap(paddr, bar.size, true))
+        .ok_or(DevError::NoMemory)?;
+    bar.wc_vaddr = Some(vaddr);
+    Ok(vaddr.into())
+}
+
+/// Returns the virtual address a physical address within a mapped PCI memory
+/// BAR is mapped to as device memory.
+#[cfg(any(feature = "virtio", feature = "ixgbe"))]
+pub(crate) fn bar_phys_to_virt(paddr: usize) -> Option<VirtAddr> {
+    let mappings = BAR_MAPPINGS.lock();
+    let (&start, bar) = mappings.range(..=paddr).next_back()?;
+    (paddr < start + bar.size).then(|| VirtAddr::from(bar.vaddr + (paddr - start)))
+}
+
 fn config_pci_device(
     root: &mut PciRoot,
     bdf: DeviceFunction,
@@ -64,6 +133,7 @@ fn config_pci_device(
                         },
                         if prefetchable { " pref" } else { "" },
                     );
+                    map_bar(address as usize, size as usize, prefetchable)?;
                 }
             }
         }
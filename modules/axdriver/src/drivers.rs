@@ -78,20 +78,17 @@ cfg_if::cfg_if! {
 
 cfg_if::cfg_if! {
     if #[cfg(block_dev = "sdmmc-gpt")] {
-        use axdriver_block::{gpt::GptPartitionDev, sdmmc::SdMmcDriver};
+        use axdriver_block::sdmmc::SdMmcDriver;
         use axhal::mem::phys_to_virt;
 
         pub struct SdMmcGptDriver;
-        register_block_driver!(SdMmcGptDriver, GptPartitionDev<SdMmcDriver>);
+        register_block_driver!(SdMmcGptDriver, SdMmcDriver);
 
         impl DriverProbe for SdMmcGptDriver {
             fn probe_global() -> Option<AxDeviceEnum> {
                 // FIXME: this configuration is specific to vf2!
-                let root = "root".parse().unwrap();
                 let sdmmc = unsafe { SdMmcDriver::new(phys_to_virt(0x1602_0000.into()).into()) };
-                GptPartitionDev::try_new(sdmmc, |_, part| part.name == root)
-                    .ok()
-                    .map(AxDeviceEnum::from_block)
+                Some(AxDeviceEnum::from_block(sdmmc))
             }
         }
     }
@@ -100,7 +97,6 @@ cfg_if::cfg_if! {
 cfg_if::cfg_if! {
     if #[cfg(block_dev = "ahci-gpt")] {
         use axdriver_base::{DevResult, DevError};
-        use axdriver_block::gpt::GptPartitionDev;
         use axhal::mem::phys_to_virt;
 
         pub struct AhciDriver(ahci_driver::ahci_device);
@@ -152,16 +148,13 @@ cfg_if::cfg_if! {
         }
 
         pub struct AhciGptDriver;
-        register_block_driver!(AhciGptDriver, GptPartitionDev<AhciDriver>);
+        register_block_driver!(AhciGptDriver, AhciDriver);
 
         impl DriverProbe for AhciGptDriver {
             fn probe_global() -> Option<AxDeviceEnum> {
                 let mut dev = ahci_driver::ahci_device::default();
                 ahci_driver::ahci_init(&mut dev);
-                let dev = AhciDriver(dev);
-                GptPartitionDev::try_new(dev, |_, _| true)
-                    .ok()
-                    .map(AxDeviceEnum::from_block)
+                Some(AxDeviceEnum::from_block(AhciDriver(dev)))
             }
         }
 
@@ -213,7 +206,6 @@ cfg_if::cfg_if! {
 cfg_if::cfg_if! {
     if #[cfg(net_dev = "ixgbe")] {
         use crate::ixgbe::IxgbeHalImpl;
-        use axhal::mem::phys_to_virt;
         pub struct IxgbeDriver;
         register_net_driver!(IxgbeDriver, axdriver_net::ixgbe::IxgbeNic<IxgbeHalImpl, 1024, 1>);
         impl DriverProbe for IxgbeDriver {
@@ -240,7 +232,7 @@ cfg_if::cfg_if! {
                                 ..
                             } => {
                                 let ixgbe_nic = IxgbeNic::<IxgbeHalImpl, QS, QN>::init(
-                                    phys_to_virt((address as usize).into()).into(),
+                                    crate::bus::mmio_phys_to_virt(address as usize).into(),
                                     size as usize
                                 )
                                 .expect("failed to initialize ixgbe device");
@@ -1,8 +1,10 @@
 use axdma::{BusAddr, DMAInfo, alloc_coherent, dealloc_coherent};
 use axdriver_net::ixgbe::{IxgbeHal, PhysAddr as IxgbePhysAddr};
-use axhal::mem::{phys_to_virt, virt_to_phys};
+use axhal::mem::virt_to_phys;
 use core::{alloc::Layout, ptr::NonNull};
 
+use crate::bus::mmio_phys_to_virt;
+
 pub struct IxgbeHalImpl;
 
 unsafe impl IxgbeHal for IxgbeHalImpl {
@@ -25,7 +27,7 @@ unsafe impl IxgbeHal for IxgbeHalImpl {
     }
 
     unsafe fn mmio_phys_to_virt(paddr: IxgbePhysAddr, _size: usize) -> NonNull<u8> {
-        NonNull::new(phys_to_virt(paddr.into()).as_mut_ptr()).unwrap()
+        NonNull::new(mmio_phys_to_virt(paddr).as_mut_ptr()).unwrap()
     }
 
     unsafe fn mmio_virt_to_phys(vaddr: NonNull<u8>, _size: usize) -> IxgbePhysAddr {
@@ -56,7 +56,7 @@
 //! [trait objects]: https://doc.rust-lang.org/book/ch17-02-trait-objects.html
 //! [dyn]: https://doc.rust-lang.org/std/keyword.dyn.html
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(doc_auto_cfg)]
 #![feature(associated_type_defaults)]
 #![feature(c_variadic)]
@@ -80,8 +80,13 @@ mod virtio;
 #[cfg(feature = "ixgbe")]
 mod ixgbe;
 
+#[cfg(feature = "block")]
+pub mod partition;
 pub mod prelude;
 
+#[cfg(feature = "block")]
+use alloc::{format, vec::Vec};
+
 #[allow(unused_imports)]
 use self::prelude::*;
 #[cfg(feature = "block")]
@@ -91,6 +96,20 @@ pub use self::structs::AxDisplayDevice;
 #[cfg(feature = "net")]
 pub use self::structs::AxNetDevice;
 pub use self::structs::{AxDeviceContainer, AxDeviceEnum};
+#[cfg(bus = "pci")]
+pub use self::bus::map_bar_wc;
+
+/// Extern interfaces that must be implemented in other crates.
+#[crate_interface::def_interface]
+pub trait DriverIf {
+    /// Maps the MMIO region `[paddr, paddr + size)` into the kernel address
+    /// space, and returns its virtual address.
+    ///
+    /// The mapping is kept for the lifetime of the kernel. It is device
+    /// memory unless `write_combine` is `true`, which drivers only ask for
+    /// when they know the region tolerates merged and reordered writes.
+    fn ioremap(paddr: usize, size: usize, write_combine: bool) -> Option<usize>;
+}
 
 /// A structure that contains all device drivers, organized by their category.
 #[derive(Default)]
@@ -146,7 +165,7 @@ impl AllDevices {
             #[cfg(feature = "net")]
             AxDeviceEnum::Net(dev) => self.net.push(dev),
             #[cfg(feature = "block")]
-            AxDeviceEnum::Block(dev) => self.block.push(dev),
+            AxDeviceEnum::Block(dev) => self.add_disk(dev),
             #[cfg(feature = "display")]
             AxDeviceEnum::Display(dev) => self.display.push(dev),
             #[cfg(feature = "input")]
@@ -155,6 +174,32 @@ impl AllDevices {
     }
 }
 
+#[cfg(feature = "block")]
+impl AllDevices {
+    /// Adds a whole disk, numbered in probe order (e.g. `virtio-blk0`),
+    /// followed by its partitions (e.g. `virtio-blk0p1`).
+    fn add_disk(&mut self, mut disk: AxBlockDevice) {
+        let index = self.block.iter().filter(|it| it.info().is_none()).count();
+        disk.set_name(format!("{}{index}", disk.device_name()));
+        let partitions = disk.partitions().unwrap_or_else(|err| {
+            warn!(
+                "failed to read partitions of {}: {err:?}",
+                disk.device_name()
+            );
+            Vec::new()
+        });
+        self.block.push(disk);
+        for partition in partitions {
+            info!(
+                "registered a new partition {:?}: {:?}",
+                partition.device_name(),
+                partition.info().unwrap(),
+            );
+            self.block.push(partition);
+        }
+    }
+}
+
 /// Probes and initializes all device drivers, returns the [`AllDevices`]
 /// struct.
 pub fn init_drivers() -> AllDevices {
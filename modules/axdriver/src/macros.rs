@@ -12,9 +12,13 @@ macro_rules! register_net_driver {
 
 macro_rules! register_block_driver {
     ($driver_type:ty, $device_type:ty) => {
-        /// The unified type of the NIC devices.
+        /// The type of the disks given by the block driver.
+        #[cfg(not(feature = "dyn"))]
+        pub type AxBlockDisk = $device_type;
+
+        /// The unified type of the block storage devices.
         #[cfg(not(feature = "dyn"))]
-        pub type AxBlockDevice = $device_type;
+        pub type AxBlockDevice = crate::partition::PartitionDev<$device_type>;
     };
 }
 
@@ -0,0 +1,770 @@
+//! Partition tables (MBR and GPT) on top of any block device.
+//!
+//! Every block device is a [`PartitionDev`]: the whole disk given by its
+//! driver, and one device for each of its partitions, named `<disk>p<N>`.
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
+use core::{fmt, str::FromStr};
+
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_block::BlockDriverOps;
+use kspin::SpinNoIrq;
+
+/// A GUID as stored in a GPT, with mixed-endian fields.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Returns whether all bytes are zero, which marks unused GPT entries.
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        b[10..].iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for Guid {
+    type Err = DevError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split('-').collect::<Vec<_>>();
+        if fields.iter().map(|it| it.len()).ne([8, 4, 4, 4, 12]) {
+            return Err(DevError::InvalidParam);
+        }
+        let hex = |s: &str| u64::from_str_radix(s, 16).map_err(|_| DevError::InvalidParam);
+        let mut guid = [0u8; 16];
+        guid[0..4].copy_from_slice(&(hex(fields[0])? as u32).to_le_bytes());
+        guid[4..6].copy_from_slice(&(hex(fields[1])? as u16).to_le_bytes());
+        guid[6..8].copy_from_slice(&(hex(fields[2])? as u16).to_le_bytes());
+        guid[8..10].copy_from_slice(&(hex(fields[3])? as u16).to_be_bytes());
+        guid[10..].copy_from_slice(&hex(fields[4])?.to_be_bytes()[2..]);
+        Ok(Self(guid))
+    }
+}
+
+/// The type of a partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    /// The partition type GUID of a GPT entry.
+    Gpt(Guid),
+    /// The system ID of an MBR entry.
+    Mbr(u8),
+}
+
+/// Information about a partition.
+#[derive(Debug, Clone)]
+pub struct PartitionInfo {
+    /// The number of the partition, starting from 1. Logical partitions of
+    /// an MBR are numbered from 5.
+    pub index: usize,
+    /// The partition name of a GPT entry, empty for MBR partitions.
+    pub name: String,
+    /// The unique partition GUID for GPT, or `SSSSSSSS-NN` (disk signature
+    /// and partition number) for MBR, as Linux reports in `PARTUUID`.
+    pub uuid: String,
+    pub part_type: PartitionType,
+    /// The first block of the partition.
+    pub start: u64,
+    /// The number of blocks in the partition.
+    pub num_blocks: u64,
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+const GPT_PROTECTIVE: u8 = 0xee;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// Upper bound on the number of logical partitions, to stop on cyclic EBR
+/// chains.
+const MAX_LOGICAL: usize = 128;
+/// Upper bound on the number of GPT entries, larger tables are rejected.
+const MAX_GPT_ENTRIES: u32 = 1024;
+
+/// An MBR partition entry.
+struct MbrEntry {
+    status: u8,
+    part_type: u8,
+    start: u64,
+    num_blocks: u64,
+}
+
+fn mbr_entries(sector: &[u8]) -> impl Iterator<Item = MbrEntry> + '_ {
+    (0..4).map(|i| {
+        let entry = &sector[446 + i * 16..446 + (i + 1) * 16];
+        MbrEntry {
+            status: entry[0],
+            part_type: entry[4],
+            start: read_u32(entry, 8) as u64,
+            num_blocks: read_u32(entry, 12) as u64,
+        }
+    })
+}
+
+/// Reads the partition table of `disk`.
+///
+/// Returns an empty list if the disk has no (valid) partition table.
+pub fn read_partitions<D: BlockDriverOps + ?Sized>(disk: &mut D) -> DevResult<Vec<PartitionInfo>> {
+    let block_size = disk.block_size();
+    if block_size < 512 {
+        return Ok(Vec::new());
+    }
+    let mut sector = vec![0u8; block_size];
+    disk.read_block(0, &mut sector)?;
+    if sector[510..512] != [0x55, 0xaa] {
+        return Ok(Vec::new());
+    }
+
+    let total = disk.num_blocks();
+    let mut valid = false;
+    for entry in mbr_entries(&sector) {
+        if entry.status & 0x7f != 0 {
+            // Not an MBR, e.g. a FAT boot sector.
+            return Ok(Vec::new());
+        }
+        if entry.part_type == GPT_PROTECTIVE {
+            return read_gpt(disk);
+        }
+        if entry.start + entry.num_blocks > total {
+            return Ok(Vec::new());
+        }
+        valid |= entry.part_type != 0;
+    }
+    if !valid {
+        return Ok(Vec::new());
+    }
+    read_mbr(disk, &sector)
+}
+
+fn read_mbr<D: BlockDriverOps + ?Sized>(
+    disk: &mut D,
+    sector: &[u8],
+) -> DevResult<Vec<PartitionInfo>> {
+    let signature = read_u32(sector, 440);
+    let partition = |index: usize, part_type: u8, start: u64, num_blocks: u64| PartitionInfo {
+        index,
+        name: String::new(),
+        uuid: format!("{signature:08x}-{index:02x}"),
+        part_type: PartitionType::Mbr(part_type),
+        start,
+        num_blocks,
+    };
+
+    let mut result = Vec::new();
+    let mut extended = None;
+    for (i, entry) in mbr_entries(sector).enumerate() {
+        if entry.part_type == 0 {
+            continue;
+        }
+        if MBR_EXTENDED.contains(&entry.part_type) {
+            extended.get_or_insert(entry.start);
+        } else {
+            result.push(partition(
+                i + 1,
+                entry.part_type,
+                entry.start,
+                entry.num_blocks,
+            ));
+        }
+    }
+
+    // Logical partitions are chained through extended boot records, where
+    // the next record is addressed relative to the extended partition.
+    if let Some(ext_start) = extended {
+        let mut ebr = vec![0u8; sector.len()];
+        let mut current = ext_start;
+        for index in 5..5 + MAX_LOGICAL {
+            disk.read_block(current, &mut ebr)?;
+            if ebr[510..512] != [0x55, 0xaa] {
+                break;
+            }
+            let mut entries = mbr_entries(&ebr);
+            let (logical, next) = (entries.next().unwrap(), entries.next().unwrap());
+            if logical.part_type != 0 {
+                result.push(partition(
+                    index,
+                    logical.part_type,
+                    current + logical.start,
+                    logical.num_blocks,
+                ));
+            }
+            if !MBR_EXTENDED.contains(&next.part_type) {
+                break;
+            }
+            current = ext_start + next.start;
+        }
+    }
+    Ok(result)
+}
+
+/// Computes the CRC32 (IEEE 802.3) of `data`, as used by GPT.
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// A validated GPT header together with its partition entry array.
+struct Gpt {
+    first_usable: u64,
+    last_usable: u64,
+    entry_size: usize,
+    entries: Vec<u8>,
+}
+
+/// Reads the GPT header at `lba` and its entries, returning `None` if either
+/// fails its checks.
+fn read_gpt_at<D: BlockDriverOps + ?Sized>(disk: &mut D, lba: u64) -> DevResult<Option<Gpt>> {
+    let block_size = disk.block_size();
+    let total = disk.num_blocks();
+    let mut header = vec![0u8; block_size];
+    disk.read_block(lba, &mut header)?;
+    if &header[0..8] != b"EFI PART" {
+        return Ok(None);
+    }
+    let header_size = read_u32(&header, 12) as usize;
+    if !(92..=block_size).contains(&header_size) {
+        return Ok(None);
+    }
+    let header_crc = read_u32(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != header_crc || read_u64(&header, 24) != lba {
+        return Ok(None);
+    }
+
+    let first_usable = read_u64(&header, 40);
+    let last_usable = read_u64(&header, 48);
+    let entries_lba = read_u64(&header, 72);
+    let num_entries = read_u32(&header, 80);
+    let entry_size = read_u32(&header, 84) as usize;
+    if first_usable > last_usable
+        || last_usable >= total
+        || num_entries > MAX_GPT_ENTRIES
+        || entry_size < 128
+        || !entry_size.is_power_of_two()
+    {
+        return Ok(None);
+    }
+    let len = num_entries as usize * entry_size;
+    let blocks = len.div_ceil(block_size) as u64;
+    if entries_lba
+        .checked_add(blocks)
+        .is_none_or(|end| end > total)
+    {
+        return Ok(None);
+    }
+    let mut entries = vec![0u8; blocks as usize * block_size];
+    for (i, block) in entries.chunks_exact_mut(block_size).enumerate() {
+        disk.read_block(entries_lba + i as u64, block)?;
+    }
+    entries.truncate(len);
+    if crc32(&entries) != read_u32(&header, 88) {
+        return Ok(None);
+    }
+    Ok(Some(Gpt {
+        first_usable,
+        last_usable,
+        entry_size,
+        entries,
+    }))
+}
+
+fn read_gpt<D: BlockDriverOps + ?Sized>(disk: &mut D) -> DevResult<Vec<PartitionInfo>> {
+    let gpt = match read_gpt_at(disk, 1)? {
+        Some(gpt) => gpt,
+        None => {
+            // The backup header is in the last block of the disk.
+            let backup = disk.num_blocks().saturating_sub(1);
+            match read_gpt_at(disk, backup)? {
+                Some(gpt) => {
+                    warn!("primary GPT is corrupt, using the backup");
+                    gpt
+                }
+                None => {
+                    warn!("protective MBR without valid GPT");
+                    return Ok(Vec::new());
+                }
+            }
+        }
+    };
+
+    let mut result = Vec::new();
+    for (i, entry) in gpt.entries.chunks_exact(gpt.entry_size).enumerate() {
+        let type_guid = Guid(entry[0..16].try_into().unwrap());
+        if type_guid.is_zero() {
+            continue;
+        }
+        let unique_guid = Guid(entry[16..32].try_into().unwrap());
+        let first = read_u64(entry, 32);
+        let last = read_u64(entry, 40);
+        if last < first || first < gpt.first_usable || last > gpt.last_usable {
+            warn!("GPT entry {} has invalid range {first}..={last}", i + 1);
+            continue;
+        }
+        let name = char::decode_utf16(
+            entry[56..128]
+                .chunks_exact(2)
+                .map(|it| u16::from_le_bytes([it[0], it[1]]))
+                .take_while(|&it| it != 0),
+        )
+        .map(|it| it.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+        result.push(PartitionInfo {
+            index: i + 1,
+            name,
+            uuid: format!("{unique_guid}"),
+            part_type: PartitionType::Gpt(type_guid),
+            start: first,
+            num_blocks: last - first + 1,
+        });
+    }
+    Ok(result)
+}
+
+/// Selects a partition of a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionSelector<'a> {
+    /// The first partition.
+    First,
+    /// The GPT partition with the given name (`PARTLABEL=<name>`).
+    Label(&'a str),
+    /// The partition with the given unique GUID, or MBR `SSSSSSSS-NN` id
+    /// (`PARTUUID=<uuid>`).
+    Uuid(&'a str),
+}
+
+impl<'a> PartitionSelector<'a> {
+    /// Parses a selector in the form of `PARTLABEL=<name>` or
+    /// `PARTUUID=<uuid>`.
+    pub fn parse(s: &'a str) -> Option<Self> {
+        if let Some(label) = s.strip_prefix("PARTLABEL=") {
+            Some(Self::Label(label))
+        } else {
+            s.strip_prefix("PARTUUID=").map(Self::Uuid)
+        }
+    }
+
+    /// Returns whether `info` is the selected partition.
+    pub fn matches(&self, info: &PartitionInfo) -> bool {
+        match self {
+            Self::First => true,
+            Self::Label(label) => info.name == *label,
+            Self::Uuid(uuid) => info.uuid.eq_ignore_ascii_case(uuid),
+        }
+    }
+}
+
+/// A partition of a disk, or the whole disk, used as a block device.
+///
+/// The whole disk and its partitions share the underlying driver.
+pub struct PartitionDev<D: ?Sized> {
+    disk: Arc<SpinNoIrq<Box<D>>>,
+    info: Option<PartitionInfo>,
+    name: String,
+    block_size: usize,
+    start: u64,
+    num_blocks: u64,
+}
+
+impl<D: BlockDriverOps + ?Sized> PartitionDev<D> {
+    /// Wraps the whole `disk`, named after its driver.
+    pub fn new(disk: Box<D>) -> Self {
+        Self {
+            name: disk.device_name().into(),
+            block_size: disk.block_size(),
+            start: 0,
+            num_blocks: disk.num_blocks(),
+            info: None,
+            disk: Arc::new(SpinNoIrq::new(disk)),
+        }
+    }
+
+    /// Returns the partition, or `None` if this is the whole disk.
+    pub fn info(&self) -> Option<&PartitionInfo> {
+        self.info.as_ref()
+    }
+
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Returns a device for each partition of the whole disk, named
+    /// `<disk>p<N>`.
+    ///
+    /// Returns an empty list for a partition, or a disk without a partition
+    /// table.
+    pub fn partitions(&self) -> DevResult<Vec<Self>> {
+        if self.info.is_some() {
+            return Ok(Vec::new());
+        }
+        let partitions = read_partitions(&mut **self.disk.lock())?;
+        Ok(partitions
+            .into_iter()
+            .map(|info| Self {
+                disk: self.disk.clone(),
+                name: format!("{}p{}", self.name, info.index),
+                block_size: self.block_size,
+                start: info.start,
+                num_blocks: info.num_blocks,
+                info: Some(info),
+            })
+            .collect())
+    }
+
+    fn check_range(&self, block_id: u64, len: usize) -> DevResult {
+        let blocks = (len as u64).div_ceil(self.block_size as u64);
+        if block_id
+            .checked_add(blocks)
+            .is_none_or(|end| end > self.num_blocks)
+        {
+            return Err(DevError::InvalidParam);
+        }
+        Ok(())
+    }
+}
+
+impl<D: BlockDriverOps + ?Sized> BaseDriverOps for PartitionDev<D> {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+
+    fn device_name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<D: BlockDriverOps + ?Sized> BlockDriverOps for PartitionDev<D> {
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.check_range(block_id, buf.len())?;
+        self.disk.lock().read_block(self.start + block_id, buf)
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.check_range(block_id, buf.len())?;
+        self.disk.lock().write_block(self.start + block_id, buf)
+    }
+
+    fn flush(&mut self) -> DevResult {
+        self.disk.lock().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: usize = 512;
+
+    /// A disk kept in memory.
+    struct MemDisk {
+        data: Vec<u8>,
+    }
+
+    impl MemDisk {
+        fn new(num_blocks: usize) -> Self {
+            Self {
+                data: vec![0; num_blocks * BLOCK_SIZE],
+            }
+        }
+
+        fn block(&mut self, block_id: u64) -> &mut [u8] {
+            let start = block_id as usize * BLOCK_SIZE;
+            &mut self.data[start..start + BLOCK_SIZE]
+        }
+    }
+
+    impl BaseDriverOps for MemDisk {
+        fn device_type(&self) -> DeviceType {
+            DeviceType::Block
+        }
+
+        fn device_name(&self) -> &str {
+            "memdisk"
+        }
+    }
+
+    impl BlockDriverOps for MemDisk {
+        fn num_blocks(&self) -> u64 {
+            (self.data.len() / BLOCK_SIZE) as u64
+        }
+
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+            let start = block_id as usize * BLOCK_SIZE;
+            let src = self
+                .data
+                .get(start..start + buf.len())
+                .ok_or(DevError::InvalidParam)?;
+            buf.copy_from_slice(src);
+            Ok(())
+        }
+
+        fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+            let start = block_id as usize * BLOCK_SIZE;
+            self.data
+                .get_mut(start..start + buf.len())
+                .ok_or(DevError::InvalidParam)?
+                .copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> DevResult {
+            Ok(())
+        }
+    }
+
+    const NUM_BLOCKS: u64 = 8192;
+    const LINUX_GUID: &str = "0fc63daf-8483-4772-8e79-3d69d8477de4";
+    const UNIQUE_GUID: &str = "12345678-9abc-def0-1234-56789abcdef0";
+    const GPT_ENTRIES: usize = 128;
+    const GPT_ENTRY_SIZE: usize = 128;
+
+    /// Writes an MBR partition entry at `index` of the record in `sector`.
+    fn set_mbr_entry(sector: &mut [u8], index: usize, part_type: u8, start: u32, num_blocks: u32) {
+        let entry = &mut sector[446 + index * 16..446 + (index + 1) * 16];
+        entry[4] = part_type;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&num_blocks.to_le_bytes());
+        sector[510..512].copy_from_slice(&[0x55, 0xaa]);
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        !data.iter().fold(!0u32, |mut crc, &byte| {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+            crc
+        })
+    }
+
+    /// Writes a GPT header at `lba` with its entries at `entries_lba`.
+    fn write_gpt(disk: &mut MemDisk, lba: u64, entries_lba: u64, entries: &[u8]) {
+        let header = disk.block(lba);
+        header.fill(0);
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[40..48].copy_from_slice(&34u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(NUM_BLOCKS - 34).to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&(GPT_ENTRIES as u32).to_le_bytes());
+        header[84..88].copy_from_slice(&(GPT_ENTRY_SIZE as u32).to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(entries).to_le_bytes());
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        let start = entries_lba as usize * BLOCK_SIZE;
+        disk.data[start..start + entries.len()].copy_from_slice(entries);
+    }
+
+    /// Returns a GPT entry array with a valid partition and one out of the
+    /// usable range.
+    fn gpt_entries() -> Vec<u8> {
+        let mut entries = vec![0; GPT_ENTRIES * GPT_ENTRY_SIZE];
+        let type_guid: Guid = LINUX_GUID.parse().unwrap();
+        let unique_guid: Guid = UNIQUE_GUID.parse().unwrap();
+        for (i, (first, last)) in [(2048u64, 4095u64), (10, 20)].into_iter().enumerate() {
+            let entry = &mut entries[i * GPT_ENTRY_SIZE..(i + 1) * GPT_ENTRY_SIZE];
+            entry[0..16].copy_from_slice(&type_guid.0);
+            entry[16..32].copy_from_slice(&unique_guid.0);
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            for (j, unit) in "root".encode_utf16().enumerate() {
+                entry[56 + j * 2..58 + j * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+        entries
+    }
+
+    /// Returns a disk with a protective MBR and a GPT whose entries are
+    /// [`gpt_entries`].
+    fn gpt_disk() -> MemDisk {
+        let mut disk = MemDisk::new(NUM_BLOCKS as usize);
+        set_mbr_entry(disk.block(0), 0, 0xee, 1, (NUM_BLOCKS - 1) as u32);
+        let entries = gpt_entries();
+        write_gpt(&mut disk, 1, 2, &entries);
+        write_gpt(&mut disk, NUM_BLOCKS - 1, NUM_BLOCKS - 33, &entries);
+        disk
+    }
+
+    fn check_gpt(partitions: &[PartitionInfo]) {
+        assert_eq!(partitions.len(), 1);
+        let partition = &partitions[0];
+        assert_eq!(partition.index, 1);
+        assert_eq!(partition.name, "root");
+        assert_eq!(partition.uuid, UNIQUE_GUID);
+        assert_eq!(
+            partition.part_type,
+            PartitionType::Gpt(LINUX_GUID.parse().unwrap())
+        );
+        assert_eq!((partition.start, partition.num_blocks), (2048, 2048));
+    }
+
+    #[test]
+    fn test_no_partition_table() {
+        let mut disk = MemDisk::new(NUM_BLOCKS as usize);
+        assert!(read_partitions(&mut disk).unwrap().is_empty());
+
+        // A boot signature without any partition is not a partition table.
+        disk.block(0)[510..512].copy_from_slice(&[0x55, 0xaa]);
+        assert!(read_partitions(&mut disk).unwrap().is_empty());
+
+        // Neither is a partition beyond the end of the disk.
+        set_mbr_entry(disk.block(0), 0, 0x83, 2048, NUM_BLOCKS as u32);
+        assert!(read_partitions(&mut disk).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mbr() {
+        let mut disk = MemDisk::new(NUM_BLOCKS as usize);
+        let mbr = disk.block(0);
+        mbr[440..444].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        set_mbr_entry(mbr, 0, 0x83, 2048, 1000);
+        set_mbr_entry(mbr, 1, 0x05, 4096, 4096);
+        // Logical partitions are addressed relative to their EBR, and the next
+        // EBR relative to the extended partition.
+        let ebr = disk.block(4096);
+        set_mbr_entry(ebr, 0, 0x83, 1, 100);
+        set_mbr_entry(ebr, 1, 0x05, 200, 300);
+        set_mbr_entry(disk.block(4096 + 200), 0, 0x07, 1, 50);
+
+        let partitions = read_partitions(&mut disk).unwrap();
+        let summary = partitions
+            .iter()
+            .map(|it| (it.index, it.part_type, it.start, it.num_blocks))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (1, PartitionType::Mbr(0x83), 2048, 1000),
+                (5, PartitionType::Mbr(0x83), 4097, 100),
+                (6, PartitionType::Mbr(0x07), 4297, 50),
+            ]
+        );
+        assert_eq!(partitions[0].uuid, "12345678-01");
+        assert_eq!(partitions[2].uuid, "12345678-06");
+    }
+
+    #[test]
+    fn test_ebr_cycle() {
+        let mut disk = MemDisk::new(NUM_BLOCKS as usize);
+        set_mbr_entry(disk.block(0), 0, 0x05, 4096, 4096);
+        // The EBR points back to itself.
+        let ebr = disk.block(4096);
+        set_mbr_entry(ebr, 0, 0x83, 1, 10);
+        set_mbr_entry(ebr, 1, 0x05, 0, 4096);
+
+        let partitions = read_partitions(&mut disk).unwrap();
+        assert!(!partitions.is_empty() && partitions.len() <= 128);
+    }
+
+    #[test]
+    fn test_gpt() {
+        let mut disk = gpt_disk();
+        check_gpt(&read_partitions(&mut disk).unwrap());
+    }
+
+    #[test]
+    fn test_gpt_backup() {
+        let mut disk = gpt_disk();
+        // Corrupting the primary header makes its CRC fail.
+        disk.block(1)[40] ^= 1;
+        check_gpt(&read_partitions(&mut disk).unwrap());
+
+        // Without a valid header, the protective MBR alone yields nothing.
+        disk.block(NUM_BLOCKS - 1)[40] ^= 1;
+        assert!(read_partitions(&mut disk).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_gpt_entries_crc() {
+        let mut disk = gpt_disk();
+        // Entries not matching the CRC of either header are rejected.
+        disk.block(2)[32] ^= 1;
+        disk.block(NUM_BLOCKS - 33)[32] ^= 1;
+        assert!(read_partitions(&mut disk).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_partition_devices() {
+        let mut disk = MemDisk::new(NUM_BLOCKS as usize);
+        set_mbr_entry(disk.block(0), 0, 0x83, 2048, 1000);
+        set_mbr_entry(disk.block(0), 1, 0x83, 4096, 100);
+        let mut whole = PartitionDev::new(Box::new(disk));
+        let mut partitions = whole.partitions().unwrap();
+        let names = partitions
+            .iter()
+            .map(|it| it.device_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["memdiskp1", "memdiskp2"]);
+        assert_eq!(partitions[1].num_blocks(), 100);
+        assert!(partitions[1].partitions().unwrap().is_empty());
+
+        // Partitions are windows on the shared disk.
+        let buf = [0x5a; BLOCK_SIZE];
+        partitions[1].write_block(1, &buf).unwrap();
+        let mut read = [0; BLOCK_SIZE];
+        whole.read_block(4097, &mut read).unwrap();
+        assert_eq!(read, buf);
+        assert!(partitions[1].read_block(100, &mut read).is_err());
+    }
+}
@@ -8,7 +8,7 @@ use crate::prelude::*;
 pub type AxNetDevice = Box<dyn NetDriverOps>;
 /// The unified type of the block storage devices.
 #[cfg(feature = "block")]
-pub type AxBlockDevice = Box<dyn BlockDriverOps>;
+pub type AxBlockDevice = crate::partition::PartitionDev<dyn BlockDriverOps>;
 /// The unified type of the graphics display devices.
 #[cfg(feature = "display")]
 pub type AxDisplayDevice = Box<dyn DisplayDriverOps>;
@@ -23,10 +23,10 @@ impl super::AxDeviceEnum {
         Self::Net(Box::new(dev))
     }
 
-    /// Constructs a block device.
+    /// Constructs a block device from a whole disk.
     #[cfg(feature = "block")]
     pub fn from_block(dev: impl BlockDriverOps + 'static) -> Self {
-        Self::Block(Box::new(dev))
+        Self::Block(AxBlockDevice::new(Box::new(dev)))
     }
 
     /// Constructs a display device.
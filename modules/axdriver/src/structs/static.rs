@@ -1,5 +1,10 @@
+#[cfg(feature = "block")]
+use alloc::boxed::Box;
+
 #[cfg(feature = "block")]
 pub use crate::drivers::AxBlockDevice;
+#[cfg(feature = "block")]
+use crate::drivers::AxBlockDisk;
 #[cfg(feature = "display")]
 pub use crate::drivers::AxDisplayDevice;
 #[cfg(feature = "input")]
@@ -14,10 +19,10 @@ impl super::AxDeviceEnum {
         Self::Net(dev)
     }
 
-    /// Constructs a block device.
+    /// Constructs a block device from a whole disk.
     #[cfg(feature = "block")]
-    pub const fn from_block(dev: AxBlockDevice) -> Self {
-        Self::Block(dev)
+    pub fn from_block(dev: AxBlockDisk) -> Self {
+        Self::Block(AxBlockDevice::new(Box::new(dev)))
     }
 
     /// Constructs a display device.
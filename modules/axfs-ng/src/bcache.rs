@@ -0,0 +1,378 @@
+//! A write-back cache of disk blocks shared by all filesystems.
+//!
+//! Blocks are cached in page-sized buffers. Each disk keeps its buffers in
+//! LRU order behind a lock of its own, which is held across the disk's I/O
+//! without blocking other disks. Once the global capacity is reached, a disk
+//! evicts its own least recently used buffers first, then those of disks
+//! that are not busy.
+
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use allocator::AllocError;
+use axalloc::{UsageKind, global_allocator};
+use axdriver::{AxBlockDevice, prelude::BlockDriverOps};
+use axfs_ng_vfs::{VfsError, VfsResult};
+use axhal::mem::VirtAddr;
+use axsync::Mutex;
+use log::warn;
+use lru::LruCache;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Maximum number of pages read from a disk at once on a cache miss.
+const READAHEAD_PAGES: u64 = 8;
+
+/// Default capacity of the buffer cache in bytes.
+const DEFAULT_CAPACITY: usize = 8 * 1024 * 1024;
+
+/// Maximum number of buffers.
+static CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_CAPACITY / PAGE_SIZE);
+/// Number of buffers allocated for all disks.
+static USED: AtomicUsize = AtomicUsize::new(0);
+
+/// All disks with a buffer cache, to evict from and flush.
+static DISKS: spin::Mutex<Vec<Weak<DiskHandle>>> = spin::Mutex::new(Vec::new());
+
+struct Disk {
+    dev: AxBlockDevice,
+    block_size: usize,
+    num_blocks: u64,
+}
+
+impl Disk {
+    /// Returns the range of blocks backing `pages` pages starting at
+    /// `page`, clipped to the end of the disk.
+    fn blocks(&self, page: u64, pages: u64) -> (u64, usize) {
+        let per_page = (PAGE_SIZE / self.block_size) as u64;
+        let start = page * per_page;
+        let count = (pages * per_page).min(self.num_blocks.saturating_sub(start));
+        (start, count as usize)
+    }
+
+    fn num_pages(&self) -> u64 {
+        (self.num_blocks * self.block_size as u64).div_ceil(PAGE_SIZE as u64)
+    }
+
+    // Blocks are transferred one at a time, since not every driver accepts
+    // buffers larger than one block.
+
+    fn read_pages(&mut self, page: u64, buf: &mut [u8]) -> VfsResult<()> {
+        let (start, count) = self.blocks(page, (buf.len() / PAGE_SIZE) as u64);
+        let blocks = buf[..count * self.block_size].chunks_exact_mut(self.block_size);
+        for (block_id, block) in (start..).zip(blocks) {
+            self.dev.read_block(block_id, block).map_err(|err| {
+                warn!("Failed to read block {block_id}: {err:?}");
+                VfsError::EIO
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_back(&mut self, page: u64, buffer: &mut Buffer) -> VfsResult<()> {
+        if !buffer.dirty {
+            return Ok(());
+        }
+        let (start, count) = self.blocks(page, 1);
+        let blocks = buffer.data()[..count * self.block_size].chunks_exact(self.block_size);
+        for (block_id, block) in (start..).zip(blocks) {
+            self.dev.write_block(block_id, block).map_err(|err| {
+                warn!("Failed to write block {block_id}: {err:?}");
+                VfsError::EIO
+            })?;
+        }
+        buffer.dirty = false;
+        Ok(())
+    }
+}
+
+struct Buffer {
+    addr: VirtAddr,
+    dirty: bool,
+}
+
+impl Buffer {
+    fn new() -> VfsResult<Self> {
+        let addr = global_allocator()
+            .alloc_pages(1, PAGE_SIZE, UsageKind::BufferCache)
+            .map_err(|err| {
+                warn!("Failed to allocate buffer cache: {:?}", err);
+                match err {
+                    AllocError::NoMemory => VfsError::ENOMEM,
+                    _ => VfsError::EINVAL,
+                }
+            })?;
+        USED.fetch_add(1, Ordering::Relaxed);
+        Ok(Self {
+            addr: addr.into(),
+            dirty: false,
+        })
+    }
+
+    fn data(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.addr.as_ptr(), PAGE_SIZE) }
+    }
+
+    fn data_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.addr.as_mut_ptr(), PAGE_SIZE) }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if self.dirty {
+            warn!("dirty buffer dropped without flushing");
+        }
+        global_allocator().dealloc_pages(self.addr.as_usize(), 1, UsageKind::BufferCache);
+        USED.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The buffers of a disk indexed by page number, together with the disk.
+struct DiskCache {
+    disk: Disk,
+    buffers: LruCache<u64, Buffer>,
+}
+
+impl DiskCache {
+    /// Evicts buffers until at most `len` remain, writing back dirty ones.
+    fn shrink_to(&mut self, len: usize) -> VfsResult<()> {
+        while self.buffers.len() > len {
+            let (page, mut buffer) = self.buffers.pop_lru().unwrap();
+            if let Err(err) = self.disk.write_back(page, &mut buffer) {
+                self.buffers.push(page, buffer);
+                self.buffers.demote(&page);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Evicts buffers so that `pages` more fit in the global capacity,
+    /// taking them from this disk first.
+    fn make_room(&mut self, pages: usize) -> VfsResult<()> {
+        let excess = || {
+            let capacity = CAPACITY.load(Ordering::Relaxed);
+            (USED.load(Ordering::Relaxed) + pages).saturating_sub(capacity)
+        };
+        self.shrink_to(self.buffers.len().saturating_sub(excess()))?;
+        if excess() > 0 {
+            // This disk is locked by us, so `try_lock` skips it.
+            for disk in disks() {
+                let over = excess();
+                if over == 0 {
+                    break;
+                }
+                if let Some(mut other) = disk.cache.try_lock() {
+                    let len = other.buffers.len().saturating_sub(over);
+                    other.shrink_to(len)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the buffer of a page, reading it and the following uncached
+    /// pages from the disk on a miss.
+    fn get(&mut self, page: u64) -> VfsResult<&mut Buffer> {
+        if !self.buffers.contains(&page) {
+            let pages = (page..self.disk.num_pages().min(page + READAHEAD_PAGES))
+                .take(CAPACITY.load(Ordering::Relaxed).max(1))
+                .take_while(|&next| next == page || !self.buffers.contains(&next))
+                .count();
+            let mut data = vec![0; pages * PAGE_SIZE];
+            self.disk.read_pages(page, &mut data)?;
+            self.make_room(pages)?;
+            // Insert in reverse so that the requested page is the most
+            // recently used one.
+            for (i, chunk) in data.chunks(PAGE_SIZE).enumerate().rev() {
+                let mut buffer = Buffer::new()?;
+                buffer.data_mut().copy_from_slice(chunk);
+                self.buffers.push(page + i as u64, buffer);
+            }
+        }
+        Ok(self.buffers.get_mut(&page).unwrap())
+    }
+
+    /// Returns the buffer of a page that is about to be overwritten
+    /// entirely, without reading it from the disk.
+    fn get_for_overwrite(&mut self, page: u64) -> VfsResult<&mut Buffer> {
+        if !self.buffers.contains(&page) {
+            self.make_room(1)?;
+            self.buffers.push(page, Buffer::new()?);
+        }
+        Ok(self.buffers.get_mut(&page).unwrap())
+    }
+
+    fn flush(&mut self) -> VfsResult<()> {
+        for (page, buffer) in self.buffers.iter_mut() {
+            self.disk.write_back(*page, buffer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the disks that are still alive.
+fn disks() -> Vec<Arc<DiskHandle>> {
+    DISKS.lock().iter().filter_map(Weak::upgrade).collect()
+}
+
+/// Returns the capacity of the buffer cache in bytes.
+pub fn buffer_cache_capacity() -> usize {
+    CAPACITY.load(Ordering::Relaxed) * PAGE_SIZE
+}
+
+/// Sets the capacity of the buffer cache in bytes, evicting buffers if the
+/// cache holds more than that.
+pub fn set_buffer_cache_capacity(capacity: usize) -> VfsResult<()> {
+    let capacity = capacity / PAGE_SIZE;
+    CAPACITY.store(capacity, Ordering::Relaxed);
+    for disk in disks() {
+        let over = USED.load(Ordering::Relaxed).saturating_sub(capacity);
+        if over == 0 {
+            break;
+        }
+        let mut cache = disk.cache.lock();
+        let len = cache.buffers.len().saturating_sub(over);
+        cache.shrink_to(len)?;
+    }
+    Ok(())
+}
+
+/// Writes back all dirty buffers of all disks.
+pub fn flush_buffer_cache() -> VfsResult<()> {
+    for disk in disks() {
+        disk.cache.lock().flush()?;
+    }
+    Ok(())
+}
+
+struct DiskHandle {
+    name: String,
+    block_size: usize,
+    size: u64,
+    cache: Mutex<DiskCache>,
+}
+
+impl Drop for DiskHandle {
+    fn drop(&mut self) {
+        let cache = self.cache.get_mut();
+        if let Err(err) = cache.flush() {
+            warn!("Failed to flush disk on drop: {err:?}");
+        }
+        cache.buffers.clear();
+        DISKS.lock().retain(|disk| disk.strong_count() > 0);
+    }
+}
+
+/// A block device accessed through the shared buffer cache.
+///
+/// Writes stay in the cache until they are evicted or [`flush`] is called.
+/// Remaining dirty blocks are written back when the last clone is dropped.
+///
+/// [`flush`]: CachedDisk::flush
+#[derive(Clone)]
+pub struct CachedDisk(Arc<DiskHandle>);
+
+impl CachedDisk {
+    /// Creates a cached disk on top of `dev`.
+    pub fn new(dev: AxBlockDevice) -> Self {
+        let block_size = dev.block_size();
+        assert!(block_size.is_power_of_two() && block_size <= PAGE_SIZE);
+        let num_blocks = dev.num_blocks();
+        let handle = Arc::new(DiskHandle {
+            name: dev.device_name().into(),
+            block_size,
+            size: num_blocks * block_size as u64,
+            cache: Mutex::new(DiskCache {
+                disk: Disk {
+                    dev,
+                    block_size,
+                    num_blocks,
+                },
+                buffers: LruCache::unbounded(),
+            }),
+        });
+        DISKS.lock().push(Arc::downgrade(&handle));
+        Self(handle)
+    }
+
+    /// Returns the name of the underlying device's driver.
+    pub fn device_name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Returns the block size of the underlying device.
+    pub fn block_size(&self) -> usize {
+        self.0.block_size
+    }
+
+    /// Returns the size of the disk in bytes.
+    pub fn size(&self) -> u64 {
+        self.0.size
+    }
+
+    /// Reads from the disk at `offset`, returns the number of bytes read,
+    /// which is less than `buf.len()` only at the end of the disk.
+    pub fn read_at(&self, mut buf: &mut [u8], mut offset: u64) -> VfsResult<usize> {
+        let mut cache = self.0.cache.lock();
+        let mut read = 0;
+        while !buf.is_empty() && offset < self.size() {
+            let page = offset / PAGE_SIZE as u64;
+            let start = offset as usize % PAGE_SIZE;
+            let len = buf
+                .len()
+                .min(PAGE_SIZE - start)
+                .min((self.size() - offset) as usize);
+            let buffer = cache.get(page)?;
+            let (dst, rest) = buf.split_at_mut(len);
+            dst.copy_from_slice(&buffer.data()[start..start + len]);
+            buf = rest;
+            offset += len as u64;
+            read += len;
+        }
+        Ok(read)
+    }
+
+    /// Writes to the disk at `offset`, returns the number of bytes written,
+    /// which is less than `buf.len()` only at the end of the disk.
+    pub fn write_at(&self, mut buf: &[u8], mut offset: u64) -> VfsResult<usize> {
+        let mut cache = self.0.cache.lock();
+        let mut written = 0;
+        while !buf.is_empty() && offset < self.size() {
+            let page = offset / PAGE_SIZE as u64;
+            let start = offset as usize % PAGE_SIZE;
+            let len = buf
+                .len()
+                .min(PAGE_SIZE - start)
+                .min((self.size() - offset) as usize);
+            let buffer = if len == PAGE_SIZE {
+                cache.get_for_overwrite(page)?
+            } else {
+                cache.get(page)?
+            };
+            let (src, rest) = buf.split_at(len);
+            buffer.data_mut()[start..start + len].copy_from_slice(src);
+            buffer.dirty = true;
+            buf = rest;
+            offset += len as u64;
+            written += len;
+        }
+        Ok(written)
+    }
+
+    /// Writes back the dirty blocks of this disk and flushes the device.
+    pub fn flush(&self) -> VfsResult<()> {
+        let mut cache = self.0.cache.lock();
+        cache.flush()?;
+        cache.disk.dev.flush().map_err(|err| {
+            warn!("Failed to flush device: {err:?}");
+            VfsError::EIO
+        })
+    }
+}
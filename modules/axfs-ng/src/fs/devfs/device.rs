@@ -0,0 +1,299 @@
+use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc, vec::Vec};
+use core::task::Context;
+
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsError, VfsResult};
+use axio::{IoEvents, Pollable};
+use kspin::SpinNoPreempt as Mutex;
+
+use crate::bcache::CachedDisk;
+
+/// Operations of a device node in devfs.
+pub trait DeviceOps: Pollable + Send + Sync {
+    /// Reads from the device at `offset`, which is ignored by stream devices.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize>;
+
+    /// Writes to the device at `offset`, which is ignored by stream devices.
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize>;
+
+    /// Returns the size of the device, `0` for character devices.
+    fn len(&self) -> u64 {
+        0
+    }
+
+    /// Returns the flags of the device node.
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+/// A node in devfs.
+pub struct Device {
+    pub(crate) name: String,
+    pub(crate) node_type: NodeType,
+    pub(crate) rdev: DeviceId,
+    pub(crate) ops: Arc<dyn DeviceOps>,
+}
+
+impl Device {
+    /// Creates a device node named `name`.
+    pub fn new(
+        name: impl Into<String>,
+        node_type: NodeType,
+        rdev: DeviceId,
+        ops: Arc<dyn DeviceOps>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            node_type,
+            rdev,
+            ops,
+        }
+    }
+
+    /// Creates a block device node for raw access to `disk`.
+    ///
+    /// Writes through the node and through filesystems on `disk` share its
+    /// buffers.
+    pub fn block(name: impl Into<String>, rdev: DeviceId, disk: CachedDisk) -> Self {
+        Self::new(
+            name,
+            NodeType::BlockDevice,
+            rdev,
+            Arc::new(BlockDevice(disk)),
+        )
+    }
+}
+
+/// Returns `null`, `zero`, `full`, `random`, `urandom` and `console`.
+pub fn standard_devices() -> Vec<Device> {
+    let random = Arc::new(Random::new());
+    let char_dev = |name: &str, major, minor, ops: Arc<dyn DeviceOps>| {
+        Device::new(
+            name,
+            NodeType::CharacterDevice,
+            DeviceId::new(major, minor),
+            ops,
+        )
+    };
+    [
+        char_dev("null", 1, 3, Arc::new(Null)),
+        char_dev("zero", 1, 5, Arc::new(Zero)),
+        char_dev("full", 1, 7, Arc::new(Full)),
+        char_dev("random", 1, 8, random.clone()),
+        char_dev("urandom", 1, 9, random),
+        char_dev("console", 5, 1, Arc::new(Console::default())),
+    ]
+    .into()
+}
+
+/// Returns nodes for raw access to `disks`, named after the devices, e.g.
+/// `virtio-blk0` for a disk and `virtio-blk0p1` for its first partition.
+pub fn block_devices(disks: impl IntoIterator<Item = CachedDisk>) -> Vec<Device> {
+    disks
+        .into_iter()
+        .enumerate()
+        .map(|(i, disk)| {
+            let name = String::from(disk.device_name());
+            // Major 259 is the one Linux uses for dynamically numbered
+            // block devices.
+            Device::block(name, DeviceId::new(259, i as _), disk)
+        })
+        .collect()
+}
+
+/// Character devices that are always ready.
+macro_rules! always_ready {
+    ($($ty:ty),*) => {
+        $(impl Pollable for $ty {
+            fn poll(&self) -> IoEvents {
+                IoEvents::IN | IoEvents::OUT
+            }
+
+            fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+        })*
+    };
+}
+
+always_ready!(Null, Zero, Full, Random);
+
+/// `/dev/null`, which discards writes and reads as empty.
+struct Null;
+
+impl DeviceOps for Null {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Ok(0)
+    }
+
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/zero`, which discards writes and reads as zeros.
+struct Zero;
+
+impl DeviceOps for Zero {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/full`, which reads as zeros and is always out of space.
+struct Full;
+
+impl DeviceOps for Full {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(VfsError::ENOSPC)
+    }
+}
+
+/// `/dev/random` and `/dev/urandom`.
+///
+/// This is a non-cryptographic generator (splitmix64) seeded from the boot
+/// time; writes are mixed into the state.
+struct Random {
+    state: Mutex<u64>,
+}
+
+impl Random {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(axhal::time::monotonic_time_nanos() ^ 0x853c_49e6_748f_ea9b),
+        }
+    }
+
+    fn next(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl DeviceOps for Random {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        let mut state = self.state.lock();
+        for chunk in buf.chunks_mut(8) {
+            let bytes = Self::next(&mut state).to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        let mut state = self.state.lock();
+        for chunk in buf.chunks(8) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *state ^= u64::from_le_bytes(bytes);
+            Self::next(&mut state);
+        }
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/console`, backed by the platform console.
+///
+/// Reads fail with `EAGAIN` if there is no pending input, so readers are
+/// expected to wait for [`IoEvents::IN`].
+#[derive(Default)]
+struct Console {
+    /// Input consumed from the console while polling.
+    pending: Mutex<VecDeque<u8>>,
+}
+
+impl Console {
+    fn fill_pending(&self, pending: &mut VecDeque<u8>) {
+        let mut buf = [0; 64];
+        let n = axhal::console::read_bytes(&mut buf);
+        pending.extend(&buf[..n]);
+    }
+}
+
+impl DeviceOps for Console {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        let mut pending = self.pending.lock();
+        if pending.is_empty() {
+            self.fill_pending(&mut pending);
+        }
+        let n = pending.len().min(buf.len());
+        if n == 0 && !buf.is_empty() {
+            return Err(VfsError::EAGAIN);
+        }
+        for (dst, src) in buf.iter_mut().zip(pending.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        axhal::console::write_bytes(buf);
+        Ok(buf.len())
+    }
+}
+
+impl Pollable for Console {
+    fn poll(&self) -> IoEvents {
+        let mut pending = self.pending.lock();
+        if pending.is_empty() {
+            self.fill_pending(&mut pending);
+        }
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, !pending.is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if !events.contains(IoEvents::IN) {
+            return;
+        }
+        #[cfg(feature = "irq")]
+        {
+            if let Some(irq) = axhal::console::get_console_irq() {
+                axhal::irq::register_irq_waker(irq as _, context.waker());
+                return;
+            }
+        }
+        // Without a console interrupt, we can only poll again.
+        context.waker().wake_by_ref();
+    }
+}
+
+/// Raw access to a block device.
+struct BlockDevice(CachedDisk);
+
+impl DeviceOps for BlockDevice {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        self.0.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let written = self.0.write_at(buf, offset)?;
+        if written == 0 && !buf.is_empty() {
+            return Err(VfsError::ENOSPC);
+        }
+        self.0.flush()?;
+        Ok(written)
+    }
+
+    fn len(&self) -> u64 {
+        self.0.size()
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+always_ready!(BlockDevice);
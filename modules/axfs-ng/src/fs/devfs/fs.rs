@@ -0,0 +1,288 @@
+use alloc::{borrow::ToOwned, collections::btree_map::BTreeMap, string::String, sync::Arc};
+use core::{any::Any, cell::OnceCell, task::Context, time::Duration};
+
+use axfs_ng_vfs::{
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
+    FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType,
+    Reference, StatFs, VfsError, VfsResult, WeakDirEntry, path::MAX_NAME_LEN,
+};
+use axio::{IoEvents, Pollable};
+
+use super::Device;
+
+/// `DEVFS_SUPER_MAGIC` as reported by Linux.
+const DEVFS_MAGIC: u64 = 0x1373;
+
+const ROOT_INO: u64 = 1;
+
+/// A filesystem exposing devices, usually mounted on `/dev`.
+///
+/// The set of devices is fixed when the filesystem is created.
+pub struct DevFilesystem {
+    devices: BTreeMap<String, (u64, Arc<Device>)>,
+    created: Duration,
+    root_dir: OnceCell<DirEntry>,
+}
+
+impl DevFilesystem {
+    /// Creates a devfs holding `devices`, e.g. the ones returned by
+    /// [`standard_devices`](super::standard_devices).
+    pub fn new(devices: impl IntoIterator<Item = Device>) -> Filesystem {
+        let devices = devices
+            .into_iter()
+            .zip(ROOT_INO + 1..)
+            .map(|(device, ino)| (device.name.clone(), (ino, Arc::new(device))))
+            .collect();
+        let fs = Arc::new(Self {
+            devices,
+            created: crate::fs::now(),
+            root_dir: OnceCell::new(),
+        });
+        let _ = fs.root_dir.set(DirEntry::new_dir(
+            |this| {
+                DirNode::new(Arc::new(DevDir {
+                    fs: fs.clone(),
+                    this,
+                }))
+            },
+            Reference::root(),
+        ));
+        Filesystem::new(fs)
+    }
+
+    fn metadata(&self, inode: u64, node_type: NodeType, mode: u16, rdev: DeviceId) -> Metadata {
+        Metadata {
+            inode,
+            device: 0,
+            nlink: 1,
+            mode: NodePermission::from_bits_truncate(mode),
+            node_type,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            block_size: 4096,
+            blocks: 0,
+            rdev,
+            atime: self.created,
+            mtime: self.created,
+            ctime: self.created,
+        }
+    }
+}
+
+unsafe impl Send for DevFilesystem {}
+
+unsafe impl Sync for DevFilesystem {}
+
+impl FilesystemOps for DevFilesystem {
+    fn name(&self) -> &str {
+        "devfs"
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.root_dir.get().unwrap().clone()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        Ok(StatFs {
+            fs_type: DEVFS_MAGIC as _,
+            block_size: 4096,
+            blocks: 0,
+            blocks_free: 0,
+            blocks_available: 0,
+
+            file_count: self.devices.len() as u64 + 1,
+            free_file_count: 0,
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: 0,
+            mount_flags: 0,
+        })
+    }
+}
+
+/// The root directory of a devfs.
+struct DevDir {
+    fs: Arc<DevFilesystem>,
+    this: WeakDirEntry,
+}
+
+impl NodeOps for DevDir {
+    fn inode(&self) -> u64 {
+        ROOT_INO
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        let mut meta = self
+            .fs
+            .metadata(ROOT_INO, NodeType::Directory, 0o755, DeviceId::default());
+        meta.nlink = 2;
+        Ok(meta)
+    }
+
+    fn update_metadata(&self, _update: MetadataUpdate) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        Ok(0)
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        &*self.fs
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::empty()
+    }
+}
+
+impl DirNodeOps for DevDir {
+    fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        let entries = [(".", ROOT_INO), ("..", ROOT_INO)]
+            .into_iter()
+            .map(|(name, ino)| (name, ino, NodeType::Directory))
+            .chain(
+                self.fs
+                    .devices
+                    .iter()
+                    .map(|(name, (ino, device))| (name.as_str(), *ino, device.node_type)),
+            );
+        let mut count = 0;
+        for (i, (name, ino, node_type)) in entries.enumerate().skip(offset as usize) {
+            if !sink.accept(name, ino, node_type, i as u64 + 1) {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        let (ino, device) = self.fs.devices.get(name).ok_or(VfsError::ENOENT)?;
+        let node = Arc::new(DevNode {
+            fs: self.fs.clone(),
+            ino: *ino,
+            device: device.clone(),
+        });
+        Ok(DirEntry::new_file(
+            FileNode::new(node),
+            device.node_type,
+            Reference::new(self.this.upgrade(), name.to_owned()),
+        ))
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<DirEntry> {
+        Err(VfsError::EPERM)
+    }
+
+    fn link(&self, _name: &str, _node: &DirEntry) -> VfsResult<DirEntry> {
+        Err(VfsError::EPERM)
+    }
+
+    fn unlink(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+
+    fn rename(&self, _src_name: &str, _dst_dir: &DirNode, _dst_name: &str) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+}
+
+/// A device node of a devfs.
+struct DevNode {
+    fs: Arc<DevFilesystem>,
+    ino: u64,
+    device: Arc<Device>,
+}
+
+impl NodeOps for DevNode {
+    fn inode(&self) -> u64 {
+        self.ino
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        let device = &self.device;
+        let mode = match device.node_type {
+            NodeType::BlockDevice => 0o660,
+            _ => 0o666,
+        };
+        let mut meta = self
+            .fs
+            .metadata(self.ino, device.node_type, mode, device.rdev);
+        meta.size = device.ops.len();
+        Ok(meta)
+    }
+
+    fn update_metadata(&self, _update: MetadataUpdate) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        Ok(self.device.ops.len())
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        &*self.fs
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        self.device.ops.flags()
+    }
+}
+
+impl FileNodeOps for DevNode {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        self.device.ops.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        self.device.ops.write_at(buf, offset)
+    }
+
+    fn append(&self, buf: &[u8]) -> VfsResult<(usize, u64)> {
+        let offset = self.device.ops.len();
+        let written = self.device.ops.write_at(buf, offset)?;
+        Ok((written, offset + written as u64))
+    }
+
+    fn set_len(&self, _len: u64) -> VfsResult<()> {
+        // Truncating devices on open is a no-op.
+        Ok(())
+    }
+
+    fn set_symlink(&self, _target: &str) -> VfsResult<()> {
+        Err(VfsError::EINVAL)
+    }
+}
+
+impl Pollable for DevNode {
+    fn poll(&self) -> IoEvents {
+        self.device.ops.poll()
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        self.device.ops.register(context, events)
+    }
+}
@@ -0,0 +1,5 @@
+mod device;
+mod fs;
+
+pub use device::*;
+pub use fs::*;
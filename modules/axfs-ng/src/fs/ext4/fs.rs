@@ -1,7 +1,6 @@
 use alloc::sync::Arc;
 use core::cell::OnceCell;
 
-use axdriver::AxBlockDevice;
 use axfs_ng_vfs::{
     DirEntry, DirNode, Filesystem, FilesystemOps, Reference, StatFs, VfsResult, path::MAX_NAME_LEN,
 };
@@ -12,21 +11,26 @@ use super::{
     Ext4Disk, Inode,
     util::{LwExt4Filesystem, into_vfs_err},
 };
+use crate::bcache::CachedDisk;
 
-const EXT4_CONFIG: FsConfig = FsConfig { bcache_size: 256 };
+// Blocks are mostly cached by the shared buffer cache, lwext4 only needs a
+// few of its own.
+const EXT4_CONFIG: FsConfig = FsConfig { bcache_size: 32 };
 
 pub struct Ext4Filesystem {
     inner: Mutex<LwExt4Filesystem>,
+    disk: CachedDisk,
     root_dir: OnceCell<DirEntry>,
 }
 
 impl Ext4Filesystem {
-    pub fn new(dev: AxBlockDevice) -> VfsResult<Filesystem> {
-        let ext4 =
-            lwext4_rust::Ext4Filesystem::new(Ext4Disk(dev), EXT4_CONFIG).map_err(into_vfs_err)?;
+    pub fn new(disk: CachedDisk) -> VfsResult<Filesystem> {
+        let ext4 = lwext4_rust::Ext4Filesystem::new(Ext4Disk(disk.clone()), EXT4_CONFIG)
+            .map_err(into_vfs_err)?;
 
         let fs = Arc::new(Self {
             inner: Mutex::new(ext4),
+            disk,
             root_dir: OnceCell::new(),
         });
         let _ = fs.root_dir.set(DirEntry::new_dir(
@@ -74,6 +78,7 @@ impl FilesystemOps for Ext4Filesystem {
     }
 
     fn flush(&self) -> VfsResult<()> {
-        self.inner.lock().flush().map_err(into_vfs_err)
+        self.inner.lock().flush().map_err(into_vfs_err)?;
+        self.disk.flush()
     }
 }
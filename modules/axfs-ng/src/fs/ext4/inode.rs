@@ -1,5 +1,13 @@
-use alloc::{borrow::ToOwned, string::String, sync::Arc};
-use core::{any::Any, task::Context};
+use alloc::{borrow::ToOwned, format, string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    any::Any,
+    ffi::c_char,
+    mem,
+    ops::Range,
+    ptr::{null_mut, slice_from_raw_parts},
+    slice,
+    task::Context,
+};
 
 use axfs_ng_vfs::{
     DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, FilesystemOps,
@@ -7,13 +15,112 @@ use axfs_ng_vfs::{
     VfsResult, WeakDirEntry,
 };
 use axio::{IoEvents, Pollable};
-use lwext4_rust::{FileAttr, InodeType};
+use lwext4_rust::{
+    Ext4Error, Ext4Result, FileAttr, InodeType,
+    ffi::{
+        EIO, ENODATA, EXT4_INODE_FLAG_EXTENTS, ext4_block, ext4_block_get, ext4_block_set,
+        ext4_blockdev, ext4_extent_get_blocks, ext4_extent_remove_space, ext4_extract_xattr_name,
+        ext4_get_xattr_name_prefix, ext4_inode_has_flag, ext4_inode_ref, ext4_xattr_get,
+        ext4_xattr_list, ext4_xattr_list_entry, ext4_xattr_remove, ext4_xattr_set,
+    },
+};
 
 use super::{
     Ext4Filesystem,
-    util::{LwExt4Filesystem, into_vfs_err, into_vfs_type},
+    util::{LwExt4Filesystem, check_ret, into_vfs_err, into_vfs_type, with_raw_inode_ref},
+};
+use crate::{
+    fs::{SpaceOps, XattrOps, rename_flags},
+    highlevel::{RenameFlags, invalidate_entry},
 };
 
+/// Maximum length of an attribute value, as with Linux `XATTR_SIZE_MAX`.
+const XATTR_SIZE_MAX: usize = 65536;
+
+/// Calls `f` with the lwext4 index of the namespace of the attribute `name`
+/// and the rest of the name, failing with `EOPNOTSUPP` for namespaces ext4
+/// cannot store.
+fn with_xattr_name<R>(
+    name: &str,
+    f: impl FnOnce(u8, *const c_char, usize) -> VfsResult<R>,
+) -> VfsResult<R> {
+    let mut index = 0;
+    let mut len = 0;
+    let mut found = false;
+    let rest = unsafe {
+        ext4_extract_xattr_name(
+            name.as_ptr() as _,
+            name.len(),
+            &mut index,
+            &mut len,
+            &mut found,
+        )
+    };
+    if !found {
+        return Err(VfsError::EOPNOTSUPP);
+    }
+    f(index, rest, len)
+}
+
+/// Magic number of the header of an extent tree node.
+const EXT4_EXTENT_MAGIC: u16 = 0xf30a;
+
+/// Size of the header and of each entry of an extent tree node.
+const EXT4_EXTENT_ENTRY_SIZE: usize = 12;
+
+/// Returns the first block at or after `block` mapped by the extent tree
+/// rooted in `inode`, or `None` if there is none.
+fn next_mapped_block(inode: *mut ext4_inode_ref, block: u32) -> Ext4Result<Option<u32>> {
+    unsafe {
+        let root = &(*(*inode).inode).blocks;
+        let root = slice::from_raw_parts(root.as_ptr() as *const u8, mem::size_of_val(root));
+        next_mapped_block_in(&mut *(*(*inode).fs).bdev, root, block)
+    }
+}
+
+/// Returns the first block at or after `block` mapped by the extent tree
+/// node `node`, reading the nodes below it from `bdev`.
+fn next_mapped_block_in(
+    bdev: &mut ext4_blockdev,
+    node: &[u8],
+    block: u32,
+) -> Ext4Result<Option<u32>> {
+    let u16_at = |at: usize| u16::from_le_bytes([node[at], node[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(node[at..at + 4].try_into().unwrap());
+    if node.len() < EXT4_EXTENT_ENTRY_SIZE || u16_at(0) != EXT4_EXTENT_MAGIC {
+        return Err(Ext4Error::new(EIO as _, None));
+    }
+    let entries = (u16_at(2) as usize).min(node.len() / EXT4_EXTENT_ENTRY_SIZE - 1);
+    let depth = u16_at(6);
+    for i in 0..entries {
+        let entry = (i + 1) * EXT4_EXTENT_ENTRY_SIZE;
+        let first = u32_at(entry);
+        if depth == 0 {
+            // Lengths above 32768 mark unwritten extents.
+            let len = u16_at(entry + 4) as u32;
+            let len = if len > 32768 { len - 32768 } else { len };
+            if first.saturating_add(len) > block {
+                return Ok(Some(first.max(block)));
+            }
+            continue;
+        }
+        // An index covers the blocks up to the first one of the next index.
+        if i + 1 < entries && u32_at(entry + EXT4_EXTENT_ENTRY_SIZE) <= block {
+            continue;
+        }
+        let child = u32_at(entry + 4) as u64 | (u16_at(entry + 8) as u64) << 32;
+        let mut buf: ext4_block = unsafe { mem::zeroed() };
+        check_ret(unsafe { ext4_block_get(bdev, &mut buf, child) })?;
+        let data = unsafe { slice::from_raw_parts(buf.data, bdev.lg_bsize as usize) };
+        let found = next_mapped_block_in(bdev, data, block);
+        check_ret(unsafe { ext4_block_set(bdev, &mut buf) })?;
+        if let Some(found) = found? {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
 pub struct Inode {
     fs: Arc<Ext4Filesystem>,
     ino: u32,
@@ -50,6 +157,61 @@ impl Inode {
         Ok(self.create_entry(&entry, name))
     }
 
+    fn len_locked(&self, fs: &mut LwExt4Filesystem) -> VfsResult<u64> {
+        fs.with_inode_ref(self.ino, |inode| Ok(inode.size()))
+            .map_err(into_vfs_err)
+    }
+
+    fn write_zeros_locked(&self, fs: &mut LwExt4Filesystem, range: Range<u64>) -> VfsResult<()> {
+        const ZEROS: [u8; 4096] = [0; 4096];
+        let mut pos = range.start;
+        while pos < range.end {
+            let len = ((range.end - pos) as usize).min(ZEROS.len());
+            pos += fs
+                .write_at(self.ino, &ZEROS[..len], pos)
+                .map_err(into_vfs_err)? as u64;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the blocks of the inode are mapped by extents, which
+    /// holes are managed through.
+    fn uses_extents(&self, fs: &mut LwExt4Filesystem) -> VfsResult<bool> {
+        with_raw_inode_ref(fs, self.ino, |inode| {
+            Ok(unsafe { ext4_inode_has_flag((*inode).inode, EXT4_INODE_FLAG_EXTENTS) })
+        })
+    }
+
+    fn get_xattr_locked(
+        &self,
+        fs: &mut LwExt4Filesystem,
+        name: &str,
+    ) -> VfsResult<Option<Vec<u8>>> {
+        with_xattr_name(name, |index, name, len| {
+            with_raw_inode_ref(fs, self.ino, |inode| {
+                let mut buf = vec![0u8; XATTR_SIZE_MAX];
+                let mut size = 0;
+                let ret = unsafe {
+                    ext4_xattr_get(
+                        inode,
+                        index,
+                        name,
+                        len,
+                        buf.as_mut_ptr() as _,
+                        buf.len(),
+                        &mut size,
+                    )
+                };
+                if ret == ENODATA as i32 {
+                    return Ok(None);
+                }
+                check_ret(ret)?;
+                buf.truncate(size);
+                Ok(Some(buf))
+            })
+        })
+    }
+
     fn update_ctime_locked(&self, fs: &mut LwExt4Filesystem, ino: u32) -> VfsResult<()> {
         fs.with_inode_ref(ino, |ino| {
             ino.update_ctime();
@@ -170,6 +332,146 @@ impl FileNodeOps for Inode {
     }
 }
 
+impl SpaceOps for Inode {
+    fn punch_hole(&self, range: Range<u64>) -> VfsResult<()> {
+        let mut fs = self.fs.lock();
+        let range = range.start..range.end.min(self.len_locked(&mut fs)?);
+        if range.is_empty() {
+            return Ok(());
+        }
+        let block_size = fs.stat().map_err(into_vfs_err)?.block_size as u64;
+        let first = range.start.div_ceil(block_size);
+        let last = range.end / block_size;
+        if first >= last || !self.uses_extents(&mut fs)? {
+            // No whole block to deallocate, or only extents can have holes.
+            self.write_zeros_locked(&mut fs, range)?;
+        } else {
+            // Zero the partial blocks at both ends, and free the whole ones.
+            self.write_zeros_locked(&mut fs, range.start..first * block_size)?;
+            self.write_zeros_locked(&mut fs, last * block_size..range.end)?;
+            with_raw_inode_ref(&mut fs, self.ino, |inode| {
+                check_ret(unsafe {
+                    ext4_extent_remove_space(inode, first as u32, (last - 1) as u32)
+                })
+            })?;
+        }
+        fs.with_inode_ref(self.ino, |inode| {
+            inode.update_ctime();
+            Ok(())
+        })
+        .map_err(into_vfs_err)
+    }
+
+    fn seek_data(&self, offset: u64, data: bool) -> VfsResult<u64> {
+        let mut fs = self.fs.lock();
+        let len = self.len_locked(&mut fs)?;
+        if offset >= len {
+            return Err(VfsError::ENXIO);
+        }
+        if !self.uses_extents(&mut fs)? {
+            // Without extents, the file is reported as a single extent of
+            // data.
+            return if data { Ok(offset) } else { Ok(len) };
+        }
+        let block_size = fs.stat().map_err(into_vfs_err)?.block_size as u64;
+        let end = len.div_ceil(block_size);
+        let mut block = offset / block_size;
+        while block < end {
+            let (mapped, next) = with_raw_inode_ref(&mut fs, self.ino, |inode| {
+                let mut fblock = 0;
+                let mut count = 0;
+                let max = (end - block).min(u32::MAX as u64) as u32;
+                check_ret(unsafe {
+                    ext4_extent_get_blocks(inode, block as u32, max, &mut fblock, false, &mut count)
+                })?;
+                if fblock != 0 {
+                    return Ok((true, block + count.max(1) as u64));
+                }
+                // Holes are not measured by lwext4, so the next extent is
+                // looked up in the extent tree.
+                let next = next_mapped_block(inode, block as u32)?;
+                Ok((false, next.map_or(end, |next| next as u64)))
+            })?;
+            if mapped == data {
+                return Ok((block * block_size).max(offset));
+            }
+            block = next.max(block + 1);
+        }
+        if data { Err(VfsError::ENXIO) } else { Ok(len) }
+    }
+}
+
+impl XattrOps for Inode {
+    fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>> {
+        self.get_xattr_locked(&mut self.fs.lock(), name)?
+            .ok_or(VfsError::ENODATA)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8], create: bool, replace: bool) -> VfsResult<()> {
+        let mut fs = self.fs.lock();
+        if create || replace {
+            match self.get_xattr_locked(&mut fs, name)? {
+                Some(_) if create => return Err(VfsError::EEXIST),
+                None if replace => return Err(VfsError::ENODATA),
+                _ => {}
+            }
+        }
+        with_xattr_name(name, |index, name, len| {
+            with_raw_inode_ref(&mut fs, self.ino, |inode| {
+                check_ret(unsafe {
+                    ext4_xattr_set(inode, index, name, len, value.as_ptr() as _, value.len())
+                })
+            })
+        })?;
+        self.update_ctime_locked(&mut fs, self.ino)
+    }
+
+    fn list_xattr(&self) -> VfsResult<Vec<String>> {
+        with_raw_inode_ref(&mut self.fs.lock(), self.ino, |inode| {
+            let mut size = 0;
+            check_ret(unsafe { ext4_xattr_list(inode, null_mut(), &mut size) })?;
+            if size == 0 {
+                return Ok(Vec::new());
+            }
+            // The entries are laid out in the buffer along with their names.
+            let mut buf = vec![0u64; size.div_ceil(8)];
+            let list = buf.as_mut_ptr() as *mut ext4_xattr_list_entry;
+            check_ret(unsafe { ext4_xattr_list(inode, list, &mut size) })?;
+            let mut names = Vec::new();
+            let mut entry = list;
+            while !entry.is_null() {
+                let item = unsafe { &*entry };
+                let mut prefix_len = 0;
+                let prefix =
+                    unsafe { ext4_get_xattr_name_prefix(item.name_index, &mut prefix_len) };
+                if !prefix.is_null() {
+                    let (prefix, name) = unsafe {
+                        (
+                            &*slice_from_raw_parts(prefix as *const u8, prefix_len),
+                            &*slice_from_raw_parts(item.name as *const u8, item.name_len),
+                        )
+                    };
+                    if let Ok(name) = String::from_utf8([prefix, name].concat()) {
+                        names.push(name);
+                    }
+                }
+                entry = item.next;
+            }
+            Ok(names)
+        })
+    }
+
+    fn remove_xattr(&self, name: &str) -> VfsResult<()> {
+        let mut fs = self.fs.lock();
+        with_xattr_name(name, |index, name, len| {
+            with_raw_inode_ref(&mut fs, self.ino, |inode| {
+                check_ret(unsafe { ext4_xattr_remove(inode, index, name, len) })
+            })
+        })?;
+        self.update_ctime_locked(&mut fs, self.ino)
+    }
+}
+
 impl Pollable for Inode {
     fn poll(&self) -> IoEvents {
         IoEvents::IN | IoEvents::OUT
@@ -228,6 +530,7 @@ impl DirNodeOps for Inode {
         let ino = fs
             .create(self.ino, name, inode_type, permission.bits() as _)
             .map_err(into_vfs_err)?;
+        invalidate_entry(self.this.as_ref(), name);
         self.update_ctime_locked(&mut fs, ino)?;
 
         let reference = Reference::new(
@@ -252,18 +555,74 @@ impl DirNodeOps for Inode {
         let mut fs = self.fs.lock();
         fs.link(self.ino, name, node.inode() as _)
             .map_err(into_vfs_err)?;
+        invalidate_entry(self.this.as_ref(), name);
         self.update_ctime_locked(&mut fs, node.inode() as _)?;
         self.lookup_locked(&mut fs, name)
     }
 
     fn unlink(&self, name: &str) -> VfsResult<()> {
-        self.fs.lock().unlink(self.ino, name).map_err(into_vfs_err)
+        self.fs
+            .lock()
+            .unlink(self.ino, name)
+            .map_err(into_vfs_err)?;
+        invalidate_entry(self.this.as_ref(), name);
+        Ok(())
     }
 
     fn rename(&self, src_name: &str, dst_dir: &DirNode, dst_name: &str) -> VfsResult<()> {
+        let flags = rename_flags();
         let dst_dir: Arc<Self> = dst_dir.downcast().map_err(|_| VfsError::EINVAL)?;
         let mut fs = self.fs.lock();
-        fs.rename(self.ino, src_name, dst_dir.ino, dst_name)
-            .map_err(into_vfs_err)
+        if flags.contains(RenameFlags::EXCHANGE) {
+            exchange(&mut fs, self.ino, src_name, dst_dir.ino, dst_name)?;
+        } else if flags.contains(RenameFlags::NOREPLACE) && fs.lookup(dst_dir.ino, dst_name).is_ok()
+        {
+            return Err(VfsError::EEXIST);
+        } else {
+            fs.rename(self.ino, src_name, dst_dir.ino, dst_name)
+                .map_err(into_vfs_err)?;
+        }
+        invalidate_entry(self.this.as_ref(), src_name);
+        invalidate_entry(dst_dir.this.as_ref(), dst_name);
+        Ok(())
+    }
+}
+
+/// Swaps `src_dir/src_name` and `dst_dir/dst_name` through a temporary name,
+/// undoing the completed steps if one fails.
+///
+/// The caller holds the filesystem lock, so no other operation sees the
+/// intermediate states.
+fn exchange(
+    fs: &mut LwExt4Filesystem,
+    src_dir: u32,
+    src_name: &str,
+    dst_dir: u32,
+    dst_name: &str,
+) -> VfsResult<()> {
+    fs.lookup(src_dir, src_name).map_err(into_vfs_err)?;
+    fs.lookup(dst_dir, dst_name).map_err(into_vfs_err)?;
+    if src_dir == dst_dir && src_name == dst_name {
+        return Ok(());
+    }
+    let mut i = 0;
+    let temp = loop {
+        let temp = format!(".exchange-{i}");
+        if fs.lookup(src_dir, &temp).is_err() {
+            break temp;
+        }
+        i += 1;
+    };
+    fs.rename(src_dir, src_name, src_dir, &temp)
+        .map_err(into_vfs_err)?;
+    if let Err(err) = fs.rename(dst_dir, dst_name, src_dir, src_name) {
+        let _ = fs.rename(src_dir, &temp, src_dir, src_name);
+        return Err(into_vfs_err(err));
+    }
+    if let Err(err) = fs.rename(src_dir, &temp, dst_dir, dst_name) {
+        let _ = fs.rename(src_dir, src_name, dst_dir, dst_name);
+        let _ = fs.rename(src_dir, &temp, src_dir, src_name);
+        return Err(into_vfs_err(err));
     }
+    Ok(())
 }
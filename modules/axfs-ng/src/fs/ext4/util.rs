@@ -1,5 +1,5 @@
-use axfs_ng_vfs::{NodeType, VfsError};
-use lwext4_rust::{Ext4Error, InodeType, SystemHal};
+use axfs_ng_vfs::{NodeType, VfsError, VfsResult};
+use lwext4_rust::{Ext4Error, Ext4Result, InodeType, SystemHal, ffi::ext4_inode_ref};
 
 use super::Ext4Disk;
 
@@ -20,6 +20,26 @@ pub fn into_vfs_err(err: Ext4Error) -> VfsError {
     VfsError::try_from(err.code).unwrap_or(VfsError::EIO)
 }
 
+/// Calls `f` with the raw lwext4 reference of inode `ino`, for the operations
+/// the bindings do not wrap.
+pub fn with_raw_inode_ref<R>(
+    fs: &mut LwExt4Filesystem,
+    ino: u32,
+    f: impl FnOnce(*mut ext4_inode_ref) -> Ext4Result<R>,
+) -> VfsResult<R> {
+    fs.with_inode_ref(ino, |inode| f(inode.as_raw_mut()))
+        .map_err(into_vfs_err)
+}
+
+/// Converts the return code of an lwext4 function.
+pub fn check_ret(ret: i32) -> Ext4Result<()> {
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Ext4Error::new(ret as _, None))
+    }
+}
+
 pub fn into_vfs_type(ty: InodeType) -> NodeType {
     match ty {
         InodeType::RegularFile => NodeType::RegularFile,
@@ -1,4 +1,4 @@
-use alloc::{string::String, sync::Arc};
+use alloc::{format, string::String, sync::Arc};
 use core::{any::Any, mem, ops::Deref, time::Duration};
 
 use axfs_ng_vfs::{
@@ -12,6 +12,10 @@ use super::{
     fs::FatFilesystem,
     util::{file_metadata, into_vfs_err},
 };
+use crate::{
+    fs::rename_flags,
+    highlevel::{RenameFlags, invalidate_entry},
+};
 
 pub struct FatDirNode {
     fs: Arc<FatFilesystem>,
@@ -46,6 +50,34 @@ impl FatDirNode {
             )
         }
     }
+
+    /// Moves `self/src_name` to `dst_dir/dst_name` as requested by the flags
+    /// of the rename in progress.
+    fn move_entry(&self, src_name: &str, dst_dir: &FatDirNode, dst_name: &str) -> VfsResult<()> {
+        let flags = rename_flags();
+        let fs = self.fs.lock();
+        let dir = self.inner.borrow(&fs);
+        if flags.contains(RenameFlags::EXCHANGE) {
+            return exchange(dir, src_name, dst_dir.inner.borrow(&fs), dst_name);
+        }
+        if flags.contains(RenameFlags::NOREPLACE) && has_entry(dst_dir.inner.borrow(&fs), dst_name)?
+        {
+            return Err(VfsError::EEXIST);
+        }
+
+        // The default implementation throws EEXIST if dst exists, so we need to
+        // handle it
+        match dst_dir.inner.borrow(&fs).remove(dst_name) {
+            Ok(_) => {
+                log::warn!("对 I removed {}", dst_name);
+            }
+            Err(fatfs::Error::NotFound) => {}
+            Err(err) => return Err(into_vfs_err(err)),
+        }
+
+        dir.rename(src_name, dst_dir.inner.borrow(&fs), dst_name)
+            .map_err(into_vfs_err)
+    }
 }
 
 unsafe impl Send for FatDirNode {}
@@ -156,7 +188,7 @@ impl DirNodeOps for FatDirNode {
         let mut fs = self.fs.lock();
         let dir = self.inner.borrow(&fs);
         let reference = Reference::new(self.this.upgrade(), name.to_ascii_lowercase());
-        match node_type {
+        let result = match node_type {
             NodeType::RegularFile => dir
                 .create_file(name)
                 .map(|file| {
@@ -177,7 +209,8 @@ impl DirNodeOps for FatDirNode {
                 })
                 .map_err(into_vfs_err),
             _ => Err(VfsError::EINVAL),
-        }
+        };
+        result.inspect(|_| invalidate_entry(Some(&self.this), name))
     }
 
     fn link(&self, _name: &str, _node: &DirEntry) -> VfsResult<DirEntry> {
@@ -189,28 +222,63 @@ impl DirNodeOps for FatDirNode {
     fn unlink(&self, name: &str) -> VfsResult<()> {
         let fs = self.fs.lock();
         let dir = self.inner.borrow(&fs);
-        dir.remove(name).map_err(into_vfs_err)
+        dir.remove(name).map_err(into_vfs_err)?;
+        invalidate_entry(Some(&self.this), name);
+        Ok(())
     }
 
     fn rename(&self, src_name: &str, dst_dir: &DirNode, dst_name: &str) -> VfsResult<()> {
-        let fs = self.fs.lock();
         let dst_dir: Arc<Self> = dst_dir.downcast().map_err(|_| VfsError::EINVAL)?;
+        self.move_entry(src_name, &dst_dir, dst_name)?;
+        invalidate_entry(Some(&self.this), src_name);
+        invalidate_entry(Some(&dst_dir.this), dst_name);
+        Ok(())
+    }
+}
 
-        let dir = self.inner.borrow(&fs);
-
-        // The default implementation throws EEXIST if dst exists, so we need to
-        // handle it
-        match dst_dir.inner.borrow(&fs).remove(dst_name) {
-            Ok(_) => {
-                log::warn!("对 I removed {}", dst_name);
-            }
-            Err(fatfs::Error::NotFound) => {}
-            Err(err) => return Err(into_vfs_err(err)),
+/// Returns whether `dir` has an entry named `name`.
+fn has_entry(dir: &ff::Dir, name: &str) -> VfsResult<bool> {
+    for entry in dir.iter() {
+        if entry.map_err(into_vfs_err)?.eq_name(name) {
+            return Ok(true);
         }
+    }
+    Ok(false)
+}
 
-        dir.rename(src_name, dst_dir.inner.borrow(&fs), dst_name)
-            .map_err(into_vfs_err)
+/// Swaps `src_dir/src_name` and `dst_dir/dst_name` through a temporary name,
+/// undoing the completed steps if one fails.
+///
+/// FAT has no way to swap entries atomically, but the caller holds the
+/// filesystem lock, so no other operation sees the intermediate states.
+fn exchange(src_dir: &ff::Dir, src_name: &str, dst_dir: &ff::Dir, dst_name: &str) -> VfsResult<()> {
+    if !has_entry(src_dir, src_name)? || !has_entry(dst_dir, dst_name)? {
+        return Err(VfsError::ENOENT);
+    }
+    if core::ptr::eq(src_dir, dst_dir) && src_name == dst_name {
+        return Ok(());
+    }
+    let mut i = 0;
+    let temp = loop {
+        let temp = format!(".exchange-{i}");
+        if !has_entry(src_dir, &temp)? {
+            break temp;
+        }
+        i += 1;
+    };
+    src_dir
+        .rename(src_name, src_dir, &temp)
+        .map_err(into_vfs_err)?;
+    if let Err(err) = dst_dir.rename(dst_name, src_dir, src_name) {
+        let _ = src_dir.rename(&temp, src_dir, src_name);
+        return Err(into_vfs_err(err));
+    }
+    if let Err(err) = src_dir.rename(&temp, dst_dir, dst_name) {
+        let _ = src_dir.rename(src_name, dst_dir, dst_name);
+        let _ = src_dir.rename(&temp, src_dir, src_name);
+        return Err(into_vfs_err(err));
     }
+    Ok(())
 }
 
 impl Drop for FatDirNode {
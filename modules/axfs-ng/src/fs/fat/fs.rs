@@ -1,7 +1,6 @@
 use alloc::sync::Arc;
 use core::marker::PhantomPinned;
 
-use axdriver::AxBlockDevice;
 use axfs_ng_vfs::{
     DirEntry, Filesystem, FilesystemOps, Reference, StatFs, VfsResult, path::MAX_NAME_LEN,
 };
@@ -9,7 +8,7 @@ use kspin::{SpinNoPreempt as Mutex, SpinNoPreemptGuard as MutexGuard};
 use slab::Slab;
 
 use super::{dir::FatDirNode, ff, util::into_vfs_err};
-use crate::disk::SeekableDisk;
+use crate::{bcache::CachedDisk, disk::SeekableDisk};
 
 pub struct FatFilesystemInner {
     pub inner: ff::FileSystem,
@@ -33,10 +32,10 @@ pub struct FatFilesystem {
 }
 
 impl FatFilesystem {
-    pub fn new(dev: AxBlockDevice) -> Filesystem {
+    pub fn new(disk: CachedDisk) -> VfsResult<Filesystem> {
         let mut inner = FatFilesystemInner {
-            inner: ff::FileSystem::new(SeekableDisk::new(dev), fatfs::FsOptions::new())
-                .expect("failed to initialize FAT filesystem"),
+            inner: ff::FileSystem::new(SeekableDisk::new(disk), fatfs::FsOptions::new())
+                .map_err(into_vfs_err)?,
             inode_allocator: Slab::new(),
             _pinned: PhantomPinned,
         };
@@ -58,7 +57,7 @@ impl FatFilesystem {
             Reference::root(),
         );
         *result.root_dir.lock() = Some(root_dir);
-        Filesystem::new(result)
+        Ok(Filesystem::new(result))
     }
 }
 
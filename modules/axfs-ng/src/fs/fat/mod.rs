@@ -43,7 +43,7 @@ impl fatfs::Seek for SeekableDisk {
             SeekFrom::End(off) => size.checked_add_signed(off),
         }
         .ok_or(())?;
-        self.set_position(new_pos).map_err(|_| ())?;
+        self.set_position(new_pos);
         Ok(new_pos)
     }
 }
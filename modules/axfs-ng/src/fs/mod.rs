@@ -4,18 +4,191 @@ pub mod fat;
 #[cfg(feature = "ext4")]
 pub mod ext4;
 
+pub mod devfs;
+pub mod procfs;
+pub mod tmpfs;
+
+mod probe;
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
 use axdriver::AxBlockDevice;
-use axfs_ng_vfs::{Filesystem, VfsResult};
-use cfg_if::cfg_if;
+use axfs_ng_vfs::{FileNode, Filesystem, Location, VfsError, VfsResult};
+pub use probe::*;
 
+use crate::{bcache::CachedDisk, highlevel::RenameFlags};
+
+/// Creates the filesystem found on `dev`, see [`probe`].
 pub fn new_default(dev: AxBlockDevice) -> VfsResult<Filesystem> {
-    cfg_if! {
-        if #[cfg(feature = "ext4")] {
-            ext4::Ext4Filesystem::new(dev)
-        } else if #[cfg(feature = "fat")] {
-            Ok(fat::FatFilesystem::new(dev))
-        } else {
-            panic!("No filesystem feature enabled");
+    probe(CachedDisk::new(dev))
+}
+
+/// Operations on the allocated space of regular files, which the VFS does
+/// not cover.
+pub(crate) trait SpaceOps {
+    /// Deallocates `range` of the file, which then reads as zeros, without
+    /// changing its size.
+    fn punch_hole(&self, range: Range<u64>) -> VfsResult<()>;
+
+    /// Returns the first offset at or after `offset` that is in data if
+    /// `data` is `true`, or in a hole otherwise. The end of the file counts
+    /// as a hole.
+    ///
+    /// Fails with `ENXIO` if `offset` is beyond the end of the file or no
+    /// such offset exists.
+    fn seek_data(&self, offset: u64, data: bool) -> VfsResult<u64>;
+}
+
+/// Returns the space operations of `file`, or `None` if its filesystem does
+/// not support holes.
+pub(crate) fn space_ops(file: &FileNode) -> Option<Arc<dyn SpaceOps>> {
+    if let Ok(node) = file.downcast::<tmpfs::TmpNode>() {
+        return Some(node);
+    }
+    #[cfg(feature = "ext4")]
+    if let Ok(node) = file.downcast::<ext4::Inode>() {
+        return Some(node);
+    }
+    None
+}
+
+/// Operations on the extended attributes of files, which the VFS does not
+/// cover. Names include their namespace prefix, e.g. `user.`.
+pub(crate) trait XattrOps {
+    /// Returns the value of an attribute, or fails with `ENODATA`.
+    fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>>;
+
+    /// Sets an attribute. If `create` is `true`, fails with `EEXIST` if it
+    /// exists, and if `replace` is `true`, fails with `ENODATA` if it does
+    /// not.
+    fn set_xattr(&self, name: &str, value: &[u8], create: bool, replace: bool) -> VfsResult<()>;
+
+    /// Returns the names of the attributes.
+    fn list_xattr(&self) -> VfsResult<Vec<String>>;
+
+    /// Removes an attribute, or fails with `ENODATA`.
+    fn remove_xattr(&self, name: &str) -> VfsResult<()>;
+}
+
+/// Returns the extended attribute operations of the node at `loc`, or
+/// `None` if its filesystem cannot store extended attributes.
+pub(crate) fn xattr_ops(loc: &Location) -> Option<Arc<dyn XattrOps>> {
+    let entry = loc.entry();
+    if let Ok(dir) = entry.as_dir() {
+        if let Ok(node) = dir.downcast::<tmpfs::TmpNode>() {
+            return Some(node);
+        }
+        #[cfg(feature = "ext4")]
+        if let Ok(node) = dir.downcast::<ext4::Inode>() {
+            return Some(node);
         }
+        return None;
+    }
+    let file = entry.as_file().ok()?;
+    if let Ok(node) = file.downcast::<tmpfs::TmpNode>() {
+        return Some(node);
+    }
+    #[cfg(feature = "ext4")]
+    if let Ok(node) = file.downcast::<ext4::Inode>() {
+        return Some(node);
+    }
+    None
+}
+
+/// The flags of the renames in progress, by task.
+///
+/// `DirNodeOps::rename` of the VFS has no way to pass them, so the `rename`
+/// of the filesystems accepted by [`supports_rename_flags`] reads them with
+/// [`rename_flags`] instead. Without the `multitask` feature there is a
+/// single task, whose key is `0`.
+static RENAME_FLAGS: spin::Mutex<BTreeMap<u64, RenameFlags>> = spin::Mutex::new(BTreeMap::new());
+
+/// The number of entries in [`RENAME_FLAGS`], so that renames without flags
+/// do not take its lock.
+static RENAMES_WITH_FLAGS: AtomicUsize = AtomicUsize::new(0);
+
+fn current_task() -> u64 {
+    #[cfg(feature = "multitask")]
+    return axtask::current().id().as_u64();
+    #[cfg(not(feature = "multitask"))]
+    0
+}
+
+/// Returns whether the filesystem of the directory `dir` reads the flags of
+/// its renames.
+fn supports_rename_flags(dir: &Location) -> bool {
+    let Ok(dir) = dir.entry().as_dir() else {
+        return false;
+    };
+    #[cfg(feature = "fat")]
+    if dir.downcast::<fat::FatDirNode>().is_ok() {
+        return true;
+    }
+    #[cfg(feature = "ext4")]
+    if dir.downcast::<ext4::Inode>().is_ok() {
+        return true;
+    }
+    dir.downcast::<tmpfs::TmpNode>().is_ok()
+}
+
+/// Clears the flags set by [`with_rename_flags`] when dropped, so that they
+/// are cleared even if the rename panics.
+struct RenameFlagsGuard(u64);
+
+impl Drop for RenameFlagsGuard {
+    fn drop(&mut self) {
+        RENAME_FLAGS.lock().remove(&self.0);
+        RENAMES_WITH_FLAGS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Calls `f`, which renames in the directory `dir` through the VFS, with
+/// `flags` passed to the `rename` of the filesystem.
+///
+/// Fails with `EINVAL` if `flags` is not empty and the filesystem would
+/// ignore it.
+pub(crate) fn with_rename_flags(
+    dir: &Location,
+    flags: RenameFlags,
+    f: impl FnOnce() -> VfsResult<()>,
+) -> VfsResult<()> {
+    if flags.is_empty() {
+        return f();
+    }
+    if !supports_rename_flags(dir) {
+        return Err(VfsError::EINVAL);
+    }
+    let task = current_task();
+    RENAME_FLAGS.lock().insert(task, flags);
+    RENAMES_WITH_FLAGS.fetch_add(1, Ordering::Relaxed);
+    let _guard = RenameFlagsGuard(task);
+    f()
+}
+
+/// Returns the flags of the rename in progress on the current task, see
+/// [`with_rename_flags`].
+pub(crate) fn rename_flags() -> RenameFlags {
+    if RENAMES_WITH_FLAGS.load(Ordering::Relaxed) == 0 {
+        return RenameFlags::empty();
+    }
+    RENAME_FLAGS
+        .lock()
+        .get(&current_task())
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Returns the current time for the timestamps of in-memory filesystems, or
+/// zero without the `times` feature.
+pub(crate) fn now() -> Duration {
+    if cfg!(feature = "times") {
+        axhal::time::wall_time()
+    } else {
+        Duration::default()
     }
 }
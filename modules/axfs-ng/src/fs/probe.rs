@@ -0,0 +1,94 @@
+//! Filesystem detection from the contents of a block device.
+
+use alloc::{vec, vec::Vec};
+
+use axfs_ng_vfs::{Filesystem, VfsError, VfsResult};
+use log::{debug, warn};
+use spin::{Lazy, Mutex};
+
+use crate::bcache::CachedDisk;
+
+/// Number of bytes at the start of a device passed to
+/// [`FsDriver::probe`].
+pub const PROBE_SIZE: usize = 4096;
+
+/// A filesystem driver that can be selected by [`probe`].
+#[derive(Clone, Copy)]
+pub struct FsDriver {
+    /// The name of the filesystem type.
+    pub name: &'static str,
+    /// Checks whether the first [`PROBE_SIZE`] bytes of a device hold this
+    /// filesystem.
+    pub probe: fn(&[u8]) -> bool,
+    /// Creates the filesystem on a disk accepted by `probe`.
+    pub mount: fn(CachedDisk) -> VfsResult<Filesystem>,
+}
+
+static DRIVERS: Lazy<Mutex<Vec<FsDriver>>> = Lazy::new(|| {
+    #[allow(unused_mut)]
+    let mut drivers = Vec::new();
+    #[cfg(feature = "ext4")]
+    drivers.push(FsDriver {
+        name: "ext4",
+        probe: is_ext,
+        mount: super::ext4::Ext4Filesystem::new,
+    });
+    #[cfg(feature = "fat")]
+    drivers.push(FsDriver {
+        name: "vfat",
+        probe: is_fat,
+        mount: super::fat::FatFilesystem::new,
+    });
+    Mutex::new(drivers)
+});
+
+/// Registers a filesystem driver, which takes precedence over the ones
+/// registered before it.
+pub fn register_filesystem(driver: FsDriver) {
+    DRIVERS.lock().insert(0, driver);
+}
+
+/// Detects the filesystem on `disk` and creates it.
+///
+/// Fails with `EINVAL` if no registered driver recognizes the disk.
+pub fn probe(disk: CachedDisk) -> VfsResult<Filesystem> {
+    let mut buf = vec![0u8; PROBE_SIZE];
+    let read = disk
+        .read_at(&mut buf, 0)
+        .inspect_err(|err| warn!("Failed to read superblock: {err:?}"))?;
+    if read < PROBE_SIZE {
+        return Err(VfsError::EINVAL);
+    }
+
+    let driver = DRIVERS
+        .lock()
+        .iter()
+        .find(|driver| (driver.probe)(&buf))
+        .copied()
+        .ok_or(VfsError::EINVAL)?;
+    debug!("Detected {} filesystem", driver.name);
+    (driver.mount)(disk)
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+/// Checks for an ext2/3/4 superblock, whose magic is at byte 56 of the
+/// superblock starting at byte 1024.
+pub fn is_ext(buf: &[u8]) -> bool {
+    read_u16(buf, 1080) == 0xef53
+}
+
+/// Checks for a FAT12/16/32 boot sector.
+pub fn is_fat(buf: &[u8]) -> bool {
+    let jump = buf[0] == 0xe9 || (buf[0] == 0xeb && buf[2] == 0x90);
+    let bytes_per_sector = read_u16(buf, 11);
+    let sectors_per_cluster = buf[13];
+    jump && buf[510..512] == [0x55, 0xaa]
+        && bytes_per_sector.is_power_of_two()
+        && (512..=4096).contains(&bytes_per_sector)
+        && sectors_per_cluster.is_power_of_two()
+        && read_u16(buf, 14) != 0
+        && buf[16] != 0
+}
@@ -0,0 +1,87 @@
+//! Entries of a procfs built from the state known to this crate.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use axalloc::{UsageKind, global_allocator};
+
+use super::ProcEntry;
+use crate::MountFlags;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Returns `meminfo`, `mounts` and, with the `irq` feature, `interrupts`.
+pub fn default_entries() -> Vec<(String, ProcEntry)> {
+    #[allow(unused_mut)]
+    let mut entries = vec![
+        ("meminfo".to_string(), ProcEntry::file(meminfo)),
+        ("mounts".to_string(), ProcEntry::file(mounts)),
+    ];
+    #[cfg(feature = "irq")]
+    entries.push(("interrupts".to_string(), ProcEntry::file(interrupts)));
+    entries
+}
+
+/// Generates `/proc/meminfo` from the global allocator.
+pub fn meminfo() -> String {
+    let allocator = global_allocator();
+    let stats = allocator.usage_stats();
+    let total_pages = allocator.used_pages() + allocator.available_pages();
+    let lines = [
+        ("MemTotal", total_pages * PAGE_SIZE),
+        ("MemFree", allocator.available_pages() * PAGE_SIZE),
+        ("HeapUsed", allocator.used_bytes()),
+        ("HeapFree", allocator.available_bytes()),
+        ("RustHeap", stats.get(UsageKind::RustHeap)),
+        ("UserMem", stats.get(UsageKind::UserMem)),
+        ("PageCache", stats.get(UsageKind::PageCache)),
+        ("Buffers", stats.get(UsageKind::BufferCache)),
+        ("PageTables", stats.get(UsageKind::PageTable)),
+        ("Dma", stats.get(UsageKind::Dma)),
+        ("Global", stats.get(UsageKind::Global)),
+    ];
+    let mut out = String::new();
+    for (name, bytes) in lines {
+        let _ = writeln!(out, "{:<16}{:>10} kB", format!("{name}:"), bytes / 1024);
+    }
+    out
+}
+
+/// Generates `/proc/mounts` from the mount table.
+pub fn mounts() -> String {
+    let mut out = String::new();
+    for mount in crate::mounts() {
+        let mut options = String::from(if mount.flags.contains(MountFlags::RDONLY) {
+            "ro"
+        } else {
+            "rw"
+        });
+        if mount.flags.contains(MountFlags::NOSUID) {
+            options.push_str(",nosuid");
+        }
+        if mount.flags.contains(MountFlags::NOEXEC) {
+            options.push_str(",noexec");
+        }
+        let _ = writeln!(
+            out,
+            "{} {} {} {options} 0 0",
+            mount.source, mount.target, mount.fs_type
+        );
+    }
+    out
+}
+
+/// Generates `/proc/interrupts` from the IRQ counters.
+#[cfg(feature = "irq")]
+pub fn interrupts() -> String {
+    let mut out = String::from("IRQ       COUNT\n");
+    for (irq, count) in axhal::irq::irq_counts() {
+        let _ = writeln!(out, "{irq:>3}: {count:>10}");
+    }
+    out
+}
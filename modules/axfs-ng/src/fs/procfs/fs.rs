@@ -0,0 +1,347 @@
+use alloc::{borrow::ToOwned, string::String, sync::Arc, vec::Vec};
+use core::{any::Any, cell::OnceCell, task::Context, time::Duration};
+
+use axfs_ng_vfs::{
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
+    FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType,
+    Reference, StatFs, VfsError, VfsResult, WeakDirEntry, path::MAX_NAME_LEN,
+};
+use axio::{IoEvents, Pollable};
+
+/// `PROC_SUPER_MAGIC` as reported by Linux.
+const PROC_MAGIC: u64 = 0x9fa0;
+
+const ROOT_INO: u64 = 1;
+
+/// Generates the content of a procfs file.
+pub type ProcGenerator = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// Lists the entries of a procfs directory.
+pub type ProcLister = Arc<dyn Fn() -> Vec<(String, ProcEntry)> + Send + Sync>;
+
+/// An entry of a procfs directory.
+#[derive(Clone)]
+pub enum ProcEntry {
+    /// A read-only file whose content is generated on every read.
+    File(ProcGenerator),
+    /// A directory whose entries are listed on every lookup.
+    Dir(ProcLister),
+}
+
+impl ProcEntry {
+    /// Creates a file generated by `generate`.
+    pub fn file(generate: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        Self::File(Arc::new(generate))
+    }
+
+    /// Creates a directory listed by `list`.
+    pub fn dir(list: impl Fn() -> Vec<(String, ProcEntry)> + Send + Sync + 'static) -> Self {
+        Self::Dir(Arc::new(list))
+    }
+
+    /// Creates a directory holding a fixed set of entries.
+    pub fn fixed_dir(entries: Vec<(String, ProcEntry)>) -> Self {
+        Self::dir(move || entries.clone())
+    }
+
+    fn node_type(&self) -> NodeType {
+        match self {
+            Self::File(_) => NodeType::RegularFile,
+            Self::Dir(_) => NodeType::Directory,
+        }
+    }
+}
+
+/// Derives a stable inode number from the parent's one and the name, as
+/// procfs entries come and go with the kernel state.
+fn entry_ino(parent: u64, name: &str) -> u64 {
+    // FNV-1a
+    let mut hash = 0xcbf2_9ce4_8422_2325 ^ parent;
+    for &byte in name.as_bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    // Keep clear of the root inode.
+    hash.max(ROOT_INO + 1)
+}
+
+/// A filesystem exposing kernel state, usually mounted on `/proc`.
+///
+/// See [`default_entries`](super::default_entries) for the entries provided
+/// by this crate.
+pub struct ProcFilesystem {
+    created: Duration,
+    root_dir: OnceCell<DirEntry>,
+}
+
+impl ProcFilesystem {
+    /// Creates a procfs whose root directory is listed by `root`.
+    pub fn new(root: impl Fn() -> Vec<(String, ProcEntry)> + Send + Sync + 'static) -> Filesystem {
+        let fs = Arc::new(Self {
+            created: crate::fs::now(),
+            root_dir: OnceCell::new(),
+        });
+        let list: ProcLister = Arc::new(root);
+        let _ = fs.root_dir.set(DirEntry::new_dir(
+            |this| {
+                DirNode::new(Arc::new(ProcDir {
+                    fs: fs.clone(),
+                    ino: ROOT_INO,
+                    parent_ino: ROOT_INO,
+                    list,
+                    this,
+                }))
+            },
+            Reference::root(),
+        ));
+        Filesystem::new(fs)
+    }
+
+    fn metadata(&self, inode: u64, node_type: NodeType, mode: u16) -> Metadata {
+        let now = crate::fs::now();
+        Metadata {
+            inode,
+            device: 0,
+            nlink: 1,
+            mode: NodePermission::from_bits_truncate(mode),
+            node_type,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            block_size: 4096,
+            blocks: 0,
+            rdev: DeviceId::default(),
+            atime: now,
+            mtime: now,
+            ctime: self.created,
+        }
+    }
+}
+
+unsafe impl Send for ProcFilesystem {}
+
+unsafe impl Sync for ProcFilesystem {}
+
+impl FilesystemOps for ProcFilesystem {
+    fn name(&self) -> &str {
+        "proc"
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.root_dir.get().unwrap().clone()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        Ok(StatFs {
+            fs_type: PROC_MAGIC as _,
+            block_size: 4096,
+            blocks: 0,
+            blocks_free: 0,
+            blocks_available: 0,
+
+            file_count: 0,
+            free_file_count: 0,
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: 0,
+            mount_flags: 0,
+        })
+    }
+}
+
+/// A directory of a procfs.
+struct ProcDir {
+    fs: Arc<ProcFilesystem>,
+    ino: u64,
+    parent_ino: u64,
+    list: ProcLister,
+    this: WeakDirEntry,
+}
+
+impl NodeOps for ProcDir {
+    fn inode(&self) -> u64 {
+        self.ino
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        let mut meta = self.fs.metadata(self.ino, NodeType::Directory, 0o555);
+        meta.nlink = 2;
+        Ok(meta)
+    }
+
+    fn update_metadata(&self, _update: MetadataUpdate) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        Ok(0)
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        &*self.fs
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+impl DirNodeOps for ProcDir {
+    fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        let entries = (self.list)();
+        let entries = [(".", self.ino), ("..", self.parent_ino)]
+            .into_iter()
+            .map(|(name, ino)| (name, ino, NodeType::Directory))
+            .chain(entries.iter().map(|(name, entry)| {
+                (name.as_str(), entry_ino(self.ino, name), entry.node_type())
+            }));
+        let mut count = 0;
+        for (i, (name, ino, node_type)) in entries.enumerate().skip(offset as usize) {
+            if !sink.accept(name, ino, node_type, i as u64 + 1) {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        let entry = (self.list)()
+            .into_iter()
+            .find_map(|(entry_name, entry)| (entry_name == name).then_some(entry))
+            .ok_or(VfsError::ENOENT)?;
+        let ino = entry_ino(self.ino, name);
+        let reference = Reference::new(self.this.upgrade(), name.to_owned());
+        Ok(match entry {
+            ProcEntry::File(generate) => DirEntry::new_file(
+                FileNode::new(Arc::new(ProcFile {
+                    fs: self.fs.clone(),
+                    ino,
+                    generate,
+                })),
+                NodeType::RegularFile,
+                reference,
+            ),
+            ProcEntry::Dir(list) => DirEntry::new_dir(
+                |this| {
+                    DirNode::new(Arc::new(ProcDir {
+                        fs: self.fs.clone(),
+                        ino,
+                        parent_ino: self.ino,
+                        list,
+                        this,
+                    }))
+                },
+                reference,
+            ),
+        })
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<DirEntry> {
+        Err(VfsError::EPERM)
+    }
+
+    fn link(&self, _name: &str, _node: &DirEntry) -> VfsResult<DirEntry> {
+        Err(VfsError::EPERM)
+    }
+
+    fn unlink(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+
+    fn rename(&self, _src_name: &str, _dst_dir: &DirNode, _dst_name: &str) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+}
+
+/// A generated file of a procfs.
+///
+/// Like on Linux, the file reports a size of zero and its content is
+/// generated again on every read.
+struct ProcFile {
+    fs: Arc<ProcFilesystem>,
+    ino: u64,
+    generate: ProcGenerator,
+}
+
+impl NodeOps for ProcFile {
+    fn inode(&self) -> u64 {
+        self.ino
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        Ok(self.fs.metadata(self.ino, NodeType::RegularFile, 0o444))
+    }
+
+    fn update_metadata(&self, _update: MetadataUpdate) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        Ok(0)
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        &*self.fs
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+impl FileNodeOps for ProcFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let content = (self.generate)();
+        let content = content.as_bytes();
+        let start = (offset as usize).min(content.len());
+        let len = buf.len().min(content.len() - start);
+        buf[..len].copy_from_slice(&content[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(VfsError::EPERM)
+    }
+
+    fn append(&self, _buf: &[u8]) -> VfsResult<(usize, u64)> {
+        Err(VfsError::EPERM)
+    }
+
+    fn set_len(&self, _len: u64) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+
+    fn set_symlink(&self, _target: &str) -> VfsResult<()> {
+        Err(VfsError::EINVAL)
+    }
+}
+
+impl Pollable for ProcFile {
+    fn poll(&self) -> IoEvents {
+        IoEvents::IN
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
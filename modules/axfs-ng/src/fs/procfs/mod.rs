@@ -0,0 +1,8 @@
+//! A filesystem whose files are generated from kernel state when read,
+//! usually mounted on `/proc`.
+
+mod entries;
+mod fs;
+
+pub use entries::*;
+pub use fs::*;
@@ -0,0 +1,130 @@
+use alloc::sync::Arc;
+use core::{
+    cell::OnceCell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axalloc::global_allocator;
+use axfs_ng_vfs::{
+    DirEntry, DirNode, Filesystem, FilesystemOps, NodePermission, Reference, StatFs, VfsError,
+    VfsResult, path::MAX_NAME_LEN,
+};
+use kspin::SpinNoPreempt as Mutex;
+
+use super::{Inode, TmpNode};
+
+/// `TMPFS_MAGIC` as reported by Linux.
+const TMPFS_MAGIC: u64 = 0x0102_1994;
+
+pub(crate) const BLOCK_SIZE: u64 = 4096;
+
+/// Space accounting of a tmpfs.
+pub(crate) struct Usage {
+    limit: Option<u64>,
+    used: AtomicU64,
+}
+
+impl Usage {
+    /// Charges `bytes` to the filesystem, failing with `ENOSPC` if it would
+    /// exceed the size limit.
+    pub fn reserve(&self, bytes: u64) -> VfsResult<()> {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                let new = used.checked_add(bytes)?;
+                self.limit.is_none_or(|limit| new <= limit).then_some(new)
+            })
+            .map(|_| ())
+            .map_err(|_| VfsError::ENOSPC)
+    }
+
+    pub fn release(&self, bytes: u64) {
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+}
+
+/// A filesystem keeping all of its contents in memory.
+///
+/// Regular file contents are stored in page cache pages, which
+/// [`CachedFile`](crate::CachedFile) uses directly instead of caching a copy.
+pub struct TmpFilesystem {
+    usage: Arc<Usage>,
+    next_ino: AtomicU64,
+    /// Serializes changes to the directory tree, so that operations touching
+    /// multiple directories see a consistent state.
+    pub(crate) namespace_lock: Mutex<()>,
+    root_dir: OnceCell<DirEntry>,
+}
+
+impl TmpFilesystem {
+    /// Creates an empty tmpfs.
+    ///
+    /// The contents are limited to `size_limit` bytes, or to the available
+    /// memory if it is `None`.
+    pub fn new(size_limit: Option<u64>) -> Filesystem {
+        let fs = Arc::new(Self {
+            usage: Arc::new(Usage {
+                limit: size_limit,
+                used: AtomicU64::new(0),
+            }),
+            next_ino: AtomicU64::new(1),
+            namespace_lock: Mutex::new(()),
+            root_dir: OnceCell::new(),
+        });
+        let root = Inode::new_dir(
+            fs.alloc_ino(),
+            NodePermission::from_bits_truncate(0o1777),
+            None,
+            fs.usage.clone(),
+        );
+        let _ = fs.root_dir.set(DirEntry::new_dir(
+            |this| DirNode::new(TmpNode::new(fs.clone(), root, Some(this))),
+            Reference::root(),
+        ));
+        Filesystem::new(fs)
+    }
+
+    pub(crate) fn alloc_ino(&self) -> u64 {
+        self.next_ino.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn usage(&self) -> &Arc<Usage> {
+        &self.usage
+    }
+}
+
+unsafe impl Send for TmpFilesystem {}
+
+unsafe impl Sync for TmpFilesystem {}
+
+impl FilesystemOps for TmpFilesystem {
+    fn name(&self) -> &str {
+        "tmpfs"
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.root_dir.get().unwrap().clone()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        let used = self.usage.used.load(Ordering::Acquire);
+        let total = self
+            .usage
+            .limit
+            .unwrap_or_else(|| used + global_allocator().available_bytes() as u64);
+        let free = total.saturating_sub(used) / BLOCK_SIZE;
+        Ok(StatFs {
+            fs_type: TMPFS_MAGIC as _,
+            block_size: BLOCK_SIZE as _,
+            blocks: total / BLOCK_SIZE,
+            blocks_free: free,
+            blocks_available: free,
+
+            file_count: 0,
+            free_file_count: 0,
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: 0,
+            mount_flags: 0,
+        })
+    }
+}
@@ -0,0 +1,855 @@
+use alloc::{
+    borrow::ToOwned,
+    collections::btree_map::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{any::Any, ops::Range, task::Context, time::Duration};
+
+use axfs_ng_vfs::{
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, FilesystemOps,
+    Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType, Reference, VfsError,
+    VfsResult, WeakDirEntry,
+};
+use axio::{IoEvents, Pollable};
+use kspin::SpinNoPreempt as Mutex;
+use lru::LruCache;
+
+use super::{
+    TmpFilesystem,
+    fs::{BLOCK_SIZE, Usage},
+};
+use crate::{
+    fs::{SpaceOps, XattrOps, now, rename_flags},
+    highlevel::{CachedFileShared, PageAction, PageCache, RenameFlags, invalidate_entry},
+};
+
+struct InodeMeta {
+    mode: NodePermission,
+    uid: u32,
+    gid: u32,
+    nlink: u64,
+    size: u64,
+    atime: Duration,
+    mtime: Duration,
+    ctime: Duration,
+}
+
+impl InodeMeta {
+    fn touch(&mut self) {
+        let now = now();
+        self.mtime = now;
+        self.ctime = now;
+    }
+}
+
+struct DirContent {
+    parent: Weak<Inode>,
+    entries: BTreeMap<String, Arc<Inode>>,
+}
+
+enum Content {
+    /// Pages of a regular file, shared with its `CachedFile`s.
+    File(Arc<CachedFileShared>),
+    Dir(Mutex<DirContent>),
+    Symlink(Mutex<String>),
+    /// Devices, FIFOs and sockets, which have no contents.
+    Special,
+}
+
+/// A tmpfs inode, shared by all the hard links to it.
+pub(crate) struct Inode {
+    ino: u64,
+    node_type: NodeType,
+    usage: Arc<Usage>,
+    meta: Mutex<InodeMeta>,
+    content: Content,
+    /// Extended attributes, charged to the filesystem by name and value
+    /// length.
+    xattrs: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl Inode {
+    fn new(
+        ino: u64,
+        node_type: NodeType,
+        mode: NodePermission,
+        content: Content,
+        usage: Arc<Usage>,
+    ) -> Arc<Self> {
+        let now = now();
+        Arc::new(Self {
+            ino,
+            node_type,
+            usage,
+            meta: Mutex::new(InodeMeta {
+                mode,
+                uid: 0,
+                gid: 0,
+                nlink: if node_type == NodeType::Directory {
+                    2
+                } else {
+                    1
+                },
+                size: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+            }),
+            content,
+            xattrs: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub(crate) fn new_dir(
+        ino: u64,
+        mode: NodePermission,
+        parent: Option<&Arc<Inode>>,
+        usage: Arc<Usage>,
+    ) -> Arc<Self> {
+        let content = Content::Dir(Mutex::new(DirContent {
+            parent: parent.map_or_else(Weak::new, Arc::downgrade),
+            entries: BTreeMap::new(),
+        }));
+        Self::new(ino, NodeType::Directory, mode, content, usage)
+    }
+
+    fn is_dir(&self) -> bool {
+        self.node_type == NodeType::Directory
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        match &self.content {
+            Content::Dir(dir) => dir.lock().entries.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Returns the bytes charged to the filesystem for contents of `size`.
+    fn charge(&self, size: u64) -> u64 {
+        match self.content {
+            Content::File(_) => size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE,
+            _ => size,
+        }
+    }
+
+    fn set_parent(&self, parent: &Arc<Inode>) {
+        if let Content::Dir(dir) = &self.content {
+            dir.lock().parent = Arc::downgrade(parent);
+        }
+    }
+
+    fn add_links(&self, delta: i64) {
+        let mut meta = self.meta.lock();
+        meta.nlink = meta.nlink.saturating_add_signed(delta);
+        meta.ctime = now();
+    }
+}
+
+impl Drop for Inode {
+    fn drop(&mut self) {
+        let size = self.meta.lock().size;
+        self.usage.release(self.charge(size));
+        let xattrs = self.xattrs.get_mut();
+        self.usage.release(
+            xattrs
+                .iter()
+                .map(|(name, value)| (name.len() + value.len()) as u64)
+                .sum(),
+        );
+    }
+}
+
+fn page_range(offset: u64, len: usize) -> impl Iterator<Item = (u32, Range<usize>)> {
+    let end = offset + len as u64;
+    let mut pos = offset;
+    core::iter::from_fn(move || {
+        if pos >= end {
+            return None;
+        }
+        let in_page = (pos % BLOCK_SIZE) as usize;
+        let n = (BLOCK_SIZE - pos % BLOCK_SIZE).min(end - pos) as usize;
+        let item = ((pos / BLOCK_SIZE) as u32, in_page..in_page + n);
+        pos += n as u64;
+        Some(item)
+    })
+}
+
+/// Removes page `pn` from the cache once its mappings have dropped it.
+///
+/// If an address space mapping the page is locked elsewhere, the page is
+/// zeroed and kept instead, as freeing it would leave the mapping pointing
+/// at freed memory.
+fn remove_page(pages: &CachedFileShared, cache: &mut LruCache<u32, PageCache>, pn: u32) {
+    if pages.notify(pn, PageAction::Unmap, None) {
+        cache.pop(&pn);
+    } else if let Some(page) = cache.peek_mut(&pn) {
+        page.data().fill(0);
+    }
+}
+
+/// Fails with `EINVAL` if `dir` is `inode` or within it, as a directory
+/// cannot be moved into its own subtree.
+fn check_not_within(inode: &Arc<Inode>, dir: &Arc<Inode>) -> VfsResult<()> {
+    if !inode.is_dir() {
+        return Ok(());
+    }
+    let mut ancestor = Some(dir.clone());
+    while let Some(dir) = ancestor {
+        if Arc::ptr_eq(&dir, inode) {
+            return Err(VfsError::EINVAL);
+        }
+        ancestor = match &dir.content {
+            Content::Dir(content) => content.lock().parent.upgrade(),
+            _ => None,
+        };
+    }
+    Ok(())
+}
+
+/// A tmpfs node, i.e. an inode as seen through a directory entry.
+pub struct TmpNode {
+    fs: Arc<TmpFilesystem>,
+    inode: Arc<Inode>,
+    this: Option<WeakDirEntry>,
+}
+
+impl TmpNode {
+    pub(crate) fn new(
+        fs: Arc<TmpFilesystem>,
+        inode: Arc<Inode>,
+        this: Option<WeakDirEntry>,
+    ) -> Arc<Self> {
+        Arc::new(Self { fs, inode, this })
+    }
+
+    pub(crate) fn pages(&self) -> Option<Arc<CachedFileShared>> {
+        match &self.inode.content {
+            Content::File(pages) => Some(pages.clone()),
+            _ => None,
+        }
+    }
+
+    fn dir(&self) -> VfsResult<&Mutex<DirContent>> {
+        match &self.inode.content {
+            Content::Dir(dir) => Ok(dir),
+            _ => Err(VfsError::ENOTDIR),
+        }
+    }
+
+    fn create_entry(&self, inode: Arc<Inode>, name: impl Into<String>) -> DirEntry {
+        let reference = Reference::new(
+            self.this.as_ref().and_then(WeakDirEntry::upgrade),
+            name.into(),
+        );
+        if inode.is_dir() {
+            DirEntry::new_dir(
+                |this| DirNode::new(TmpNode::new(self.fs.clone(), inode, Some(this))),
+                reference,
+            )
+        } else {
+            let node_type = inode.node_type;
+            DirEntry::new_file(
+                FileNode::new(TmpNode::new(self.fs.clone(), inode, None)),
+                node_type,
+                reference,
+            )
+        }
+    }
+
+    fn resize(&self, meta: &mut InodeMeta, pages: &CachedFileShared, len: u64) -> VfsResult<()> {
+        let old_charge = self.inode.charge(meta.size);
+        let new_charge = self.inode.charge(len);
+        if new_charge > old_charge {
+            self.inode.usage.reserve(new_charge - old_charge)?;
+        } else {
+            self.inode.usage.release(old_charge - new_charge);
+        }
+
+        if len < meta.size {
+            let mut cache = pages.page_cache.lock();
+            let kept_pages = len.div_ceil(BLOCK_SIZE) as u32;
+            let keys = cache
+                .iter()
+                .map(|(pn, _)| *pn)
+                .filter(|pn| *pn >= kept_pages)
+                .collect::<Vec<_>>();
+            for pn in keys {
+                remove_page(pages, &mut cache, pn);
+            }
+            // Clear the truncated tail of the last page, so that it reads as
+            // zeros if the file grows again.
+            if len % BLOCK_SIZE != 0 {
+                if let Some(page) = cache.peek_mut(&((len / BLOCK_SIZE) as u32)) {
+                    page.data()[(len % BLOCK_SIZE) as usize..].fill(0);
+                }
+            }
+        }
+        meta.size = len;
+        meta.touch();
+        Ok(())
+    }
+
+    /// Moves `self/src_name` to `dst_dir/dst_name` as requested by the flags
+    /// of the rename in progress.
+    fn move_entry(&self, src_name: &str, dst_dir: &TmpNode, dst_name: &str) -> VfsResult<()> {
+        let flags = rename_flags();
+        let _guard = self.fs.namespace_lock.lock();
+        let src_entries = self.dir()?;
+        let dst_entries = dst_dir.dir()?;
+        let inode = src_entries
+            .lock()
+            .entries
+            .get(src_name)
+            .cloned()
+            .ok_or(VfsError::ENOENT)?;
+        let replaced = dst_entries.lock().entries.get(dst_name).cloned();
+        if flags.contains(RenameFlags::NOREPLACE) && replaced.is_some() {
+            return Err(VfsError::EEXIST);
+        }
+        if flags.contains(RenameFlags::EXCHANGE) {
+            let other = replaced.ok_or(VfsError::ENOENT)?;
+            return self.exchange(dst_dir, src_name, inode, dst_name, other);
+        }
+
+        check_not_within(&inode, &dst_dir.inode)?;
+        if let Some(replaced) = &replaced {
+            if Arc::ptr_eq(replaced, &inode) {
+                // Both names refer to the same inode.
+                return Ok(());
+            }
+            match (inode.is_dir(), replaced.is_dir()) {
+                (true, false) => return Err(VfsError::ENOTDIR),
+                (false, true) => return Err(VfsError::EISDIR),
+                (true, true) if !replaced.is_empty_dir() => return Err(VfsError::ENOTEMPTY),
+                _ => {}
+            }
+        }
+
+        src_entries.lock().entries.remove(src_name);
+        dst_entries
+            .lock()
+            .entries
+            .insert(dst_name.to_owned(), inode.clone());
+
+        if let Some(replaced) = replaced {
+            if replaced.is_dir() {
+                replaced.add_links(-2);
+                dst_dir.inode.add_links(-1);
+            } else {
+                replaced.add_links(-1);
+            }
+        }
+        if inode.is_dir() && !Arc::ptr_eq(&self.inode, &dst_dir.inode) {
+            inode.set_parent(&dst_dir.inode);
+            self.inode.add_links(-1);
+            dst_dir.inode.add_links(1);
+        }
+        inode.meta.lock().ctime = now();
+        self.inode.meta.lock().touch();
+        dst_dir.inode.meta.lock().touch();
+        Ok(())
+    }
+
+    /// Swaps `src` at `self/src_name` and `dst` at `dst_dir/dst_name`, with
+    /// the namespace lock held.
+    fn exchange(
+        &self,
+        dst_dir: &TmpNode,
+        src_name: &str,
+        src: Arc<Inode>,
+        dst_name: &str,
+        dst: Arc<Inode>,
+    ) -> VfsResult<()> {
+        if Arc::ptr_eq(&src, &dst) {
+            return Ok(());
+        }
+        check_not_within(&src, &dst_dir.inode)?;
+        check_not_within(&dst, &self.inode)?;
+        self.dir()?
+            .lock()
+            .entries
+            .insert(src_name.to_owned(), dst.clone());
+        dst_dir
+            .dir()?
+            .lock()
+            .entries
+            .insert(dst_name.to_owned(), src.clone());
+        if !Arc::ptr_eq(&self.inode, &dst_dir.inode) {
+            // Subdirectories count as links of their parent.
+            for (inode, from, to) in [(&src, self, dst_dir), (&dst, dst_dir, self)] {
+                if inode.is_dir() {
+                    inode.set_parent(&to.inode);
+                    from.inode.add_links(-1);
+                    to.inode.add_links(1);
+                }
+            }
+        }
+        src.meta.lock().ctime = now();
+        dst.meta.lock().ctime = now();
+        self.inode.meta.lock().touch();
+        dst_dir.inode.meta.lock().touch();
+        Ok(())
+    }
+
+    /// Makes `range` of a regular file read as zeros, freeing the pages
+    /// entirely within it.
+    pub(crate) fn clear_range(&self, range: Range<u64>) -> VfsResult<()> {
+        let Content::File(pages) = &self.inode.content else {
+            return Err(VfsError::ENODEV);
+        };
+        let mut meta = self.inode.meta.lock();
+        let range = range.start..range.end.min(meta.size);
+        let mut cache = pages.page_cache.lock();
+        let keys = cache
+            .iter()
+            .map(|(pn, _)| *pn)
+            .filter(|pn| {
+                let start = *pn as u64 * BLOCK_SIZE;
+                start < range.end && range.start < start + BLOCK_SIZE
+            })
+            .collect::<Vec<_>>();
+        for pn in keys {
+            let start = pn as u64 * BLOCK_SIZE;
+            if range.start <= start && start + BLOCK_SIZE <= range.end {
+                remove_page(pages, &mut cache, pn);
+            } else if let Some(page) = cache.peek_mut(&pn) {
+                let from = range.start.max(start) - start;
+                let to = range.end.min(start + BLOCK_SIZE) - start;
+                page.data()[from as usize..to as usize].fill(0);
+            }
+        }
+        meta.touch();
+        Ok(())
+    }
+
+    /// Returns the first offset at or after `offset` that is in a page of
+    /// data if `data` is `true`, or in a hole otherwise.
+    ///
+    /// The end of the file counts as a hole. Fails with `ENXIO` if `offset`
+    /// is beyond the end of the file or no such offset exists.
+    pub(crate) fn seek(&self, offset: u64, data: bool) -> VfsResult<u64> {
+        let Content::File(pages) = &self.inode.content else {
+            return Err(VfsError::ENXIO);
+        };
+        let size = self.inode.meta.lock().size;
+        if offset >= size {
+            return Err(VfsError::ENXIO);
+        }
+        let start_page = (offset / BLOCK_SIZE) as u32;
+        let mut present = pages
+            .page_cache
+            .lock()
+            .iter()
+            .map(|(pn, _)| *pn)
+            .filter(|pn| *pn >= start_page)
+            .collect::<Vec<_>>();
+        present.sort_unstable();
+        let pn = if data {
+            match present.first() {
+                Some(pn) => *pn,
+                None => return Err(VfsError::ENXIO),
+            }
+        } else {
+            let mut pn = start_page;
+            for present in present {
+                if present != pn {
+                    break;
+                }
+                pn += 1;
+            }
+            pn
+        };
+        let pos = (pn as u64 * BLOCK_SIZE).max(offset);
+        if pos < size {
+            Ok(pos)
+        } else if data {
+            Err(VfsError::ENXIO)
+        } else {
+            Ok(size)
+        }
+    }
+
+    fn write_locked(
+        &self,
+        meta: &mut InodeMeta,
+        pages: &CachedFileShared,
+        buf: &[u8],
+        offset: u64,
+    ) -> VfsResult<usize> {
+        let end = offset + buf.len() as u64;
+        if end > meta.size {
+            self.resize(meta, pages, end)?;
+        }
+        let mut cache = pages.page_cache.lock();
+        let mut written = 0;
+        for (pn, range) in page_range(offset, buf.len()) {
+            if !cache.contains(&pn) {
+                let mut page = PageCache::new()?;
+                page.data().fill(0);
+                cache.put(pn, page);
+            }
+            let page = cache.get_mut(&pn).unwrap();
+            let len = range.end - range.start;
+            page.data()[range].copy_from_slice(&buf[written..written + len]);
+            written += len;
+        }
+        meta.touch();
+        Ok(written)
+    }
+}
+
+impl NodeOps for TmpNode {
+    fn inode(&self) -> u64 {
+        self.inode.ino
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        let meta = self.inode.meta.lock();
+        let size = if self.inode.is_dir() {
+            BLOCK_SIZE
+        } else {
+            meta.size
+        };
+        Ok(Metadata {
+            inode: self.inode.ino,
+            device: 0,
+            nlink: meta.nlink as _,
+            mode: meta.mode,
+            node_type: self.inode.node_type,
+            uid: meta.uid,
+            gid: meta.gid,
+            size,
+            block_size: BLOCK_SIZE,
+            blocks: self.inode.charge(size).div_ceil(512),
+            rdev: DeviceId::default(),
+            atime: meta.atime,
+            mtime: meta.mtime,
+            ctime: meta.ctime,
+        })
+    }
+
+    fn update_metadata(&self, update: MetadataUpdate) -> VfsResult<()> {
+        let mut meta = self.inode.meta.lock();
+        if let Some(mode) = update.mode {
+            meta.mode = mode;
+        }
+        if let Some((uid, gid)) = update.owner {
+            meta.uid = uid as _;
+            meta.gid = gid as _;
+        }
+        if let Some(atime) = update.atime {
+            meta.atime = atime;
+        }
+        if let Some(mtime) = update.mtime {
+            meta.mtime = mtime;
+        }
+        meta.ctime = now();
+        Ok(())
+    }
+
+    fn len(&self) -> VfsResult<u64> {
+        Ok(self.inode.meta.lock().size)
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        &*self.fs
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::empty()
+    }
+}
+
+impl FileNodeOps for TmpNode {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let meta = self.inode.meta.lock();
+        let len = (buf.len() as u64).min(meta.size.saturating_sub(offset)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let buf = &mut buf[..len];
+        match &self.inode.content {
+            Content::File(pages) => {
+                let mut cache = pages.page_cache.lock();
+                let mut read = 0;
+                for (pn, range) in page_range(offset, len) {
+                    let dst = &mut buf[read..read + range.end - range.start];
+                    match cache.peek_mut(&pn) {
+                        Some(page) => dst.copy_from_slice(&page.data()[range]),
+                        // Holes read as zeros.
+                        None => dst.fill(0),
+                    }
+                    read += dst.len();
+                }
+            }
+            Content::Symlink(target) => {
+                let offset = offset as usize;
+                buf.copy_from_slice(&target.lock().as_bytes()[offset..offset + len]);
+            }
+            Content::Special => {}
+            Content::Dir(_) => return Err(VfsError::EISDIR),
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        let Content::File(pages) = &self.inode.content else {
+            return Err(VfsError::EINVAL);
+        };
+        let mut meta = self.inode.meta.lock();
+        self.write_locked(&mut meta, pages, buf, offset)
+    }
+
+    fn append(&self, buf: &[u8]) -> VfsResult<(usize, u64)> {
+        let Content::File(pages) = &self.inode.content else {
+            return Err(VfsError::EINVAL);
+        };
+        let mut meta = self.inode.meta.lock();
+        let offset = meta.size;
+        let written = self.write_locked(&mut meta, pages, buf, offset)?;
+        Ok((written, offset + written as u64))
+    }
+
+    fn set_len(&self, len: u64) -> VfsResult<()> {
+        match &self.inode.content {
+            Content::File(pages) => {
+                let mut meta = self.inode.meta.lock();
+                self.resize(&mut meta, pages, len)
+            }
+            // Truncating special files on open is a no-op.
+            Content::Special if len == 0 => Ok(()),
+            _ => Err(VfsError::EINVAL),
+        }
+    }
+
+    fn set_symlink(&self, target: &str) -> VfsResult<()> {
+        let Content::Symlink(old_target) = &self.inode.content else {
+            return Err(VfsError::EINVAL);
+        };
+        let mut meta = self.inode.meta.lock();
+        let old_len = meta.size;
+        let new_len = target.len() as u64;
+        if new_len > old_len {
+            self.inode.usage.reserve(new_len - old_len)?;
+        } else {
+            self.inode.usage.release(old_len - new_len);
+        }
+        *old_target.lock() = target.to_owned();
+        meta.size = new_len;
+        meta.touch();
+        Ok(())
+    }
+}
+
+impl SpaceOps for TmpNode {
+    fn punch_hole(&self, range: Range<u64>) -> VfsResult<()> {
+        self.clear_range(range)
+    }
+
+    fn seek_data(&self, offset: u64, data: bool) -> VfsResult<u64> {
+        self.seek(offset, data)
+    }
+}
+
+impl XattrOps for TmpNode {
+    fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>> {
+        self.inode
+            .xattrs
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or(VfsError::ENODATA)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8], create: bool, replace: bool) -> VfsResult<()> {
+        let mut xattrs = self.inode.xattrs.lock();
+        let old_len = match xattrs.get(name) {
+            Some(_) if create => return Err(VfsError::EEXIST),
+            Some(old) => name.len() + old.len(),
+            None if replace => return Err(VfsError::ENODATA),
+            None => 0,
+        };
+        let new_len = name.len() + value.len();
+        if new_len > old_len {
+            self.inode.usage.reserve((new_len - old_len) as u64)?;
+        } else {
+            self.inode.usage.release((old_len - new_len) as u64);
+        }
+        xattrs.insert(name.to_owned(), value.to_vec());
+        drop(xattrs);
+        self.inode.meta.lock().ctime = now();
+        Ok(())
+    }
+
+    fn list_xattr(&self) -> VfsResult<Vec<String>> {
+        Ok(self.inode.xattrs.lock().keys().cloned().collect())
+    }
+
+    fn remove_xattr(&self, name: &str) -> VfsResult<()> {
+        let value = self
+            .inode
+            .xattrs
+            .lock()
+            .remove(name)
+            .ok_or(VfsError::ENODATA)?;
+        self.inode.usage.release((name.len() + value.len()) as u64);
+        self.inode.meta.lock().ctime = now();
+        Ok(())
+    }
+}
+
+impl Pollable for TmpNode {
+    fn poll(&self) -> IoEvents {
+        IoEvents::IN | IoEvents::OUT
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+impl DirNodeOps for TmpNode {
+    fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        let dir = self.dir()?.lock();
+        let parent_ino = dir.parent.upgrade().map_or(self.inode.ino, |it| it.ino);
+        let entries = [(".", self.inode.ino), ("..", parent_ino)]
+            .into_iter()
+            .map(|(name, ino)| (name, ino, NodeType::Directory))
+            .chain(
+                dir.entries
+                    .iter()
+                    .map(|(name, inode)| (name.as_str(), inode.ino, inode.node_type)),
+            );
+        let mut count = 0;
+        for (i, (name, ino, node_type)) in entries.enumerate().skip(offset as usize) {
+            if !sink.accept(name, ino, node_type, i as u64 + 1) {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        let inode = self
+            .dir()?
+            .lock()
+            .entries
+            .get(name)
+            .cloned()
+            .ok_or(VfsError::ENOENT)?;
+        Ok(self.create_entry(inode, name))
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        permission: NodePermission,
+    ) -> VfsResult<DirEntry> {
+        let _guard = self.fs.namespace_lock.lock();
+        let mut dir = self.dir()?.lock();
+        if dir.entries.contains_key(name) {
+            return Err(VfsError::EEXIST);
+        }
+        let ino = self.fs.alloc_ino();
+        let usage = self.fs.usage().clone();
+        let inode = match node_type {
+            NodeType::RegularFile => Inode::new(
+                ino,
+                node_type,
+                permission,
+                Content::File(Arc::new(CachedFileShared::new_unbounded())),
+                usage,
+            ),
+            NodeType::Directory => Inode::new_dir(ino, permission, Some(&self.inode), usage),
+            NodeType::Symlink => Inode::new(
+                ino,
+                node_type,
+                permission,
+                Content::Symlink(Mutex::new(String::new())),
+                usage,
+            ),
+            NodeType::CharacterDevice
+            | NodeType::BlockDevice
+            | NodeType::Fifo
+            | NodeType::Socket => Inode::new(ino, node_type, permission, Content::Special, usage),
+            NodeType::Unknown => return Err(VfsError::EINVAL),
+        };
+        dir.entries.insert(name.to_owned(), inode.clone());
+        drop(dir);
+
+        if inode.is_dir() {
+            self.inode.add_links(1);
+        }
+        self.inode.meta.lock().touch();
+        invalidate_entry(self.this.as_ref(), name);
+        Ok(self.create_entry(inode, name))
+    }
+
+    fn link(&self, name: &str, node: &DirEntry) -> VfsResult<DirEntry> {
+        // Hard links to directories are not allowed.
+        let node: Arc<TmpNode> = node
+            .as_file()
+            .map_err(|_| VfsError::EPERM)?
+            .downcast()
+            .map_err(|_| VfsError::EXDEV)?;
+        if !Arc::ptr_eq(&node.fs, &self.fs) {
+            return Err(VfsError::EXDEV);
+        }
+
+        let _guard = self.fs.namespace_lock.lock();
+        let mut dir = self.dir()?.lock();
+        if dir.entries.contains_key(name) {
+            return Err(VfsError::EEXIST);
+        }
+        dir.entries.insert(name.to_owned(), node.inode.clone());
+        drop(dir);
+
+        node.inode.add_links(1);
+        self.inode.meta.lock().touch();
+        invalidate_entry(self.this.as_ref(), name);
+        Ok(self.create_entry(node.inode.clone(), name))
+    }
+
+    fn unlink(&self, name: &str) -> VfsResult<()> {
+        let _guard = self.fs.namespace_lock.lock();
+        let mut dir = self.dir()?.lock();
+        let inode = dir.entries.get(name).ok_or(VfsError::ENOENT)?;
+        if inode.is_dir() && !inode.is_empty_dir() {
+            return Err(VfsError::ENOTEMPTY);
+        }
+        let inode = dir.entries.remove(name).unwrap();
+        drop(dir);
+
+        if inode.is_dir() {
+            inode.add_links(-2);
+            self.inode.add_links(-1);
+        } else {
+            inode.add_links(-1);
+        }
+        self.inode.meta.lock().touch();
+        invalidate_entry(self.this.as_ref(), name);
+        Ok(())
+    }
+
+    fn rename(&self, src_name: &str, dst_dir: &DirNode, dst_name: &str) -> VfsResult<()> {
+        let dst_dir: Arc<Self> = dst_dir.downcast().map_err(|_| VfsError::EXDEV)?;
+        if !Arc::ptr_eq(&dst_dir.fs, &self.fs) {
+            return Err(VfsError::EXDEV);
+        }
+
+        self.move_entry(src_name, &dst_dir, dst_name)?;
+        invalidate_entry(self.this.as_ref(), src_name);
+        invalidate_entry(dst_dir.this.as_ref(), dst_name);
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+mod fs;
+mod inode;
+
+use alloc::sync::Arc;
+
+use axfs_ng_vfs::Location;
+pub use fs::*;
+pub use inode::*;
+
+use crate::highlevel::CachedFileShared;
+
+/// Returns the pages holding the contents of a tmpfs regular file.
+pub(crate) fn file_pages(location: &Location) -> Option<Arc<CachedFileShared>> {
+    let node: Arc<TmpNode> = location.entry().as_file().ok()?.downcast().ok()?;
+    node.pages()
+}
@@ -0,0 +1,265 @@
+//! Cache of directory lookups made during path resolution.
+//!
+//! Both the names found (positive entries) and the names missing (negative
+//! entries) are cached, so that repeated lookups of either do not reach the
+//! filesystem. The node operations of filesystems invalidate the entries of
+//! the names they change, so that changes made through [`Location`] directly
+//! are seen too. The operations of [`FsContext`] invalidate them again once
+//! the VFS has updated its own cache, which a lookup racing with the change
+//! may otherwise still find.
+//!
+//! Directories whose contents are generated, marked
+//! [`NodeFlags::NON_CACHEABLE`], are never cached. Names in directories of
+//! case-insensitive filesystems are cached by their lowercase form, so that
+//! a change to one spelling of a name invalidates all of them.
+//!
+//! [`FsContext`]: super::FsContext
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axfs_ng_vfs::{Location, NodeFlags, VfsError, VfsResult, WeakDirEntry};
+use lru::LruCache;
+use spin::{Lazy, Mutex};
+
+/// Default maximum number of cached entries.
+const DEFAULT_LIMIT: usize = 4096;
+
+/// Filesystems looking names up regardless of case.
+const CASE_INSENSITIVE: &[&str] = &["vfat"];
+
+/// Identifies a directory in the cache, kept in the user data of its
+/// location.
+///
+/// Identifiers are never reused, so the entries of a dropped directory can
+/// never be mistaken for those of another one.
+struct DirId {
+    id: u64,
+    fold_case: bool,
+}
+
+impl DirId {
+    fn new(dir: &Location) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            fold_case: CASE_INSENSITIVE.contains(&dir.filesystem().name()),
+        }
+    }
+
+    /// Returns the key `name` is cached under in this directory.
+    fn key(&self, name: &str) -> (u64, String) {
+        let name = if self.fold_case {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        };
+        (self.id, name)
+    }
+}
+
+/// Statistics of the dentry cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DentryCacheStats {
+    /// The number of cached entries.
+    pub entries: usize,
+    /// Lookups answered by a positive entry.
+    pub hits: u64,
+    /// Lookups answered by a negative entry.
+    pub negative_hits: u64,
+    /// Lookups that went to the filesystem.
+    pub misses: u64,
+    /// Entries dropped to stay within the size limit.
+    pub evictions: u64,
+}
+
+/// The cached result of looking up a name in a directory.
+enum Entry {
+    Missing,
+    /// The locations found, by mountpoint of the directory, as bind mounts
+    /// share directory entries but not mountpoints, and a location carries
+    /// the restrictions of its mount.
+    Found(Vec<(usize, Location)>),
+}
+
+impl Entry {
+    fn get(&self, mount: usize) -> Option<VfsResult<Location>> {
+        match self {
+            Self::Missing => Some(Err(VfsError::ENOENT)),
+            Self::Found(found) => found
+                .iter()
+                .find(|(it, _)| *it == mount)
+                .map(|(_, loc)| Ok(loc.clone())),
+        }
+    }
+}
+
+struct DentryCache {
+    entries: LruCache<(u64, String), Entry>,
+    limit: usize,
+    /// Incremented on every invalidation, so that a lookup racing with a
+    /// change does not cache what it found before the change.
+    generation: u64,
+    stats: DentryCacheStats,
+}
+
+impl DentryCache {
+    /// Evicts entries until at most `limit` are left, returning them so that
+    /// the locations are dropped after the cache is unlocked.
+    fn shrink(&mut self, limit: usize) -> Vec<Entry> {
+        let mut evicted = Vec::new();
+        while self.entries.len() > limit {
+            if let Some((_, entry)) = self.entries.pop_lru() {
+                evicted.push(entry);
+                self.stats.evictions += 1;
+            }
+        }
+        evicted
+    }
+}
+
+static DCACHE: Lazy<Mutex<DentryCache>> = Lazy::new(|| {
+    Mutex::new(DentryCache {
+        entries: LruCache::unbounded(),
+        limit: DEFAULT_LIMIT,
+        generation: 0,
+        stats: DentryCacheStats::default(),
+    })
+});
+
+/// Returns the key `name` is cached under in `dir`.
+fn key(dir: &Location, name: &str) -> (u64, String) {
+    dir.user_data()
+        .get_or_insert_with(|| DirId::new(dir))
+        .key(name)
+}
+
+/// Identifies the mountpoint of `dir`.
+///
+/// Mountpoints are only dropped once unmounted, which clears the cache, so
+/// the address of a dropped one cannot be mistaken for that of another one.
+fn mount_id(dir: &Location) -> usize {
+    Arc::as_ptr(dir.mountpoint()) as usize
+}
+
+/// Looks up `name` in `dir` through the cache.
+pub(crate) fn lookup(dir: &Location, name: &str) -> VfsResult<Location> {
+    if dir.flags().contains(NodeFlags::NON_CACHEABLE) {
+        return dir.lookup_no_follow(name);
+    }
+
+    let key = key(dir, name);
+    let mount = mount_id(dir);
+    let generation = {
+        let mut cache = DCACHE.lock();
+        if let Some(result) = cache.entries.get(&key).and_then(|entry| entry.get(mount)) {
+            if result.is_ok() {
+                cache.stats.hits += 1;
+            } else {
+                cache.stats.negative_hits += 1;
+            }
+            return result;
+        }
+        cache.stats.misses += 1;
+        cache.generation
+    };
+
+    let result = dir.lookup_no_follow(name);
+    let found = match &result {
+        Ok(loc) => Some((mount, loc.clone())),
+        Err(VfsError::ENOENT) => None,
+        Err(_) => return result,
+    };
+    let mut cache = DCACHE.lock();
+    if cache.limit > 0 && cache.generation == generation {
+        // Other mounts of the directory may have found the name already.
+        let replaced = match found {
+            Some(found) => match cache.entries.get_mut(&key) {
+                Some(Entry::Found(others)) => {
+                    others.push(found);
+                    None
+                }
+                _ => cache.entries.put(key, Entry::Found(vec![found])),
+            },
+            None => cache.entries.put(key, Entry::Missing),
+        };
+        let limit = cache.limit;
+        let evicted = cache.shrink(limit);
+        drop(cache);
+        drop((replaced, evicted));
+    }
+    result
+}
+
+/// Drops the entries of `name` in the directory identified by `id`.
+fn invalidate_id(id: Option<&DirId>, name: &str) {
+    let Some(id) = id else {
+        return;
+    };
+    let key = id.key(name);
+    let mut cache = DCACHE.lock();
+    cache.generation += 1;
+    let entry = cache.entries.pop(&key);
+    drop(cache);
+    drop(entry);
+}
+
+/// Drops the entries of `name` in `dir`, which has just changed.
+pub(crate) fn invalidate(dir: &Location, name: &str) {
+    let id = dir.user_data().get::<DirId>();
+    invalidate_id(id.as_deref(), name);
+}
+
+/// Drops the entries of `name` in the directory `dir` in all its mounts, for
+/// filesystems to call from their node operations once `name` has changed.
+pub(crate) fn invalidate_entry(dir: Option<&WeakDirEntry>, name: &str) {
+    let Some(dir) = dir.and_then(WeakDirEntry::upgrade) else {
+        return;
+    };
+    let id = dir.user_data().get::<DirId>();
+    invalidate_id(id.as_deref(), name);
+}
+
+/// Drops all the cached entries.
+///
+/// This releases the locations held by positive entries, and is needed when
+/// mounts change, as lookups may then find a different location.
+pub fn clear_dentry_cache() {
+    let mut cache = DCACHE.lock();
+    cache.generation += 1;
+    let entries = mem::replace(&mut cache.entries, LruCache::unbounded());
+    drop(cache);
+    drop(entries);
+}
+
+/// Returns the maximum number of cached entries.
+pub fn dentry_cache_limit() -> usize {
+    DCACHE.lock().limit
+}
+
+/// Sets the maximum number of cached entries, evicting entries if the cache
+/// holds more than that. With `0`, nothing is cached.
+pub fn set_dentry_cache_limit(limit: usize) {
+    let mut cache = DCACHE.lock();
+    cache.limit = limit;
+    let evicted = cache.shrink(limit);
+    drop(cache);
+    drop(evicted);
+}
+
+/// Returns the statistics of the dentry cache.
+pub fn dentry_cache_stats() -> DentryCacheStats {
+    let cache = DCACHE.lock();
+    DentryCacheStats {
+        entries: cache.entries.len(),
+        ..cache.stats
+    }
+}
@@ -1,25 +1,36 @@
 use alloc::{
     boxed::Box,
+    collections::BTreeMap,
     sync::{Arc, Weak},
     vec::Vec,
 };
 #[cfg(feature = "times")]
-use core::sync::atomic::{AtomicU8, Ordering};
-use core::{num::NonZeroUsize, ops::Range, task::Context};
+use core::sync::atomic::AtomicU8;
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    task::Context,
+};
 
 use allocator::AllocError;
 use axalloc::{UsageKind, global_allocator};
 use axfs_ng_vfs::{
     FileNode, Location, NodeFlags, NodePermission, NodeType, VfsError, VfsResult, path::Path,
 };
-use axhal::mem::{PhysAddr, VirtAddr, virt_to_phys};
+use axhal::{
+    mem::{PhysAddr, VirtAddr, virt_to_phys},
+    time::{TimeValue, monotonic_time},
+};
 use axio::{Buf, BufMut, IoEvents, Pollable, SeekFrom};
 use intrusive_collections::{LinkedList, LinkedListAtomicLink, intrusive_adapter};
 use log::warn;
 use lru::LruCache;
-use spin::{Mutex, RwLock};
+use spin::{Lazy, Mutex, RwLock};
 
-use super::FsContext;
+use super::{
+    AccessMode, DIRTY_PAGES, FallocateFlags, FileLocks, FsContext, LockType, MountRef, PipeEnd,
+    RangeLock, WatchMask, balance_dirty_pages, check_writable, dcache, space, watch,
+};
 
 bitflags::bitflags! {
     #[derive(Debug, Clone, Copy)]
@@ -74,6 +85,7 @@ pub struct OpenOptions {
     directory: bool,
     no_follow: bool,
     direct: bool,
+    nonblocking: bool,
     user: Option<(u32, u32)>,
     path: bool,
     node_type: NodeType,
@@ -95,6 +107,7 @@ impl OpenOptions {
             directory: false,
             no_follow: false,
             direct: false,
+            nonblocking: false,
             user: None,
             path: false,
             node_type: NodeType::RegularFile,
@@ -157,7 +170,15 @@ impl OpenOptions {
         self
     }
 
-    /// Sets the user and group id to open the file with.
+    /// Sets the option to not wait for the other end when opening a FIFO,
+    /// and to not block on reads and writes of the FIFO.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Sets the user and group id to create the file with, instead of the
+    /// credentials of the [`FsContext`].
     pub fn user(&mut self, uid: u32, gid: u32) -> &mut Self {
         self.user = Some((uid, gid));
         self
@@ -192,12 +213,23 @@ impl OpenOptions {
             }
             loc.check_is_dir()?;
         }
+        if flags.contains(FileFlags::WRITE) || self.truncate {
+            check_writable(&loc)?;
+        }
         if self.truncate {
             loc.entry().as_file()?.set_len(0)?;
         }
 
         Ok(if loc.is_dir() {
             OpenResult::Dir(loc)
+        } else if !self.path && loc.metadata()?.node_type == NodeType::Fifo {
+            let end = PipeEnd::open_fifo(
+                &loc,
+                flags.contains(FileFlags::READ),
+                flags.contains(FileFlags::WRITE),
+                self.nonblocking,
+            )?;
+            OpenResult::File(File::new(FileBackend::Fifo(loc, end), flags))
         } else {
             // TODO(mivik): is this correct?
             let non_cacheable_type = matches!(
@@ -230,31 +262,67 @@ impl OpenOptions {
             return Err(VfsError::EINVAL);
         }
 
+        let mut created = false;
         let loc = match context.resolve_parent(path.as_ref()) {
             Ok((parent, name)) => {
-                let mut loc = parent.open_file(
+                context.check_access(&parent, AccessMode::EXECUTE)?;
+                // Existing files can still be opened on a read-only mount.
+                let read_only =
+                    (self.create || self.create_new) && check_writable(&parent).is_err();
+                created = (self.create || self.create_new)
+                    && !read_only
+                    && parent.lookup_no_follow(&name).is_err();
+                if created {
+                    context.check_access(&parent, AccessMode::WRITE)?;
+                }
+                let cred = context.credentials();
+                let result = parent.open_file(
                     &name,
                     &axfs_ng_vfs::OpenOptions {
-                        create: self.create,
-                        create_new: self.create_new,
+                        create: self.create && !read_only,
+                        create_new: self.create_new && !read_only,
                         node_type: self.node_type,
                         permission: NodePermission::from_bits_truncate(self.mode as _),
-                        user: self.user,
+                        user: self
+                            .user
+                            .or_else(|| (!cred.is_root()).then_some((cred.uid, cred.gid))),
                     },
-                )?;
+                );
+                let mut loc = match result {
+                    Ok(_) if read_only && self.create_new => return Err(VfsError::EEXIST),
+                    Err(VfsError::ENOENT) if read_only => return Err(VfsError::EROFS),
+                    result => result?,
+                };
+                if created {
+                    dcache::invalidate(&parent, &name);
+                }
+                if created && watch::is_watched(&parent) {
+                    watch::notify_create(&loc);
+                }
                 if !self.no_follow {
                     loc = context
-                        .with_current_dir(parent)?
+                        .with_current_dir(parent.clone())?
                         .try_resolve_symlink(loc, &mut 0)?;
                 }
+                context.check_xdev(&parent, &loc)?;
                 loc
             }
             Err(VfsError::EINVAL) => {
-                // root directory
-                context.root_dir().clone()
+                // A directory without a parent, e.g. the root directory.
+                context.resolve(path.as_ref())?
             }
             Err(err) => return Err(err),
         };
+        if !created && !self.path {
+            let mut mode = AccessMode::empty();
+            if self.read {
+                mode |= AccessMode::READ;
+            }
+            if self.write || self.append || self.truncate {
+                mode |= AccessMode::WRITE;
+            }
+            context.check_access(&loc, mode)?;
+        }
         self._open(loc)
     }
 
@@ -305,11 +373,13 @@ const PAGE_SIZE: usize = 4096;
 #[derive(Debug)]
 pub struct PageCache {
     addr: VirtAddr,
-    dirty: bool,
+    /// The time the page was first modified since it was last written back,
+    /// or `None` if it is clean.
+    dirtied_at: Option<TimeValue>,
 }
 
 impl PageCache {
-    fn new() -> VfsResult<Self> {
+    pub(crate) fn new() -> VfsResult<Self> {
         let addr = global_allocator()
             .alloc_pages(1, PAGE_SIZE, UsageKind::PageCache)
             .map_err(|err| {
@@ -321,7 +391,7 @@ impl PageCache {
             })?;
         Ok(Self {
             addr: addr.into(),
-            dirty: false,
+            dirtied_at: None,
         })
     }
 
@@ -329,8 +399,27 @@ impl PageCache {
         virt_to_phys(self.addr)
     }
 
+    pub fn is_dirty(&self) -> bool {
+        self.dirtied_at.is_some()
+    }
+
+    /// Returns the time the page was first modified since it was last
+    /// written back.
+    pub fn dirtied_at(&self) -> Option<TimeValue> {
+        self.dirtied_at
+    }
+
     pub fn mark_dirty(&mut self) {
-        self.dirty = true;
+        if self.dirtied_at.is_none() {
+            self.dirtied_at = Some(monotonic_time());
+            DIRTY_PAGES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn mark_clean(&mut self) {
+        if self.dirtied_at.take().is_some() {
+            DIRTY_PAGES.fetch_sub(1, Ordering::Relaxed);
+        }
     }
 
     pub fn data(&mut self) -> &mut [u8] {
@@ -340,41 +429,388 @@ impl PageCache {
 
 impl Drop for PageCache {
     fn drop(&mut self) {
-        if self.dirty {
+        if self.is_dirty() {
             warn!("dirty page dropped without flushing");
+            self.mark_clean();
         }
         global_allocator().dealloc_pages(self.addr.as_usize(), 1, UsageKind::PageCache);
     }
 }
 
-struct EvictListener {
-    listener: Box<dyn Fn(u32, &PageCache) + Send + Sync>,
+/// A change the page cache asks the mappings of a page to make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageAction {
+    /// The page is about to be removed from the cache, so it must be
+    /// unmapped.
+    Unmap,
+    /// The page is about to be written back, so it must be mapped read-only
+    /// for the next write through the mapping to mark it dirty again.
+    WriteProtect,
+}
+
+struct MappingListener {
+    /// The address space whose mappings the listener updates.
+    aspace: usize,
+    listener: Box<dyn Fn(u32, PageAction) -> bool + Send + Sync>,
     link: LinkedListAtomicLink,
 }
 
-intrusive_adapter!(EvictListenerAdapter = Box<EvictListener>: EvictListener { link: LinkedListAtomicLink });
+intrusive_adapter!(MappingListenerAdapter = Box<MappingListener>: MappingListener { link: LinkedListAtomicLink });
 
-struct CachedFileShared {
-    page_cache: Mutex<LruCache<u32, PageCache>>,
-    evict_listeners: Mutex<LinkedList<EvictListenerAdapter>>,
+/// Gives the tasks holding the address spaces locked by others a chance to
+/// release them.
+fn wait_for_mappings() {
+    #[cfg(feature = "multitask")]
+    axtask::yield_now();
+    #[cfg(not(feature = "multitask"))]
+    core::hint::spin_loop();
+}
+
+static NEXT_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) struct CachedFileShared {
+    /// Identifies the file in the global LRU, unlike its address, which may
+    /// be reused once it is dropped.
+    id: u64,
+    pub(crate) page_cache: Mutex<LruCache<u32, PageCache>>,
+    listeners: Mutex<LinkedList<MappingListenerAdapter>>,
+    /// The file whose pages are cached, or `None` for in-memory files, whose
+    /// pages are not subject to the global page cache limit.
+    location: Option<Location>,
+    this: Weak<CachedFileShared>,
 }
 
 impl CachedFileShared {
-    pub fn new() -> Self {
-        Self {
-            page_cache: Mutex::new(LruCache::new(NonZeroUsize::new(64).unwrap())),
-            evict_listeners: Mutex::new(LinkedList::default()),
-        }
+    pub fn new(location: Location) -> Arc<Self> {
+        let id = NEXT_FILE_ID.fetch_add(1, Ordering::Relaxed);
+        Arc::new_cyclic(|this| {
+            CACHED_FILES.lock().insert(id, this.clone());
+            Self {
+                id,
+                page_cache: Mutex::new(LruCache::unbounded()),
+                listeners: Mutex::new(LinkedList::default()),
+                location: Some(location),
+                this: this.clone(),
+            }
+        })
     }
 
     pub fn new_unbounded() -> Self {
         Self {
+            id: NEXT_FILE_ID.fetch_add(1, Ordering::Relaxed),
             page_cache: Mutex::new(LruCache::unbounded()),
-            evict_listeners: Mutex::new(LinkedList::default()),
+            listeners: Mutex::new(LinkedList::default()),
+            location: None,
+            this: Weak::new(),
+        }
+    }
+
+    /// Marks a page as the most recently used one in the global LRU.
+    fn touch(&self, pn: u32) {
+        if self.location.is_some() {
+            PAGE_CACHE_LRU.lock().put((self.id, pn), self.this.clone());
+        }
+    }
+
+    /// Removes a page from the global LRU.
+    fn forget(&self, pn: u32) {
+        if self.location.is_some() {
+            PAGE_CACHE_LRU.lock().pop(&(self.id, pn));
+        }
+    }
+
+    /// Returns whether the file is mapped in the address space `aspace`.
+    fn is_mapped_in(&self, aspace: usize) -> bool {
+        self.listeners
+            .lock()
+            .iter()
+            .any(|listener| listener.aspace == aspace)
+    }
+
+    /// Asks the mappings of page `pn` to apply `action`, except the ones in
+    /// the address space `held`, which the caller has locked and updates
+    /// itself.
+    ///
+    /// Returns `false` if an address space is locked elsewhere, in which case
+    /// its mappings did not apply the action.
+    pub(crate) fn notify(&self, pn: u32, action: PageAction, held: Option<usize>) -> bool {
+        let mut done = true;
+        for listener in self.listeners.lock().iter() {
+            if Some(listener.aspace) != held {
+                done &= (listener.listener)(pn, action);
+            }
+        }
+        done
+    }
+
+    fn write_back(&self, file: &FileNode, pn: u32, page: &mut PageCache) -> VfsResult<()> {
+        if page.is_dirty() {
+            let page_start = pn as u64 * PAGE_SIZE as u64;
+            // The page is beyond the end of the file if it was truncated.
+            let len = file.len()?.saturating_sub(page_start).min(PAGE_SIZE as u64) as usize;
+            if len > 0 {
+                file.write_at(&page.data()[..len], page_start)?;
+            }
+            page.mark_clean();
+        }
+        Ok(())
+    }
+
+    /// Writes back the dirty pages for which `filter` returns `true`, keeping
+    /// them in the cache.
+    ///
+    /// The pages are write-protected in their mappings first, so that later
+    /// writes through them mark the pages dirty again. Pages mapped in an
+    /// address space locked elsewhere are left dirty.
+    pub(crate) fn write_back_dirty(
+        &self,
+        mut filter: impl FnMut(&PageCache) -> bool,
+    ) -> VfsResult<()> {
+        let Some(location) = &self.location else {
+            return Ok(());
+        };
+        let file = location.entry().as_file()?;
+        let mut cache = self.page_cache.lock();
+        for (pn, page) in cache.iter_mut() {
+            if page.is_dirty() && filter(page) && self.notify(*pn, PageAction::WriteProtect, None) {
+                self.write_back(file, *pn, page)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the pages for which `select` returns `true` from the cache
+    /// once they are unmapped everywhere, calling `f` with each of them.
+    ///
+    /// Waits for the address spaces locked elsewhere to be released, so the
+    /// caller must not hold one mapping the file.
+    pub(crate) fn remove_pages(
+        &self,
+        mut select: impl FnMut(u32) -> bool,
+        mut f: impl FnMut(u32, PageCache) -> VfsResult<()>,
+    ) -> VfsResult<()> {
+        loop {
+            let mut guard = self.page_cache.lock();
+            let keys = guard
+                .iter()
+                .map(|(pn, _)| *pn)
+                .filter(|pn| select(*pn))
+                .collect::<Vec<_>>();
+            let mut busy = false;
+            for pn in keys {
+                if !self.notify(pn, PageAction::Unmap, None) {
+                    busy = true;
+                    continue;
+                }
+                let page = guard.pop(&pn).unwrap();
+                self.forget(pn);
+                f(pn, page)?;
+            }
+            if !busy {
+                return Ok(());
+            }
+            drop(guard);
+            wait_for_mappings();
         }
     }
 }
 
+impl Drop for CachedFileShared {
+    fn drop(&mut self) {
+        if self.location.is_some() {
+            CACHED_FILES.lock().remove(&self.id);
+            let mut lru = PAGE_CACHE_LRU.lock();
+            for (pn, _) in self.page_cache.get_mut().iter() {
+                lru.pop(&(self.id, *pn));
+            }
+        }
+    }
+}
+
+/// A page evicted from the page cache while the caller of
+/// [`CachedFile::with_page_or_insert`] has the address space it may be
+/// mapped in locked.
+///
+/// The caller must unmap it from that address space before dropping it,
+/// which frees the page. Evicted pages are always clean.
+pub struct EvictedPage {
+    file: Arc<CachedFileShared>,
+    pn: u32,
+    _page: PageCache,
+}
+
+impl EvictedPage {
+    /// Returns the number of the page within its file.
+    pub fn pn(&self) -> u32 {
+        self.pn
+    }
+
+    /// Returns whether the page belongs to `file`.
+    pub fn is_of(&self, file: &CachedFile) -> bool {
+        Arc::ptr_eq(&self.file, &file.shared)
+    }
+}
+
+/// The limit of the page cache in pages, `usize::MAX` if it is only limited
+/// by memory pressure.
+static PAGE_CACHE_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Pages are evicted when fewer pages than this are free in the global
+/// allocator.
+const MIN_FREE_PAGES: usize = 256;
+
+/// The cached pages of all files that are not in memory, in LRU order.
+static PAGE_CACHE_LRU: Lazy<Mutex<LruCache<(u64, u32), Weak<CachedFileShared>>>> =
+    Lazy::new(|| Mutex::new(LruCache::unbounded()));
+
+/// The files whose pages are subject to the page cache limit, by id.
+static CACHED_FILES: Mutex<BTreeMap<u64, Weak<CachedFileShared>>> = Mutex::new(BTreeMap::new());
+
+/// Returns the cached files, excluding in-memory files, oldest first.
+pub(crate) fn cached_files() -> Vec<Arc<CachedFileShared>> {
+    CACHED_FILES
+        .lock()
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+/// Returns the limit of the page cache in bytes, or `None` if it is only
+/// limited by memory pressure.
+pub fn page_cache_limit() -> Option<usize> {
+    match PAGE_CACHE_LIMIT.load(Ordering::Relaxed) {
+        usize::MAX => None,
+        pages => Some(pages * PAGE_SIZE),
+    }
+}
+
+/// Sets the limit of the page cache in bytes, evicting pages if the cache
+/// holds more than that.
+///
+/// With `None`, pages are only evicted under memory pressure. Pages of
+/// in-memory files (e.g. on tmpfs) are never evicted.
+pub fn set_page_cache_limit(limit: Option<usize>) -> VfsResult<()> {
+    let pages = limit.map_or(usize::MAX, |limit| limit / PAGE_SIZE);
+    PAGE_CACHE_LIMIT.store(pages, Ordering::Relaxed);
+    shrink_page_cache(None, 0, None).map(drop)
+}
+
+/// Evicts pages across all files until `incoming` more pages fit in the page
+/// cache.
+///
+/// `current` is a file whose cache is locked by the caller. Pages whose
+/// mappings cannot be unmapped right now are skipped, and so are files whose
+/// cache is locked elsewhere.
+///
+/// The mappings in the address space `held`, which the caller has locked,
+/// are left to the caller: the clean pages of files mapped there are
+/// returned for it to unmap, while their dirty pages are skipped, as they
+/// could still be written through the mappings. Other evicted pages are
+/// dropped once written back.
+fn shrink_page_cache(
+    mut current: Option<(&CachedFileShared, &mut LruCache<u32, PageCache>)>,
+    incoming: usize,
+    held: Option<usize>,
+) -> VfsResult<Vec<EvictedPage>> {
+    let mut evicted = Vec::new();
+    let mut skipped = Vec::new();
+    let result = loop {
+        let (key, shared) = {
+            let mut lru = PAGE_CACHE_LRU.lock();
+            let limit = PAGE_CACHE_LIMIT.load(Ordering::Relaxed);
+            let pressure = global_allocator().available_pages() < MIN_FREE_PAGES + incoming;
+            if lru.len() + incoming <= limit && !pressure {
+                break Ok(());
+            }
+            match lru.pop_lru() {
+                Some(victim) => victim,
+                None => break Ok(()),
+            }
+        };
+        let Some(shared) = shared.upgrade() else {
+            continue;
+        };
+        let file = match shared.location.as_ref().map(|loc| loc.entry().as_file()) {
+            Some(Ok(file)) => file,
+            Some(Err(err)) => break Err(err),
+            None => continue,
+        };
+        let mut guard;
+        let cache = match current.as_mut() {
+            Some((current, cache)) if core::ptr::eq(*current, &*shared) => &mut **cache,
+            _ => match shared.page_cache.try_lock() {
+                Some(locked) => {
+                    guard = locked;
+                    &mut *guard
+                }
+                None => {
+                    skipped.push((key, shared.this.clone()));
+                    continue;
+                }
+            },
+        };
+        let pn = key.1;
+        let Some(page) = cache.peek(&pn) else {
+            continue;
+        };
+        let deferred = held.is_some_and(|held| shared.is_mapped_in(held));
+        if (deferred && page.is_dirty()) || !shared.notify(pn, PageAction::Unmap, held) {
+            skipped.push((key, shared.this.clone()));
+            continue;
+        }
+        let mut page = cache.pop(&pn).unwrap();
+        if let Err(err) = shared.write_back(file, pn, &mut page) {
+            cache.put(pn, page);
+            skipped.push((key, shared.this.clone()));
+            break Err(err);
+        }
+        if deferred {
+            evicted.push(EvictedPage {
+                file: shared.clone(),
+                pn,
+                _page: page,
+            });
+        }
+    };
+    let mut lru = PAGE_CACHE_LRU.lock();
+    for (key, shared) in skipped {
+        lru.put(key, shared);
+    }
+    result.map(|_| evicted)
+}
+
+/// Number of pages read ahead on the first sequential read.
+const READAHEAD_MIN_PAGES: u32 = 4;
+
+/// Maximum number of pages read ahead at once.
+const READAHEAD_MAX_PAGES: u32 = 32;
+
+/// Readahead state of an open file.
+#[derive(Default)]
+struct Readahead {
+    /// The offset a sequential read is expected to start at.
+    next_offset: u64,
+    /// The number of pages read ahead of the last read, `0` if the reads are
+    /// not sequential.
+    window: u32,
+}
+
+impl Readahead {
+    /// Records a read of `range` and returns the page range that should be
+    /// cached for it, including the pages to read ahead.
+    fn advance(&mut self, range: Range<u64>) -> Range<u32> {
+        if range.start == self.next_offset {
+            self.window = (self.window * 2).clamp(READAHEAD_MIN_PAGES, READAHEAD_MAX_PAGES);
+        } else {
+            self.window = 0;
+        }
+        self.next_offset = range.end;
+        let start_page = (range.start / PAGE_SIZE as u64) as u32;
+        let end_page = range.end.div_ceil(PAGE_SIZE as u64) as u32;
+        start_page..end_page.saturating_add(self.window)
+    }
+}
+
 pub struct CachedFile {
     inner: Location,
     shared: Arc<CachedFileShared>,
@@ -382,6 +818,9 @@ pub struct CachedFile {
     /// Only one thread can append to the file at a time, while multiple writers
     /// are permitted.
     append_lock: RwLock<()>,
+    readahead: Mutex<Readahead>,
+    /// Keeps the mount of the file busy, also while it is only mapped.
+    _mount_ref: MountRef,
 }
 
 impl Clone for CachedFile {
@@ -391,6 +830,8 @@ impl Clone for CachedFile {
             shared: self.shared.clone(),
             in_memory: self.in_memory,
             append_lock: RwLock::new(()),
+            readahead: Mutex::default(),
+            _mount_ref: self._mount_ref.clone(),
         }
     }
 }
@@ -418,10 +859,13 @@ impl CachedFile {
             shared
         } else {
             let (shared, user_data) = if in_memory {
-                let shared = Arc::new(CachedFileShared::new_unbounded());
+                // The pages of a tmpfs file are its storage, so they are kept
+                // by the file itself rather than per location.
+                let shared = crate::fs::tmpfs::file_pages(&location)
+                    .unwrap_or_else(|| Arc::new(CachedFileShared::new_unbounded()));
                 (shared.clone(), FileUserData::Strong(shared))
             } else {
-                let shared = Arc::new(CachedFileShared::new());
+                let shared = CachedFileShared::new(location.clone());
                 let user_data = FileUserData::Weak(Arc::downgrade(&shared));
                 (shared, user_data)
             };
@@ -431,10 +875,12 @@ impl CachedFile {
         drop(guard);
 
         Self {
+            _mount_ref: MountRef::new(&location),
             inner: location,
             shared,
             in_memory,
             append_lock: RwLock::new(()),
+            readahead: Mutex::default(),
         }
     }
 
@@ -446,57 +892,52 @@ impl CachedFile {
         self.in_memory
     }
 
-    pub fn add_evict_listener<F>(&self, listener: F) -> usize
+    /// Registers a listener that applies [`PageAction`]s to the mappings of
+    /// the pages of this file in the address space identified by `aspace`.
+    ///
+    /// The listener returns `false` if it cannot apply the action because
+    /// the address space is locked. Returns a handle for
+    /// [`remove_mapping_listener`].
+    ///
+    /// [`remove_mapping_listener`]: CachedFile::remove_mapping_listener
+    pub fn add_mapping_listener<F>(&self, aspace: usize, listener: F) -> usize
     where
-        F: Fn(u32, &PageCache) + Send + Sync + 'static,
+        F: Fn(u32, PageAction) -> bool + Send + Sync + 'static,
     {
-        let pointer = Box::new(EvictListener {
+        let pointer = Box::new(MappingListener {
+            aspace,
             listener: Box::new(listener),
             link: LinkedListAtomicLink::new(),
         });
-        let handle = pointer.as_ref() as *const EvictListener as usize;
-        self.shared.evict_listeners.lock().push_back(pointer);
+        let handle = pointer.as_ref() as *const MappingListener as usize;
+        self.shared.listeners.lock().push_back(pointer);
         handle
     }
 
-    pub unsafe fn remove_evict_listener(&self, handle: usize) {
-        let mut guard = self.shared.evict_listeners.lock();
-        let mut cursor = unsafe { guard.cursor_mut_from_ptr(handle as *const EvictListener) };
+    pub unsafe fn remove_mapping_listener(&self, handle: usize) {
+        let mut guard = self.shared.listeners.lock();
+        let mut cursor = unsafe { guard.cursor_mut_from_ptr(handle as *const MappingListener) };
         cursor.remove();
     }
 
-    fn evict_cache(&self, file: &FileNode, pn: u32, page: &mut PageCache) -> VfsResult<()> {
-        for listener in self.shared.evict_listeners.lock().iter() {
-            (listener.listener)(pn, &page);
-        }
-        if page.dirty {
-            let page_start = pn as u64 * PAGE_SIZE as u64;
-            let len = (file.len()? - page_start).min(PAGE_SIZE as u64) as usize;
-            file.write_at(&page.data()[..len], page_start)?;
-            page.dirty = false;
-        }
-        Ok(())
-    }
-
     fn page_or_insert<'a>(
         &self,
         file: &FileNode,
         cache: &'a mut LruCache<u32, PageCache>,
         pn: u32,
-    ) -> VfsResult<(&'a mut PageCache, Option<(u32, PageCache)>)> {
+        held: Option<usize>,
+    ) -> VfsResult<(&'a mut PageCache, Vec<EvictedPage>)> {
         // TODO: Matching the result of `get_mut` confuses compiler. See
         // https://users.rust-lang.org/t/return-do-not-release-mutable-borrow/55757.
         if cache.contains(&pn) {
-            return Ok((cache.get_mut(&pn).unwrap(), None));
-        }
-        let mut evicted = None;
-        if cache.len() == cache.cap().get() {
-            // Cache is full, remove the least recently used page
-            if let Some((pn, mut page)) = cache.pop_lru() {
-                self.evict_cache(file, pn, &mut page)?;
-                evicted = Some((pn, page));
-            }
+            self.shared.touch(pn);
+            return Ok((cache.get_mut(&pn).unwrap(), Vec::new()));
         }
+        let evicted = if self.in_memory {
+            Vec::new()
+        } else {
+            shrink_page_cache(Some((&self.shared, cache)), 1, held)?
+        };
 
         // Page not in cache, read it
         let mut page = PageCache::new()?;
@@ -506,23 +947,92 @@ impl CachedFile {
             file.read_at(page.data(), pn as u64 * PAGE_SIZE as u64)?;
         }
         cache.put(pn, page);
+        self.shared.touch(pn);
         Ok((cache.get_mut(&pn).unwrap(), evicted))
     }
 
     pub fn with_page<R>(&self, pn: u32, f: impl FnOnce(Option<&mut PageCache>) -> R) -> R {
-        f(self.shared.page_cache.lock().get_mut(&pn))
+        let mut guard = self.shared.page_cache.lock();
+        let page = guard.get_mut(&pn);
+        if page.is_some() {
+            self.shared.touch(pn);
+        }
+        f(page)
     }
 
+    /// Calls `f` with a page, reading it into the cache if necessary.
+    ///
+    /// The caller has the address space `aspace` locked, so `f` is also
+    /// given the pages evicted to make room that may be mapped there, see
+    /// [`EvictedPage`].
     pub fn with_page_or_insert<R>(
         &self,
         pn: u32,
-        f: impl FnOnce(&mut PageCache, Option<(u32, PageCache)>) -> VfsResult<R>,
+        aspace: usize,
+        f: impl FnOnce(&mut PageCache, Vec<EvictedPage>) -> VfsResult<R>,
     ) -> VfsResult<R> {
         let mut guard = self.shared.page_cache.lock();
-        let (page, evicted) = self.page_or_insert(self.inner.entry().as_file()?, &mut guard, pn)?;
+        let file = self.inner.entry().as_file()?;
+        let (page, evicted) = self.page_or_insert(file, &mut guard, pn, Some(aspace))?;
         f(page, evicted)
     }
 
+    /// Reads the pages within the given page range into the cache ahead of
+    /// time, skipping the ones that are already cached or beyond the end of
+    /// the file.
+    pub fn prefetch(&self, pages: Range<u32>) -> VfsResult<()> {
+        if self.in_memory {
+            return Ok(());
+        }
+        let file = self.inner.entry().as_file()?;
+        let end_page = file.len()?.div_ceil(PAGE_SIZE as u64).min(u32::MAX as u64) as u32;
+        for pn in pages.start..pages.end.min(end_page) {
+            let mut guard = self.shared.page_cache.lock();
+            if guard.contains(&pn) {
+                continue;
+            }
+            self.page_or_insert(file, &mut guard, pn, None)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the missing pages within the given page range into the cache,
+    /// straight into the pages, making room for every run of consecutive
+    /// missing pages at once.
+    fn fill_pages(&self, file: &FileNode, pages: Range<u32>) -> VfsResult<()> {
+        let mut guard = self.shared.page_cache.lock();
+        let mut pn = pages.start;
+        while pn < pages.end {
+            if guard.contains(&pn) {
+                pn += 1;
+                continue;
+            }
+            let start = pn;
+            while pn < pages.end && !guard.contains(&pn) {
+                pn += 1;
+            }
+            let count = (pn - start) as usize;
+            shrink_page_cache(Some((&self.shared, &mut guard)), count, None)?;
+
+            for pn in start..pn {
+                let mut page = PageCache::new()?;
+                let data = page.data();
+                let offset = pn as u64 * PAGE_SIZE as u64;
+                let mut read = 0;
+                while read < PAGE_SIZE {
+                    match file.read_at(&mut data[read..], offset + read as u64)? {
+                        0 => break,
+                        n => read += n,
+                    }
+                }
+                data[read..].fill(0);
+                guard.put(pn, page);
+                self.shared.touch(pn);
+            }
+        }
+        Ok(())
+    }
+
     fn with_pages<T>(
         &self,
         range: Range<u64>,
@@ -538,7 +1048,7 @@ impl CachedFile {
             let page_start = pn as u64 * PAGE_SIZE as u64;
 
             let mut guard = self.shared.page_cache.lock();
-            let page = self.page_or_insert(file, &mut guard, pn)?.0;
+            let page = self.page_or_insert(file, &mut guard, pn, None)?.0;
 
             initial = page_each(
                 initial,
@@ -557,6 +1067,12 @@ impl CachedFile {
         if end <= offset {
             return Ok(0);
         }
+        if !self.in_memory {
+            let pages = self.readahead.lock().advance(offset..end);
+            let end_page = len.div_ceil(PAGE_SIZE as u64).min(u32::MAX as u64) as u32;
+            let file = self.inner.entry().as_file()?;
+            self.fill_pages(file, pages.start..pages.end.min(end_page))?;
+        }
         self.with_pages(
             offset..end,
             |_| Ok(0),
@@ -582,7 +1098,7 @@ impl CachedFile {
                 let len = range.end - range.start;
                 buf.read(&mut page.data()[range.start..range.end])?;
                 if !self.in_memory {
-                    page.dirty = true;
+                    page.mark_dirty();
                 }
                 Ok(written + len)
             },
@@ -590,16 +1106,28 @@ impl CachedFile {
     }
 
     pub fn write_at(&self, buf: &mut impl Buf, offset: u64) -> VfsResult<usize> {
-        let _guard = self.append_lock.read();
-        self.write_at_locked(buf, offset)
+        let written = {
+            let _guard = self.append_lock.read();
+            self.write_at_locked(buf, offset)?
+        };
+        if !self.in_memory {
+            balance_dirty_pages()?;
+        }
+        Ok(written)
     }
 
     pub fn append(&self, buf: &mut impl Buf) -> VfsResult<(usize, u64)> {
-        let _guard = self.append_lock.write();
-        let file = self.inner.entry().as_file()?;
-        let len = file.len()?;
-        self.write_at_locked(buf, len)
-            .map(|written| (written, len + written as u64))
+        let result = {
+            let _guard = self.append_lock.write();
+            let file = self.inner.entry().as_file()?;
+            let len = file.len()?;
+            self.write_at_locked(buf, len)
+                .map(|written| (written, len + written as u64))?
+        };
+        if !self.in_memory {
+            balance_dirty_pages()?;
+        }
+        Ok(result)
     }
 
     pub fn set_len(&self, len: u64) -> VfsResult<()> {
@@ -619,22 +1147,16 @@ impl CachedFile {
             }
         } else if old_last_page > new_last_page {
             // For truncating, we need to remove all pages that are beyond the
-            // new length
-            // TODO(mivik): can this be more efficient?
-            let mut guard = self.shared.page_cache.lock();
-            let keys = guard
-                .iter()
-                .map(|(k, _)| *k)
-                .filter(|it| *it > new_last_page)
-                .collect::<Vec<_>>();
-            for pn in keys {
-                if let Some(mut page) = guard.pop(&pn) {
-                    if !self.in_memory {
+            // new length. In memory files free their pages themselves.
+            if !self.in_memory {
+                self.shared.remove_pages(
+                    |pn| pn > new_last_page,
+                    |_, mut page| {
                         // Don't write back pages since they're discarded
-                        page.dirty = false;
-                        self.evict_cache(file, pn, &mut page)?;
-                    }
-                }
+                        page.mark_clean();
+                        Ok(())
+                    },
+                )?;
             }
         }
         Ok(())
@@ -645,14 +1167,86 @@ impl CachedFile {
             return Ok(());
         }
         let file = self.inner.entry().as_file()?;
-        let mut guard = self.shared.page_cache.lock();
-        while let Some((pn, mut page)) = guard.pop_lru() {
-            self.evict_cache(file, pn, &mut page)?;
-        }
+        self.shared.remove_pages(
+            |_| true,
+            |pn, mut page| self.shared.write_back(file, pn, &mut page),
+        )?;
         file.sync(data_only)?;
         Ok(())
     }
 
+    /// Removes the pages overlapping `range` from the cache, writing back
+    /// the dirty ones unless they are entirely within `range`.
+    fn discard_pages(&self, file: &FileNode, range: Range<u64>) -> VfsResult<()> {
+        self.shared.remove_pages(
+            |pn| {
+                let start = pn as u64 * PAGE_SIZE as u64;
+                start < range.end && range.start < start + PAGE_SIZE as u64
+            },
+            |pn, mut page| {
+                let start = pn as u64 * PAGE_SIZE as u64;
+                if range.start <= start && start + PAGE_SIZE as u64 <= range.end {
+                    page.mark_clean();
+                }
+                self.shared.write_back(file, pn, &mut page)
+            },
+        )
+    }
+
+    pub fn fallocate(&self, flags: FallocateFlags, range: Range<u64>) -> VfsResult<()> {
+        let file = self.inner.entry().as_file()?;
+        space::check_supported(file, flags)?;
+        if flags.intersects(FallocateFlags::PUNCH_HOLE | FallocateFlags::ZERO_RANGE) {
+            if !self.in_memory {
+                self.discard_pages(file, range.clone())?;
+            }
+            space::clear_range(file, range.clone())?;
+        }
+        if !flags.contains(FallocateFlags::KEEP_SIZE) && range.end > file.len()? {
+            self.set_len(range.end)?;
+        }
+        Ok(())
+    }
+
+    /// Writes back the dirty pages within the given page range, keeping them
+    /// in the cache.
+    ///
+    /// The pages are write-protected in every address space first, except
+    /// in `aspace`, which the caller has locked and write-protected itself.
+    /// If `wait` is `true`, the written data is also synced to the storage.
+    pub fn sync_pages(&self, pages: Range<u32>, wait: bool, aspace: usize) -> VfsResult<()> {
+        if self.in_memory {
+            return Ok(());
+        }
+        let file = self.inner.entry().as_file()?;
+        let mut next = pages.start;
+        while next < pages.end {
+            let mut guard = self.shared.page_cache.lock();
+            while next < pages.end {
+                if let Some(page) = guard.peek_mut(&next).filter(|page| page.is_dirty()) {
+                    if !self
+                        .shared
+                        .notify(next, PageAction::WriteProtect, Some(aspace))
+                    {
+                        break;
+                    }
+                    self.shared.write_back(file, next, page)?;
+                }
+                next += 1;
+            }
+            if next < pages.end {
+                // Retry with the cache unlocked, so that the task holding
+                // the address space can finish.
+                drop(guard);
+                wait_for_mappings();
+            }
+        }
+        if wait {
+            file.sync(true)?;
+        }
+        Ok(())
+    }
+
     pub fn location(&self) -> &Location {
         &self.inner
     }
@@ -676,6 +1270,8 @@ impl Drop for CachedFile {
 pub enum FileBackend {
     Cached(CachedFile),
     Direct(Location),
+    /// An open end of the pipe of a FIFO node.
+    Fifo(Location, PipeEnd),
 }
 
 impl FileBackend {
@@ -695,6 +1291,7 @@ impl FileBackend {
                     offset += *read as u64;
                 })
             }),
+            Self::Fifo(_, end) => end.read(dst),
         }
     }
 
@@ -709,6 +1306,7 @@ impl FileBackend {
                         offset += *written as u64;
                     })
             }),
+            Self::Fifo(_, end) => end.write(src),
         }
     }
 
@@ -722,13 +1320,14 @@ impl FileBackend {
                     .as_file()?
                     .append(unsafe { buffer.assume_init_ref() })
             }
+            Self::Fifo(_, end) => end.write(src).map(|written| (written, 0)),
         }
     }
 
     pub fn location(&self) -> &Location {
         match self {
             Self::Cached(cached) => cached.location(),
-            Self::Direct(loc) => loc,
+            Self::Direct(loc) | Self::Fifo(loc, _) => loc,
         }
     }
 
@@ -736,6 +1335,7 @@ impl FileBackend {
         match self {
             Self::Cached(cached) => cached.sync(data_only),
             Self::Direct(loc) => loc.entry().as_file()?.sync(data_only),
+            Self::Fifo(..) => Ok(()),
         }
     }
 
@@ -743,6 +1343,40 @@ impl FileBackend {
         match self {
             Self::Cached(cached) => cached.set_len(len),
             Self::Direct(loc) => loc.entry().as_file()?.set_len(len),
+            Self::Fifo(..) => Err(VfsError::EINVAL),
+        }
+    }
+
+    /// Preallocates, deallocates or zeros a range of the file, see
+    /// [`FallocateFlags`].
+    pub fn fallocate(&self, flags: FallocateFlags, range: Range<u64>) -> VfsResult<()> {
+        flags.validate()?;
+        if range.is_empty() {
+            return Err(VfsError::EINVAL);
+        }
+        let loc = match self {
+            Self::Cached(cached) => return cached.fallocate(flags, range),
+            Self::Direct(loc) => loc,
+            Self::Fifo(..) => return Err(VfsError::ESPIPE),
+        };
+        let file = loc.entry().as_file()?;
+        space::check_supported(file, flags)?;
+        if flags.intersects(FallocateFlags::PUNCH_HOLE | FallocateFlags::ZERO_RANGE) {
+            space::clear_range(file, range.clone())?;
+        }
+        if !flags.contains(FallocateFlags::KEEP_SIZE) && range.end > file.len()? {
+            file.set_len(range.end)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the first offset at or after `offset` that is in data if
+    /// `data` is `true`, or in a hole otherwise, as with `SEEK_DATA` and
+    /// `SEEK_HOLE`.
+    pub fn seek_data(&self, offset: u64, data: bool) -> VfsResult<u64> {
+        match self {
+            Self::Fifo(..) => Err(VfsError::ESPIPE),
+            _ => space::seek(self.location().entry().as_file()?, offset, data),
         }
     }
 }
@@ -754,11 +1388,22 @@ pub struct File {
     position: Option<Mutex<u64>>,
     #[cfg(feature = "times")]
     access_flags: AtomicU8,
+    /// The owner of the whole-file lock held through this file.
+    lock_owner: u64,
+    /// The owners of byte-range locks acquired through this file, whose
+    /// locks are released when it is dropped.
+    range_lock_owners: Mutex<Vec<u64>>,
+    /// Keeps the mount of the file from being unmounted.
+    _mount_ref: MountRef,
 }
 
+static NEXT_LOCK_OWNER: AtomicU64 = AtomicU64::new(0);
+
 impl File {
     pub fn new(inner: FileBackend, flags: FileFlags) -> Self {
-        let position = if inner.location().flags().contains(NodeFlags::STREAM) {
+        let position = if matches!(inner, FileBackend::Fifo(..))
+            || inner.location().flags().contains(NodeFlags::STREAM)
+        {
             None
         } else {
             Some(Mutex::new(if flags.contains(FileFlags::APPEND) {
@@ -767,12 +1412,16 @@ impl File {
                 0
             }))
         };
+        let mount_ref = MountRef::new(inner.location());
         Self {
             inner,
             flags,
             position,
             #[cfg(feature = "times")]
             access_flags: AtomicU8::new(0),
+            lock_owner: NEXT_LOCK_OWNER.fetch_add(1, Ordering::Relaxed),
+            range_lock_owners: Mutex::new(Vec::new()),
+            _mount_ref: mount_ref,
         }
     }
 
@@ -824,7 +1473,11 @@ impl File {
 
     /// Writes a number of bytes starting from a given offset.
     pub fn write_at(&self, src: &mut impl Buf, offset: u64) -> VfsResult<usize> {
-        self.access(FileFlags::WRITE)?.write_at(src, offset)
+        let written = self.access(FileFlags::WRITE)?.write_at(src, offset)?;
+        if written > 0 {
+            watch::notify_entry(self.location(), WatchMask::MODIFY);
+        }
+        Ok(written)
     }
 
     /// Attempts to sync OS-internal file content and metadata to disk.
@@ -836,6 +1489,76 @@ impl File {
         self.inner.sync(data_only)
     }
 
+    /// Preallocates, deallocates or zeros `len` bytes at `offset`, as with
+    /// `fallocate`.
+    pub fn fallocate(&self, flags: FallocateFlags, offset: u64, len: u64) -> VfsResult<()> {
+        let end = offset.checked_add(len).ok_or(VfsError::EFBIG)?;
+        self.access(FileFlags::WRITE)?
+            .fallocate(flags, offset..end)?;
+        watch::notify_entry(self.location(), WatchMask::MODIFY);
+        Ok(())
+    }
+
+    /// Returns the offset of the next data at or after `offset`, as with
+    /// `SEEK_DATA`.
+    pub fn seek_data(&self, offset: u64) -> VfsResult<u64> {
+        self.access(FileFlags::empty())?.seek_data(offset, true)
+    }
+
+    /// Returns the offset of the next hole at or after `offset`, as with
+    /// `SEEK_HOLE`. The end of the file counts as a hole.
+    pub fn seek_hole(&self, offset: u64) -> VfsResult<u64> {
+        self.access(FileFlags::empty())?.seek_data(offset, false)
+    }
+
+    /// Acquires, converts or releases (with `None`) the whole-file lock of
+    /// this file, as with `flock`.
+    ///
+    /// The lock is released when this file is dropped.
+    pub fn flock(&self, lock_type: Option<LockType>, wait: bool) -> VfsResult<()> {
+        self.access(FileFlags::empty())?;
+        FileLocks::of(self.location()).flock(self.lock_owner, lock_type, wait)
+    }
+
+    /// Acquires or releases (with `None`) a byte-range lock of `owner`, as
+    /// with `F_SETLK` and `F_SETLKW`.
+    ///
+    /// Read locks require the file to be opened for reading, and write locks
+    /// for writing. The locks of `owner` on this file are released when this
+    /// file is dropped.
+    pub fn lock_range(
+        &self,
+        owner: u64,
+        lock_type: Option<LockType>,
+        range: Range<u64>,
+        wait: bool,
+    ) -> VfsResult<()> {
+        match lock_type {
+            Some(LockType::Read) => self.access(FileFlags::READ)?,
+            Some(LockType::Write) => self.access(FileFlags::WRITE)?,
+            None => self.access(FileFlags::empty())?,
+        };
+        if lock_type.is_some() {
+            let mut owners = self.range_lock_owners.lock();
+            if !owners.contains(&owner) {
+                owners.push(owner);
+            }
+        }
+        FileLocks::of(self.location()).lock_range(owner, lock_type, range, wait)
+    }
+
+    /// Returns the first byte-range lock conflicting with a lock `owner`
+    /// could request, as with `F_GETLK`.
+    pub fn test_range_lock(
+        &self,
+        owner: u64,
+        lock_type: LockType,
+        range: Range<u64>,
+    ) -> VfsResult<Option<RangeLock>> {
+        self.access(FileFlags::empty())?;
+        Ok(FileLocks::of(self.location()).test_range(owner, lock_type, range))
+    }
+
     pub fn read(&self, dst: &mut impl BufMut) -> axio::Result<usize> {
         #[cfg(feature = "times")]
         {
@@ -859,10 +1582,12 @@ impl File {
         if let Some(pos) = self.position.as_ref() {
             let mut pos = pos.lock();
             if let Ok(f) = self.access(FileFlags::APPEND) {
-                f.append(src).map(|(written, new_size)| {
-                    *pos = new_size;
-                    written
-                })
+                let (written, new_size) = f.append(src)?;
+                *pos = new_size;
+                if written > 0 {
+                    watch::notify_entry(self.location(), WatchMask::MODIFY);
+                }
+                Ok(written)
             } else {
                 self.write_at(src, *pos).inspect(|n| {
                     *pos += *n as u64;
@@ -903,17 +1628,38 @@ impl<'a> axio::Seek for &'a File {
 
 impl Pollable for File {
     fn poll(&self) -> IoEvents {
-        self.inner.location().poll()
+        match &self.inner {
+            FileBackend::Fifo(_, end) => end.poll(),
+            inner => inner.location().poll(),
+        }
     }
 
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
-        self.inner.location().register(context, events)
+        match &self.inner {
+            FileBackend::Fifo(_, end) => end.register(context, events),
+            inner => inner.location().register(context, events),
+        }
     }
 }
 
-#[cfg(feature = "times")]
 impl Drop for File {
     fn drop(&mut self) {
+        let locks = self.location().user_data().get::<FileLocks>();
+        if let Some(locks) = locks {
+            let _ = locks.flock(self.lock_owner, None, false);
+            for owner in self.range_lock_owners.get_mut().drain(..) {
+                let _ = locks.lock_range(owner, None, 0..u64::MAX, false);
+            }
+        }
+
+        #[cfg(feature = "times")]
+        self.update_times();
+    }
+}
+
+#[cfg(feature = "times")]
+impl File {
+    fn update_times(&self) {
         let flags = self.access_flags.load(Ordering::Acquire);
         if flags != 0 {
             let mut update = axfs_ng_vfs::MetadataUpdate::default();
@@ -6,12 +6,15 @@ use alloc::{
 };
 
 use axfs_ng_vfs::{
-    Location, Metadata, NodePermission, NodeType, VfsError, VfsResult,
+    Location, Metadata, MetadataUpdate, NodePermission, NodeType, VfsError, VfsResult,
     path::{Component, Components, Path, PathBuf},
 };
 use axsync::Mutex;
 use spin::Once;
 
+use super::{AccessMode, Credentials, MountRef, WatchMask, check_writable, dcache, watch};
+use crate::fs::with_rename_flags;
+
 pub const SYMLINKS_MAX: usize = 40;
 
 pub static ROOT_FS_CONTEXT: Once<FsContext> = Once::new();
@@ -26,6 +29,48 @@ scope_local::scope_local! {
         ));
 }
 
+bitflags::bitflags! {
+    /// Restrictions on path resolution, using the values of Linux
+    /// `RESOLVE_*` constants.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct ResolveFlags: u32 {
+        /// Fails with `EXDEV` when crossing a mount, including through `..`
+        /// or symlinks.
+        const NO_XDEV = 0x01;
+        /// Disallows magic links, i.e. procfs-style links whose targets are
+        /// not paths. No filesystem of this crate has them, so this has no
+        /// effect.
+        const NO_MAGICLINKS = 0x02;
+        /// Fails with `ELOOP` when a symlink would be followed.
+        const NO_SYMLINKS = 0x04;
+        /// Fails with `EXDEV` when resolution would leave the starting
+        /// directory, through `..`, an absolute path or a symlink.
+        const BENEATH = 0x08;
+        /// Treats the starting directory as the root directory.
+        const IN_ROOT = 0x10;
+    }
+}
+
+/// Returns whether `a` and `b` are the same file of the same mount.
+///
+/// This compares the inode numbers the nodes keep in memory, without
+/// reading their metadata, as it is done for every `..` in a path.
+fn same_location(a: &Location, b: &Location) -> bool {
+    Arc::ptr_eq(a.mountpoint(), b.mountpoint()) && a.inode() == b.inode()
+}
+
+bitflags::bitflags! {
+    /// Flags of [`FsContext::rename_with_flags`], using the values of Linux
+    /// `RENAME_*` constants.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct RenameFlags: u32 {
+        /// Fails with `EEXIST` instead of replacing the target.
+        const NOREPLACE = 0x1;
+        /// Swaps the source and the target.
+        const EXCHANGE = 0x2;
+    }
+}
+
 pub struct ReadDirEntry {
     pub name: String,
     pub ino: u64,
@@ -34,17 +79,32 @@ pub struct ReadDirEntry {
 }
 
 /// Provides `std::fs`-like interface.
+///
+/// Operations are checked against the permissions of the files they access
+/// for the credentials of the context, which are root's by default.
 #[derive(Debug, Clone)]
 pub struct FsContext {
     root_dir: Location,
     current_dir: Location,
+    cred: Credentials,
+    resolve_flags: ResolveFlags,
+    /// The directory resolution must stay beneath with
+    /// [`ResolveFlags::BENEATH`].
+    beneath: Option<Location>,
+    /// Keeps the mounts of `root_dir` and `current_dir` from being unmounted.
+    mount_refs: [MountRef; 2],
 }
 
 impl FsContext {
     pub fn new(root_dir: Location) -> Self {
+        let mount_ref = MountRef::new(&root_dir);
         Self {
             root_dir: root_dir.clone(),
             current_dir: root_dir,
+            cred: Credentials::root(),
+            resolve_flags: ResolveFlags::empty(),
+            beneath: None,
+            mount_refs: [mount_ref.clone(), mount_ref],
         }
     }
 
@@ -58,6 +118,8 @@ impl FsContext {
 
     pub fn set_current_dir(&mut self, current_dir: Location) -> VfsResult<()> {
         current_dir.check_is_dir()?;
+        self.cred.check_access(&current_dir, AccessMode::EXECUTE)?;
+        self.mount_refs[1] = MountRef::new(&current_dir);
         self.current_dir = current_dir;
         Ok(())
     }
@@ -66,10 +128,101 @@ impl FsContext {
         current_dir.check_is_dir()?;
         Ok(Self {
             root_dir: self.root_dir.clone(),
+            mount_refs: [self.mount_refs[0].clone(), MountRef::new(&current_dir)],
             current_dir,
+            cred: self.cred.clone(),
+            resolve_flags: self.resolve_flags,
+            beneath: self.beneath.clone(),
+        })
+    }
+
+    /// Returns a context resolving paths from the current directory with the
+    /// restrictions of `flags`, as with Linux `openat2`.
+    ///
+    /// `BENEATH` and `IN_ROOT` both apply to the current directory, and
+    /// cannot be combined.
+    pub fn with_resolve_flags(&self, flags: ResolveFlags) -> VfsResult<Self> {
+        if flags.contains(ResolveFlags::BENEATH | ResolveFlags::IN_ROOT) {
+            return Err(VfsError::EINVAL);
+        }
+        let mut context = self.clone();
+        context.resolve_flags = flags;
+        if flags.contains(ResolveFlags::IN_ROOT) {
+            context.root_dir = self.current_dir.clone();
+            context.mount_refs[0] = self.mount_refs[1].clone();
+        }
+        if flags.contains(ResolveFlags::BENEATH) {
+            context.beneath = Some(self.current_dir.clone());
+        }
+        Ok(context)
+    }
+
+    pub fn resolve_flags(&self) -> ResolveFlags {
+        self.resolve_flags
+    }
+
+    /// Returns whether `..` must not go above `dir`, which is the case of the
+    /// root directory and of the directory resolution stays beneath.
+    fn is_boundary(&self, dir: &Location) -> bool {
+        same_location(dir, &self.root_dir)
+            || self
+                .beneath
+                .as_ref()
+                .is_some_and(|beneath| same_location(dir, beneath))
+    }
+
+    /// Fails with `EXDEV` if resolution moving from `from` to `to` crosses a
+    /// mount while it must not.
+    pub(crate) fn check_xdev(&self, from: &Location, to: &Location) -> VfsResult<()> {
+        if self.resolve_flags.contains(ResolveFlags::NO_XDEV)
+            && !Arc::ptr_eq(from.mountpoint(), to.mountpoint())
+        {
+            return Err(VfsError::EXDEV);
+        }
+        Ok(())
+    }
+
+    /// Returns the credentials operations are performed as.
+    pub fn credentials(&self) -> &Credentials {
+        &self.cred
+    }
+
+    pub fn set_credentials(&mut self, cred: Credentials) {
+        self.cred = cred;
+    }
+
+    /// Fails with `EACCES` unless `loc` grants `mode` access to the
+    /// credentials of this context.
+    pub fn check_access(&self, loc: &Location, mode: AccessMode) -> VfsResult<()> {
+        self.cred.check_access(loc, mode)
+    }
+
+    /// Checks that an entry can be added to or removed from `dir`.
+    fn check_dir_writable(&self, dir: &Location) -> VfsResult<()> {
+        check_writable(dir)?;
+        self.check_access(dir, AccessMode::WRITE | AccessMode::EXECUTE)
+    }
+
+    /// Makes the credentials of this context the owner of a new file.
+    ///
+    /// Filesystems create files as owned by root, so nothing needs to be done
+    /// for root.
+    fn set_owner(&self, loc: &Location) -> VfsResult<()> {
+        if self.cred.is_root() {
+            return Ok(());
+        }
+        loc.update_metadata(MetadataUpdate {
+            owner: Some((self.cred.uid, self.cred.gid)),
+            ..Default::default()
         })
     }
 
+    /// Checks that `entry` can be removed from or renamed out of `dir`.
+    fn check_removable(&self, dir: &Location, entry: &Location) -> VfsResult<()> {
+        self.check_dir_writable(dir)?;
+        self.cred.check_sticky(dir, entry)
+    }
+
     /// Attempts to resolve a possible symlink, at the current location (this
     /// assumes that `loc` is a child of current directory).
     pub fn try_resolve_symlink(
@@ -80,6 +233,9 @@ impl FsContext {
         if loc.node_type() != NodeType::Symlink {
             return Ok(loc);
         }
+        if self.resolve_flags.contains(ResolveFlags::NO_SYMLINKS) {
+            return Err(VfsError::ELOOP);
+        }
         if *follow_count >= SYMLINKS_MAX {
             return Err(VfsError::ELOOP);
         }
@@ -91,10 +247,21 @@ impl FsContext {
         self.resolve_components(PathBuf::from(target).components(), follow_count)
     }
 
+    /// Looks up `name` in `dir`, which requires search permission on it.
+    pub(crate) fn lookup_no_follow(&self, dir: &Location, name: &str) -> VfsResult<Location> {
+        self.check_access(dir, AccessMode::EXECUTE)?;
+        let loc = dcache::lookup(dir, name)?;
+        self.check_xdev(dir, &loc)?;
+        Ok(loc)
+    }
+
     fn lookup(&self, dir: &Location, name: &str, follow_count: &mut usize) -> VfsResult<Location> {
-        let loc = dir.lookup_no_follow(name)?;
-        self.with_current_dir(dir.clone())?
-            .try_resolve_symlink(loc, follow_count)
+        let loc = self.lookup_no_follow(dir, name)?;
+        let loc = self
+            .with_current_dir(dir.clone())?
+            .try_resolve_symlink(loc, follow_count)?;
+        self.check_xdev(dir, &loc)?;
+        Ok(loc)
     }
 
     fn resolve_components(
@@ -104,18 +271,27 @@ impl FsContext {
     ) -> VfsResult<Location> {
         let mut dir = self.current_dir.clone();
         for comp in components {
-            match comp {
-                Component::CurDir => {}
+            let next = match comp {
+                Component::CurDir => continue,
                 Component::ParentDir => {
-                    dir = dir.parent().unwrap_or_else(|| self.root_dir.clone());
+                    if same_location(&dir, &self.root_dir) {
+                        continue;
+                    }
+                    if self.beneath.is_some() && self.is_boundary(&dir) {
+                        return Err(VfsError::EXDEV);
+                    }
+                    dir.parent().unwrap_or_else(|| self.root_dir.clone())
                 }
                 Component::RootDir => {
-                    dir = self.root_dir.clone();
+                    if self.beneath.is_some() {
+                        return Err(VfsError::EXDEV);
+                    }
+                    self.root_dir.clone()
                 }
-                Component::Normal(name) => {
-                    dir = self.lookup(&dir, name, follow_count)?;
-                }
-            }
+                Component::Normal(name) => self.lookup(&dir, name, follow_count)?,
+            };
+            self.check_xdev(&dir, &next)?;
+            dir = next;
         }
         Ok(dir)
     }
@@ -149,7 +325,7 @@ impl FsContext {
     pub fn resolve_no_follow(&self, path: impl AsRef<Path>) -> VfsResult<Location> {
         let (dir, name) = self.resolve_inner(path.as_ref(), &mut 0)?;
         match name {
-            Some(name) => dir.lookup_no_follow(name),
+            Some(name) => self.lookup_no_follow(&dir, name),
             None => Ok(dir),
         }
     }
@@ -163,6 +339,8 @@ impl FsContext {
         let (dir, name) = self.resolve_inner(path, &mut 0)?;
         if let Some(name) = name {
             Ok((dir, Cow::Borrowed(name)))
+        } else if self.is_boundary(&dir) {
+            Err(VfsError::EINVAL)
         } else if let Some(parent) = dir.parent() {
             Ok((parent, Cow::Owned(dir.name().to_owned())))
         } else {
@@ -194,6 +372,7 @@ impl FsContext {
     /// Returns an iterator over the entries in a directory.
     pub fn read_dir(&self, path: impl AsRef<Path>) -> VfsResult<ReadDir> {
         let dir = self.resolve(path)?;
+        self.check_access(&dir, AccessMode::READ)?;
         Ok(ReadDir {
             dir,
             buf: VecDeque::new(),
@@ -205,33 +384,106 @@ impl FsContext {
     /// Removes a file from the filesystem.
     pub fn remove_file(&self, path: impl AsRef<Path>) -> VfsResult<()> {
         let entry = self.resolve_no_follow(path.as_ref())?;
-        entry
-            .parent()
-            .ok_or(VfsError::EISDIR)?
-            .unlink(entry.name(), false)
+        let parent = entry.parent().ok_or(VfsError::EISDIR)?;
+        self.check_removable(&parent, &entry)?;
+        parent.unlink(entry.name(), false)?;
+        dcache::invalidate(&parent, entry.name());
+        watch::notify_delete(&entry);
+        Ok(())
     }
 
     /// Removes a directory from the filesystem.
     pub fn remove_dir(&self, path: impl AsRef<Path>) -> VfsResult<()> {
         let entry = self.resolve_no_follow(path.as_ref())?;
-        entry
-            .parent()
-            .ok_or(VfsError::EBUSY)?
-            .unlink(entry.name(), true)
+        let parent = entry.parent().ok_or(VfsError::EBUSY)?;
+        self.check_removable(&parent, &entry)?;
+        parent.unlink(entry.name(), true)?;
+        dcache::invalidate(&parent, entry.name());
+        watch::notify_delete(&entry);
+        Ok(())
     }
 
     /// Renames a file or directory to a new name, replacing the original file
     /// if `to` already exists.
     pub fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> VfsResult<()> {
+        self.rename_with_flags(from, to, RenameFlags::empty())
+    }
+
+    /// Renames a file or directory to a new name, as with Linux
+    /// `renameat2`.
+    ///
+    /// With `NOREPLACE`, fails with `EEXIST` if `to` exists. With `EXCHANGE`,
+    /// swaps `from` and `to`, which must both exist. Fails with `EINVAL` if
+    /// flags are given and the filesystem does not support them.
+    pub fn rename_with_flags(
+        &self,
+        from: impl AsRef<Path>,
+        to: impl AsRef<Path>,
+        flags: RenameFlags,
+    ) -> VfsResult<()> {
+        if flags.contains(RenameFlags::NOREPLACE | RenameFlags::EXCHANGE) {
+            return Err(VfsError::EINVAL);
+        }
         let (src_dir, src_name) = self.resolve_parent(from.as_ref())?;
         let (dst_dir, dst_name) = self.resolve_parent(to.as_ref())?;
-        src_dir.rename(&src_name, &dst_dir, &dst_name)
+        let src = self.lookup_no_follow(&src_dir, &src_name)?;
+        self.check_removable(&src_dir, &src)?;
+        let dst = match self.lookup_no_follow(&dst_dir, &dst_name) {
+            Ok(dst) => {
+                if flags.contains(RenameFlags::NOREPLACE) {
+                    return Err(VfsError::EEXIST);
+                }
+                self.check_removable(&dst_dir, &dst)?;
+                Some(dst)
+            }
+            Err(VfsError::ENOENT) if !flags.contains(RenameFlags::EXCHANGE) => {
+                self.check_dir_writable(&dst_dir)?;
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        if flags.contains(RenameFlags::EXCHANGE) {
+            if dst.is_some_and(|dst| same_location(&src, &dst)) {
+                return Ok(());
+            }
+            let result = with_rename_flags(&src_dir, flags, || {
+                src_dir.rename(&src_name, &dst_dir, &dst_name)
+            });
+            dcache::invalidate(&src_dir, &src_name);
+            dcache::invalidate(&dst_dir, &dst_name);
+            result?;
+            if let Ok(moved) = src_dir.lookup_no_follow(&src_name) {
+                watch::notify_rename(&dst_dir, &dst_name, &moved);
+            }
+        } else {
+            // The replaced file is gone, unless it is another link to the
+            // renamed one.
+            let replaced = dst.filter(|dst| !same_location(&src, dst));
+            with_rename_flags(&src_dir, flags, || {
+                src_dir.rename(&src_name, &dst_dir, &dst_name)
+            })?;
+            dcache::invalidate(&src_dir, &src_name);
+            dcache::invalidate(&dst_dir, &dst_name);
+            if let Some(replaced) = replaced {
+                watch::notify_delete(&replaced);
+            }
+        }
+        if let Ok(moved) = dst_dir.lookup_no_follow(&dst_name) {
+            watch::notify_rename(&src_dir, &src_name, &moved);
+        }
+        Ok(())
     }
 
     /// Creates a new, empty directory at the provided path.
     pub fn create_dir(&self, path: impl AsRef<Path>, mode: NodePermission) -> VfsResult<Location> {
-        let (dir, name) = self.resolve_nonexistent(path.as_ref())?;
-        dir.create(name, NodeType::Directory, mode)
+        let (parent, name) = self.resolve_nonexistent(path.as_ref())?;
+        self.check_dir_writable(&parent)?;
+        let dir = parent.create(name, NodeType::Directory, mode)?;
+        dcache::invalidate(&parent, name);
+        self.set_owner(&dir)?;
+        watch::notify_create(&dir);
+        Ok(dir)
     }
 
     /// Creates a new hard link on the filesystem.
@@ -242,7 +494,12 @@ impl FsContext {
     ) -> VfsResult<Location> {
         let old = self.resolve(old_path.as_ref())?;
         let (new_dir, new_name) = self.resolve_nonexistent(new_path.as_ref())?;
-        new_dir.link(new_name, &old)
+        self.check_dir_writable(&new_dir)?;
+        let new = new_dir.link(new_name, &old)?;
+        dcache::invalidate(&new_dir, new_name);
+        watch::notify_create(&new);
+        watch::notify_entry(&old, WatchMask::ATTRIB);
+        Ok(new)
     }
 
     /// Creates a new symbolic link on the filesystem.
@@ -255,8 +512,12 @@ impl FsContext {
         if dir.lookup_no_follow(name).is_ok() {
             return Err(VfsError::EEXIST);
         }
+        self.check_dir_writable(&dir)?;
         let symlink = dir.create(name, NodeType::Symlink, NodePermission::default())?;
+        dcache::invalidate(&dir, name);
+        self.set_owner(&symlink)?;
         symlink.entry().as_file()?.set_symlink(target.as_ref())?;
+        watch::notify_create(&symlink);
         Ok(symlink)
     }
 
@@ -0,0 +1,285 @@
+//! Advisory file locks.
+//!
+//! Two independent kinds of locks are supported, both of which only affect
+//! other lock requests and never reads or writes:
+//!
+//! - whole-file locks with `flock` semantics, owned by an open [`File`];
+//! - POSIX byte-range locks, owned by an arbitrary `u64` owner, usually a
+//!   process ID.
+//!
+//! [`File`]: super::File
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axfs_ng_vfs::{Location, VfsError, VfsResult};
+use axio::PollSet;
+use spin::Mutex;
+
+use super::wait_until;
+
+/// The type of an advisory lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockType {
+    /// A shared lock, which can be held by multiple owners at once.
+    Read,
+    /// An exclusive lock.
+    Write,
+}
+
+impl LockType {
+    fn conflicts(self, other: LockType) -> bool {
+        self == LockType::Write || other == LockType::Write
+    }
+}
+
+/// A POSIX byte-range lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeLock {
+    pub owner: u64,
+    pub lock_type: LockType,
+    /// The locked bytes, ending at `u64::MAX` if the lock extends to the end
+    /// of the file however long it grows.
+    pub range: Range<u64>,
+}
+
+#[derive(Default)]
+struct LockState {
+    flocks: Vec<(u64, LockType)>,
+    ranges: Vec<RangeLock>,
+}
+
+impl LockState {
+    fn flock_conflict(&self, owner: u64, lock_type: LockType) -> bool {
+        self.flocks
+            .iter()
+            .any(|&(other, other_type)| other != owner && lock_type.conflicts(other_type))
+    }
+
+    fn range_conflicts(
+        &self,
+        owner: u64,
+        lock_type: LockType,
+        range: &Range<u64>,
+    ) -> impl Iterator<Item = &RangeLock> {
+        self.ranges.iter().filter(move |lock| {
+            lock.owner != owner
+                && lock.range.start < range.end
+                && range.start < lock.range.end
+                && lock_type.conflicts(lock.lock_type)
+        })
+    }
+
+    /// Removes the locks of `owner` within `range`, splitting the locks that
+    /// are only partially covered.
+    fn unlock_range(&mut self, owner: u64, range: &Range<u64>) {
+        let mut remaining = Vec::with_capacity(self.ranges.len());
+        for lock in self.ranges.drain(..) {
+            if lock.owner != owner || lock.range.end <= range.start || range.end <= lock.range.start
+            {
+                remaining.push(lock);
+                continue;
+            }
+            if lock.range.start < range.start {
+                remaining.push(RangeLock {
+                    range: lock.range.start..range.start,
+                    ..lock.clone()
+                });
+            }
+            if range.end < lock.range.end {
+                remaining.push(RangeLock {
+                    range: range.end..lock.range.end,
+                    ..lock
+                });
+            }
+        }
+        self.ranges = remaining;
+    }
+
+    /// Adds a lock of an owner holding no lock within its range, merging it
+    /// with the adjacent locks of the same owner and type as Linux does.
+    fn insert_range(&mut self, mut lock: RangeLock) {
+        self.ranges.retain(|other| {
+            let adjacent = other.owner == lock.owner
+                && other.lock_type == lock.lock_type
+                && (other.range.end == lock.range.start || lock.range.end == other.range.start);
+            if adjacent {
+                lock.range =
+                    lock.range.start.min(other.range.start)..lock.range.end.max(other.range.end);
+            }
+            !adjacent
+        });
+        self.ranges.push(lock);
+    }
+}
+
+/// For every wait for a byte-range lock, by wait ID, the waiting owner and
+/// the owners holding the conflicting locks.
+///
+/// An owner may wait for several locks at once, e.g. from several threads of
+/// a process.
+static WAITING: Mutex<BTreeMap<u64, (u64, Vec<u64>)>> = Mutex::new(BTreeMap::new());
+
+static NEXT_WAIT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Returns whether `owner` waiting for `blockers` would never be woken up,
+/// because one of them is waiting for `owner`, directly or indirectly.
+fn would_deadlock(waiting: &BTreeMap<u64, (u64, Vec<u64>)>, owner: u64, blockers: &[u64]) -> bool {
+    let mut visited = Vec::new();
+    let mut stack = blockers.to_vec();
+    while let Some(next) = stack.pop() {
+        if next == owner {
+            return true;
+        }
+        if visited.contains(&next) {
+            continue;
+        }
+        visited.push(next);
+        for (waiter, blockers) in waiting.values() {
+            if *waiter == next {
+                stack.extend_from_slice(blockers);
+            }
+        }
+    }
+    false
+}
+
+/// Identifies a wait for a byte-range lock, and removes its entry from
+/// [`WAITING`] when dropped.
+struct WaitingGuard(u64);
+
+impl WaitingGuard {
+    fn new() -> Self {
+        Self(NEXT_WAIT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Drop for WaitingGuard {
+    fn drop(&mut self) {
+        WAITING.lock().remove(&self.0);
+    }
+}
+
+/// The advisory locks of a file.
+pub struct FileLocks {
+    state: Mutex<LockState>,
+    waiters: PollSet,
+}
+
+impl FileLocks {
+    /// Returns the locks of the file at `loc`.
+    pub fn of(loc: &Location) -> Arc<FileLocks> {
+        loc.user_data().get_or_insert_with(|| FileLocks {
+            state: Mutex::default(),
+            waiters: PollSet::new(),
+        })
+    }
+
+    /// Acquires, converts or releases (with `None`) the whole-file lock of
+    /// `owner`.
+    ///
+    /// If a conflicting lock is held by another owner, fails with `EAGAIN`
+    /// unless `wait` is `true`, in which case it waits until the lock can be
+    /// acquired or the wait is interrupted.
+    ///
+    /// Like on Linux, converting a lock is not atomic: the lock `owner`
+    /// holds is released before acquiring the new one, and stays released if
+    /// that fails. Otherwise two owners upgrading their shared locks would
+    /// wait for each other forever.
+    pub fn flock(&self, owner: u64, lock_type: Option<LockType>, wait: bool) -> VfsResult<()> {
+        let mut state = self.state.lock();
+        let held = state.flocks.iter().position(|(other, _)| *other == owner);
+        if let Some(index) = held {
+            if Some(state.flocks[index].1) == lock_type {
+                return Ok(());
+            }
+            state.flocks.remove(index);
+        }
+        drop(state);
+        if held.is_some() {
+            self.waiters.wake();
+        }
+        let Some(lock_type) = lock_type else {
+            return Ok(());
+        };
+        wait_until(&self.waiters, wait, || {
+            let mut state = self.state.lock();
+            if state.flock_conflict(owner, lock_type) {
+                return Err(VfsError::EAGAIN);
+            }
+            state.flocks.push((owner, lock_type));
+            Ok(())
+        })
+    }
+
+    /// Returns the first lock conflicting with a lock `owner` could request,
+    /// or `None` if the lock could be acquired.
+    pub fn test_range(
+        &self,
+        owner: u64,
+        lock_type: LockType,
+        range: Range<u64>,
+    ) -> Option<RangeLock> {
+        self.state
+            .lock()
+            .range_conflicts(owner, lock_type, &range)
+            .next()
+            .cloned()
+    }
+
+    /// Acquires or releases (with `None`) a byte-range lock of `owner`,
+    /// replacing the locks `owner` already holds within `range`.
+    ///
+    /// If a conflicting lock is held by another owner, fails with `EAGAIN`
+    /// unless `wait` is `true`, in which case it waits until the lock can be
+    /// acquired or the wait is interrupted. Fails with `EDEADLK` if waiting
+    /// would never end because the owners are waiting for each other.
+    pub fn lock_range(
+        &self,
+        owner: u64,
+        lock_type: Option<LockType>,
+        range: Range<u64>,
+        wait: bool,
+    ) -> VfsResult<()> {
+        if range.start >= range.end {
+            return Err(VfsError::EINVAL);
+        }
+        let Some(lock_type) = lock_type else {
+            self.state.lock().unlock_range(owner, &range);
+            self.waiters.wake();
+            return Ok(());
+        };
+        let guard = WaitingGuard::new();
+        wait_until(&self.waiters, wait, || {
+            let mut state = self.state.lock();
+            let blockers: Vec<u64> = state
+                .range_conflicts(owner, lock_type, &range)
+                .map(|lock| lock.owner)
+                .collect();
+            if !blockers.is_empty() {
+                if wait {
+                    let mut waiting = WAITING.lock();
+                    if would_deadlock(&waiting, owner, &blockers) {
+                        return Err(VfsError::EDEADLK);
+                    }
+                    waiting.insert(guard.0, (owner, blockers));
+                }
+                return Err(VfsError::EAGAIN);
+            }
+            state.unlock_range(owner, &range);
+            state.insert_range(RangeLock {
+                owner,
+                lock_type,
+                range: range.clone(),
+            });
+            drop(state);
+            // Replacing a write lock may have released bytes others are
+            // waiting for.
+            self.waiters.wake();
+            Ok(())
+        })
+    }
+}
@@ -1,5 +1,60 @@
+mod dcache;
 mod file;
 mod fs;
+mod lock;
+mod mount;
+mod perm;
+mod pipe;
+mod space;
+mod watch;
+mod writeback;
+mod xattr;
 
+use axfs_ng_vfs::{VfsError, VfsResult};
+use axio::PollSet;
+pub(crate) use dcache::invalidate_entry;
+pub use dcache::{
+    DentryCacheStats, clear_dentry_cache, dentry_cache_limit, dentry_cache_stats,
+    set_dentry_cache_limit,
+};
 pub use file::*;
 pub use fs::*;
+pub use lock::*;
+pub use mount::*;
+pub use perm::*;
+pub use pipe::{PIPE_BUF, PipeEnd, pipe};
+pub use space::FallocateFlags;
+pub use watch::{WatchEvent, WatchMask, Watcher};
+pub use writeback::*;
+pub use xattr::*;
+
+/// Calls `f` until it returns something other than `EAGAIN`, waiting for
+/// `poll` to be woken up in between if `wait` is `true`.
+///
+/// Without `multitask` there is no other task to wake `poll` up, so waiting
+/// fails with `EDEADLK` instead.
+pub(crate) fn wait_until<R>(
+    poll: &PollSet,
+    wait: bool,
+    mut f: impl FnMut() -> VfsResult<R>,
+) -> VfsResult<R> {
+    match f() {
+        Err(VfsError::EAGAIN) if wait => {}
+        result => return result,
+    }
+    #[cfg(feature = "multitask")]
+    {
+        axtask::future::block_on_interruptible(core::future::poll_fn(|cx| {
+            poll.register(cx.waker());
+            match f() {
+                Err(VfsError::EAGAIN) => core::task::Poll::Pending,
+                result => core::task::Poll::Ready(result),
+            }
+        }))
+    }
+    #[cfg(not(feature = "multitask"))]
+    {
+        let _ = poll;
+        Err(VfsError::EDEADLK)
+    }
+}
@@ -0,0 +1,294 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axfs_ng_vfs::{
+    DirEntry, Filesystem, FilesystemOps, Location, Mountpoint, StatFs, VfsError, VfsResult,
+    path::{Path, PathBuf},
+};
+use spin::Mutex;
+
+use super::{FsContext, clear_dentry_cache};
+
+bitflags::bitflags! {
+    /// Per-mount flags, using the values of Linux `MS_*` constants.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct MountFlags: u32 {
+        /// Disallows writes to the mounted filesystem.
+        const RDONLY = 1;
+        /// Ignores set-user-ID and set-group-ID bits of files.
+        const NOSUID = 2;
+        /// Disallows executing files.
+        const NOEXEC = 8;
+    }
+}
+
+/// Information about a mounted filesystem, as returned by [`mounts`].
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    /// The source of the mount, e.g. a device name or the source path of a
+    /// bind mount.
+    pub source: String,
+    /// The absolute path the filesystem is mounted on.
+    pub target: PathBuf,
+    /// The name of the filesystem type.
+    pub fs_type: String,
+    pub flags: MountFlags,
+}
+
+struct MountEntry {
+    source: String,
+    /// The directory covered by the mount, `None` for the root mount.
+    target: Option<Location>,
+    mountpoint: Arc<Mountpoint>,
+    flags: MountFlags,
+    /// The number of [`MountRef`]s to the mount.
+    users: Arc<AtomicUsize>,
+}
+
+static MOUNTS: Mutex<Vec<MountEntry>> = Mutex::new(Vec::new());
+
+/// Creates the root mount of the namespace, returning its root directory.
+pub fn mount_root(source: impl Into<String>, fs: &Filesystem, flags: MountFlags) -> Location {
+    let mountpoint = Mountpoint::new_root(fs);
+    let root = mountpoint.root_location();
+    MOUNTS.lock().push(MountEntry {
+        source: source.into(),
+        target: None,
+        mountpoint,
+        flags,
+        users: Arc::default(),
+    });
+    root
+}
+
+/// Lists all mounted filesystems, in the order they were mounted.
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNTS
+        .lock()
+        .iter()
+        .map(|entry| MountInfo {
+            source: entry.source.clone(),
+            target: match &entry.target {
+                Some(target) => target.absolute_path().unwrap_or_default(),
+                None => PathBuf::from("/"),
+            },
+            fs_type: entry
+                .mountpoint
+                .root_location()
+                .filesystem()
+                .name()
+                .to_string(),
+            flags: entry.flags,
+        })
+        .collect()
+}
+
+/// Returns the flags of the mount `loc` belongs to.
+///
+/// Locations on mounts not created through this module have no flags.
+pub fn mount_flags(loc: &Location) -> MountFlags {
+    MOUNTS
+        .lock()
+        .iter()
+        .find(|entry| Arc::ptr_eq(&entry.mountpoint, loc.mountpoint()))
+        .map_or(MountFlags::empty(), |entry| entry.flags)
+}
+
+/// Returns the statistics of the filesystem `loc` is on, with the flags of
+/// the mount it belongs to.
+pub fn statfs(loc: &Location) -> VfsResult<StatFs> {
+    let mut stat = loc.filesystem().stat()?;
+    stat.mount_flags = mount_flags(loc).bits() as _;
+    Ok(stat)
+}
+
+/// Keeps the mount a location belongs to busy while alive, so that it cannot
+/// be unmounted.
+///
+/// Open files and the directories of contexts hold one.
+#[derive(Debug, Default)]
+pub(crate) struct MountRef(Option<Arc<AtomicUsize>>);
+
+impl MountRef {
+    pub fn new(loc: &Location) -> Self {
+        let users = MOUNTS
+            .lock()
+            .iter()
+            .find(|entry| Arc::ptr_eq(&entry.mountpoint, loc.mountpoint()))
+            .map(|entry| {
+                entry.users.fetch_add(1, Ordering::Relaxed);
+                entry.users.clone()
+            });
+        Self(users)
+    }
+}
+
+impl Clone for MountRef {
+    fn clone(&self) -> Self {
+        if let Some(users) = &self.0 {
+            users.fetch_add(1, Ordering::Relaxed);
+        }
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for MountRef {
+    fn drop(&mut self) {
+        if let Some(users) = &self.0 {
+            users.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+/// Flushes all mounted filesystems.
+pub(crate) fn flush_mounts() -> VfsResult<()> {
+    let roots: Vec<_> = MOUNTS
+        .lock()
+        .iter()
+        .map(|entry| entry.mountpoint.root_location())
+        .collect();
+    for root in roots {
+        root.filesystem().flush()?;
+    }
+    Ok(())
+}
+
+/// Fails with `EROFS` if `loc` is on a read-only mount.
+pub(crate) fn check_writable(loc: &Location) -> VfsResult<()> {
+    if mount_flags(loc).contains(MountFlags::RDONLY) {
+        Err(VfsError::EROFS)
+    } else {
+        Ok(())
+    }
+}
+
+/// Exposes a directory of another filesystem as a filesystem of its own, for
+/// bind mounts.
+struct BindFilesystem {
+    fs: Filesystem,
+    root: DirEntry,
+}
+
+unsafe impl Send for BindFilesystem {}
+
+unsafe impl Sync for BindFilesystem {}
+
+impl FilesystemOps for BindFilesystem {
+    fn name(&self) -> &str {
+        self.fs.name()
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        self.root.clone()
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        self.fs.stat()
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        self.fs.flush()
+    }
+}
+
+impl FsContext {
+    /// Fails with `EPERM` unless the context has root credentials, which
+    /// changing the mount table requires.
+    fn check_mount_permission(&self) -> VfsResult<()> {
+        if self.credentials().is_root() {
+            Ok(())
+        } else {
+            Err(VfsError::EPERM)
+        }
+    }
+
+    /// Mounts `fs` on the directory at `target`, returning the root directory
+    /// of the new mount.
+    pub fn mount(
+        &self,
+        source: impl Into<String>,
+        target: impl AsRef<Path>,
+        fs: &Filesystem,
+        flags: MountFlags,
+    ) -> VfsResult<Location> {
+        self.check_mount_permission()?;
+        let target = self.resolve(target)?;
+        target.check_is_dir()?;
+        let mountpoint = target.mount(fs)?;
+        let root = mountpoint.root_location();
+        MOUNTS.lock().push(MountEntry {
+            source: source.into(),
+            target: Some(target),
+            mountpoint,
+            flags,
+            users: Arc::default(),
+        });
+        // Lookups of `target` now find the root of the new mount.
+        clear_dentry_cache();
+        Ok(root)
+    }
+
+    /// Makes the directory at `source` also visible at `target`.
+    ///
+    /// Only the mount `source` is on is bound, mounts below it are not
+    /// visible through `target`.
+    pub fn bind_mount(
+        &self,
+        source: impl AsRef<Path>,
+        target: impl AsRef<Path>,
+        flags: MountFlags,
+    ) -> VfsResult<Location> {
+        let source = self.resolve(source)?;
+        source.check_is_dir()?;
+        let fs = Filesystem::new(Arc::new(BindFilesystem {
+            fs: source.filesystem().clone(),
+            root: source.entry().clone(),
+        }));
+        self.mount(source.absolute_path()?.to_string(), target, &fs, flags)
+    }
+
+    /// Unmounts the filesystem mounted at `target`.
+    ///
+    /// Fails with `EBUSY` if other filesystems are mounted below it, or if it
+    /// is still in use by an open file or as a directory of some context.
+    pub fn umount(&self, target: impl AsRef<Path>) -> VfsResult<()> {
+        self.check_mount_permission()?;
+        let root = self.resolve(target)?;
+        if !root.is_root_of_mount() {
+            return Err(VfsError::EINVAL);
+        }
+        root.filesystem().flush()?;
+        // Cached lookups hold locations on the mount.
+        clear_dentry_cache();
+
+        let mut mounts = MOUNTS.lock();
+        let index = mounts
+            .iter()
+            .position(|entry| Arc::ptr_eq(&entry.mountpoint, root.mountpoint()))
+            .ok_or(VfsError::EINVAL)?;
+        let entry = &mounts[index];
+        if entry.target.is_none() {
+            return Err(VfsError::EBUSY);
+        }
+        if mounts.iter().any(|other| {
+            other
+                .target
+                .as_ref()
+                .is_some_and(|target| Arc::ptr_eq(target.mountpoint(), &entry.mountpoint))
+        }) {
+            return Err(VfsError::EBUSY);
+        }
+        // New references are only taken with the mount table locked.
+        if entry.users.load(Ordering::Acquire) > 0 {
+            return Err(VfsError::EBUSY);
+        }
+
+        root.unmount()?;
+        mounts.remove(index);
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! POSIX permission checks.
+//!
+//! Every [`FsContext`] carries the [`Credentials`] its operations are
+//! checked against. The credentials of root bypass the permission bits,
+//! except that executing a file still requires one of its execute bits.
+//! Files on a [`MountFlags::NOEXEC`] mount cannot be executed by anyone.
+//!
+//! [`FsContext`]: super::FsContext
+
+use alloc::vec::Vec;
+
+use axfs_ng_vfs::{Location, NodeType, VfsError, VfsResult};
+
+use super::{MountFlags, mount_flags};
+
+const S_ISUID: u16 = 0o4000;
+const S_ISGID: u16 = 0o2000;
+const S_ISVTX: u16 = 0o1000;
+
+bitflags::bitflags! {
+    /// Kinds of access to a file, using the values of Linux `*_OK` constants.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct AccessMode: u32 {
+        /// Execute access, or search access for a directory.
+        const EXECUTE = 0x1;
+        const WRITE = 0x2;
+        const READ = 0x4;
+    }
+}
+
+/// The user and groups operations are performed as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups.
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Returns the credentials of root, which are granted every access.
+    pub const fn root() -> Self {
+        Self {
+            uid: 0,
+            gid: 0,
+            groups: Vec::new(),
+        }
+    }
+
+    pub const fn new(uid: u32, gid: u32) -> Self {
+        Self {
+            uid,
+            gid,
+            groups: Vec::new(),
+        }
+    }
+
+    /// Sets the supplementary groups.
+    pub fn with_groups(mut self, groups: Vec<u32>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// Returns whether the credentials belong to the group `gid`.
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    /// Fails with `EACCES` unless `loc` grants `mode` access.
+    ///
+    /// This does not read the metadata of `loc` for root, unless it executes
+    /// a file, so that path resolution as root does no I/O on cached entries.
+    pub fn check_access(&self, loc: &Location, mode: AccessMode) -> VfsResult<()> {
+        let exec_file = mode.contains(AccessMode::EXECUTE) && !loc.is_dir();
+        if exec_file && mount_flags(loc).contains(MountFlags::NOEXEC) {
+            return Err(VfsError::EACCES);
+        }
+        if self.is_root() && !exec_file {
+            return Ok(());
+        }
+        let meta = loc.metadata()?;
+        let bits = meta.mode.bits();
+        if self.is_root() {
+            // Root may execute a file only if anyone may.
+            if bits & 0o111 == 0 {
+                return Err(VfsError::EACCES);
+            }
+            return Ok(());
+        }
+        let class = if self.uid == meta.uid {
+            bits >> 6
+        } else if self.in_group(meta.gid) {
+            bits >> 3
+        } else {
+            bits
+        };
+        let granted = AccessMode::from_bits_truncate((class & 0o7) as u32);
+        if granted.contains(mode) {
+            Ok(())
+        } else {
+            Err(VfsError::EACCES)
+        }
+    }
+
+    /// Returns the credentials a program executed from the file at `loc` runs
+    /// with, failing with `EACCES` if it cannot be executed.
+    ///
+    /// The set-user-ID and set-group-ID bits of the file are honored unless
+    /// it is on a [`MountFlags::NOSUID`] mount.
+    pub fn exec_credentials(&self, loc: &Location) -> VfsResult<Self> {
+        if loc.node_type() != NodeType::RegularFile {
+            return Err(VfsError::EACCES);
+        }
+        self.check_access(loc, AccessMode::EXECUTE)?;
+        let mut cred = self.clone();
+        if mount_flags(loc).contains(MountFlags::NOSUID) {
+            return Ok(cred);
+        }
+        let meta = loc.metadata()?;
+        let bits = meta.mode.bits();
+        if bits & S_ISUID != 0 {
+            cred.uid = meta.uid;
+        }
+        // Without group execute permission the bit marks mandatory locking.
+        if bits & S_ISGID != 0 && bits & 0o010 != 0 {
+            cred.gid = meta.gid;
+        }
+        Ok(cred)
+    }
+
+    /// Fails with `EPERM` if `dir` is sticky and `entry` in it is neither
+    /// owned by these credentials nor in a directory they own.
+    pub fn check_sticky(&self, dir: &Location, entry: &Location) -> VfsResult<()> {
+        if self.is_root() {
+            return Ok(());
+        }
+        let dir_meta = dir.metadata()?;
+        if dir_meta.mode.bits() & S_ISVTX == 0 || dir_meta.uid == self.uid {
+            return Ok(());
+        }
+        if entry.metadata()?.uid == self.uid {
+            Ok(())
+        } else {
+            Err(VfsError::EPERM)
+        }
+    }
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::root()
+    }
+}
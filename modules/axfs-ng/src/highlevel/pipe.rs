@@ -0,0 +1,271 @@
+//! Pipes, either anonymous or attached to a FIFO node.
+
+use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
+
+use axfs_ng_vfs::{Location, VfsError, VfsResult};
+use axio::{Buf, BufMut, IoEvents, PollSet, Pollable};
+use spin::Mutex;
+
+use super::wait_until;
+
+/// Maximum number of bytes buffered in a pipe.
+const PIPE_CAPACITY: usize = 65536;
+
+/// Writes of at most this many bytes are atomic, i.e. never interleaved with
+/// other writes.
+pub const PIPE_BUF: usize = 4096;
+
+#[derive(Default)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    /// The number of read ends ever opened, to tell whether a reader came
+    /// and left while a writer was waiting for one.
+    opened_readers: u64,
+    /// The number of write ends ever opened.
+    opened_writers: u64,
+}
+
+/// The buffer shared by the ends of a pipe.
+struct Pipe {
+    state: Mutex<PipeState>,
+    /// Serializes the readers, which copy data out of the buffer before
+    /// consuming it, with `state` unlocked.
+    reader: axsync::Mutex<()>,
+    /// Serializes the writers, so that the space they find in the buffer
+    /// is still there once they have copied in their data with `state`
+    /// unlocked.
+    writer: axsync::Mutex<()>,
+    /// Woken up on every change of the state.
+    poll: PollSet,
+}
+
+impl Pipe {
+    fn new() -> Self {
+        Self {
+            state: Mutex::default(),
+            reader: axsync::Mutex::new(()),
+            writer: axsync::Mutex::new(()),
+            poll: PollSet::new(),
+        }
+    }
+}
+
+/// One end of a pipe.
+///
+/// Reads block while the pipe is empty and return `0` once all write ends
+/// are dropped. Writes block while the pipe is full and fail with `EPIPE`
+/// once all read ends are dropped.
+pub struct PipeEnd {
+    pipe: Arc<Pipe>,
+    readable: bool,
+    writable: bool,
+    nonblocking: AtomicBool,
+}
+
+impl PipeEnd {
+    fn new(pipe: Arc<Pipe>, readable: bool, writable: bool) -> Self {
+        let mut state = pipe.state.lock();
+        if readable {
+            state.readers += 1;
+            state.opened_readers += 1;
+        }
+        if writable {
+            state.writers += 1;
+            state.opened_writers += 1;
+        }
+        drop(state);
+        pipe.poll.wake();
+        Self {
+            pipe,
+            readable,
+            writable,
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+
+    /// Opens the pipe of a FIFO node.
+    ///
+    /// Unless `nonblocking` is `true`, opening only one end waits until the
+    /// other end is opened too. Otherwise, opening the write end fails with
+    /// `ENXIO` if the read end is not open.
+    pub(crate) fn open_fifo(
+        loc: &Location,
+        readable: bool,
+        writable: bool,
+        nonblocking: bool,
+    ) -> VfsResult<Self> {
+        let pipe = loc.user_data().get_or_insert_with(Pipe::new);
+        let (opened_readers, opened_writers) = {
+            let state = pipe.state.lock();
+            (state.opened_readers, state.opened_writers)
+        };
+        let end = Self::new(pipe, readable, writable);
+        end.set_nonblocking(nonblocking);
+        let pipe = &end.pipe;
+        match (readable, writable) {
+            (true, false) if !nonblocking => wait_until(&pipe.poll, true, || {
+                let state = pipe.state.lock();
+                if state.writers > 0 || state.opened_writers != opened_writers {
+                    Ok(())
+                } else {
+                    Err(VfsError::EAGAIN)
+                }
+            })?,
+            (false, true) => wait_until(&pipe.poll, !nonblocking, || {
+                let state = pipe.state.lock();
+                if state.readers > 0 || state.opened_readers != opened_readers {
+                    Ok(())
+                } else if nonblocking {
+                    Err(VfsError::ENXIO)
+                } else {
+                    Err(VfsError::EAGAIN)
+                }
+            })?,
+            _ => {}
+        }
+        Ok(end)
+    }
+
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether reads and writes fail with `EAGAIN` instead of blocking.
+    pub fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+
+    /// Reads from the pipe, returns `0` if the pipe is empty and has no write
+    /// ends.
+    pub fn read(&self, dst: &mut impl BufMut) -> VfsResult<usize> {
+        if !self.readable {
+            return Err(VfsError::EBADF);
+        }
+        if dst.remaining_mut() == 0 {
+            return Ok(0);
+        }
+        wait_until(&self.pipe.poll, !self.is_nonblocking(), || {
+            let _reader = self.pipe.reader.lock();
+            let state = self.pipe.state.lock();
+            if state.buf.is_empty() {
+                return if state.writers == 0 {
+                    Ok(0)
+                } else {
+                    Err(VfsError::EAGAIN)
+                };
+            }
+            let len = dst.remaining_mut().min(state.buf.len());
+            let chunk = state.buf.range(..len).copied().collect::<Vec<_>>();
+            drop(state);
+            // The data is only consumed once copied, so that none is lost if
+            // copying fails. No other reader can consume it meanwhile.
+            dst.write(&chunk)?;
+            self.pipe.state.lock().buf.drain(..len);
+            self.pipe.poll.wake();
+            Ok(len)
+        })
+    }
+
+    /// Writes to the pipe, returns the number of bytes written, which is
+    /// less than `src.remaining()` only if the end is nonblocking or the
+    /// wait is interrupted.
+    ///
+    /// Writes of at most [`PIPE_BUF`] bytes are atomic.
+    pub fn write(&self, src: &mut impl Buf) -> VfsResult<usize> {
+        if !self.writable {
+            return Err(VfsError::EBADF);
+        }
+        let nonblocking = self.is_nonblocking();
+        let mut written = 0;
+        let result = wait_until(&self.pipe.poll, !nonblocking, || {
+            let _writer = self.pipe.writer.lock();
+            let state = self.pipe.state.lock();
+            if state.readers == 0 {
+                return Err(VfsError::EPIPE);
+            }
+            let remaining = src.remaining();
+            let space = PIPE_CAPACITY - state.buf.len();
+            if remaining > 0 && (space == 0 || (remaining <= PIPE_BUF && space < remaining)) {
+                return Err(VfsError::EAGAIN);
+            }
+            drop(state);
+            let len = remaining.min(space);
+            let mut chunk = vec![0; len];
+            src.read(&mut chunk)?;
+            self.pipe.state.lock().buf.extend(chunk);
+            self.pipe.poll.wake();
+            written += len;
+            if src.remaining() > 0 && !nonblocking {
+                Err(VfsError::EAGAIN)
+            } else {
+                Ok(())
+            }
+        });
+        match result {
+            Ok(()) => Ok(written),
+            Err(_) if written > 0 => Ok(written),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Clone for PipeEnd {
+    fn clone(&self) -> Self {
+        let end = Self::new(self.pipe.clone(), self.readable, self.writable);
+        end.set_nonblocking(self.is_nonblocking());
+        end
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let mut state = self.pipe.state.lock();
+        if self.readable {
+            state.readers -= 1;
+        }
+        if self.writable {
+            state.writers -= 1;
+        }
+        if state.readers == 0 && state.writers == 0 {
+            // The data of a FIFO does not outlive its last open end.
+            state.buf = VecDeque::new();
+        }
+        drop(state);
+        self.pipe.poll.wake();
+    }
+}
+
+impl Pollable for PipeEnd {
+    fn poll(&self) -> IoEvents {
+        let state = self.pipe.state.lock();
+        let mut events = IoEvents::empty();
+        if self.readable {
+            events.set(IoEvents::IN, !state.buf.is_empty());
+            events.set(IoEvents::HUP, state.writers == 0);
+        }
+        if self.writable {
+            events.set(IoEvents::OUT, PIPE_CAPACITY - state.buf.len() >= PIPE_BUF);
+            events.set(IoEvents::ERR, state.readers == 0);
+        }
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, _events: IoEvents) {
+        self.pipe.poll.register(context.waker());
+    }
+}
+
+/// Creates an anonymous pipe, returning its read end and write end.
+pub fn pipe() -> (PipeEnd, PipeEnd) {
+    let pipe = Arc::new(Pipe::new());
+    (
+        PipeEnd::new(pipe.clone(), true, false),
+        PipeEnd::new(pipe, false, true),
+    )
+}
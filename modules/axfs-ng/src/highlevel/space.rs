@@ -0,0 +1,85 @@
+//! Space management of regular files: preallocation, holes and zeroing.
+//!
+//! tmpfs and ext4 support holes through [`SpaceOps`]. Other filesystems
+//! (e.g. FAT) do not support punching holes, store zeros when a range is
+//! zeroed, and report their files as a single extent of data.
+//!
+//! [`SpaceOps`]: crate::fs::SpaceOps
+
+use core::ops::Range;
+
+use axfs_ng_vfs::{FileNode, VfsError, VfsResult};
+
+use crate::fs::space_ops;
+
+bitflags::bitflags! {
+    /// Flags of [`File::fallocate`], using the values of Linux `FALLOC_FL_*`
+    /// constants.
+    ///
+    /// [`File::fallocate`]: super::File::fallocate
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct FallocateFlags: u32 {
+        /// Does not change the size of the file.
+        const KEEP_SIZE = 0x1;
+        /// Deallocates the range, which then reads as zeros. Requires
+        /// `KEEP_SIZE`.
+        const PUNCH_HOLE = 0x2;
+        /// Zeros the range.
+        const ZERO_RANGE = 0x10;
+    }
+}
+
+impl FallocateFlags {
+    /// Checks that the flags form a supported mode.
+    pub(crate) fn validate(self) -> VfsResult<()> {
+        if self.contains(Self::PUNCH_HOLE)
+            && (!self.contains(Self::KEEP_SIZE) || self.contains(Self::ZERO_RANGE))
+        {
+            return Err(VfsError::EOPNOTSUPP);
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the filesystem of `file` supports the mode of `flags`, before
+/// the page cache is changed.
+pub(crate) fn check_supported(file: &FileNode, flags: FallocateFlags) -> VfsResult<()> {
+    if flags.contains(FallocateFlags::PUNCH_HOLE) && space_ops(file).is_none() {
+        return Err(VfsError::EOPNOTSUPP);
+    }
+    Ok(())
+}
+
+/// Makes `range` of a file read as zeros, without changing its size.
+///
+/// The range is deallocated if the filesystem supports holes, and
+/// overwritten with zeros otherwise.
+pub(crate) fn clear_range(file: &FileNode, range: Range<u64>) -> VfsResult<()> {
+    if let Some(ops) = space_ops(file) {
+        return ops.punch_hole(range);
+    }
+    const ZEROS: [u8; 4096] = [0; 4096];
+    let mut pos = range.start;
+    let end = range.end.min(file.len()?);
+    while pos < end {
+        let len = ((end - pos) as usize).min(ZEROS.len());
+        pos += file.write_at(&ZEROS[..len], pos)? as u64;
+    }
+    Ok(())
+}
+
+/// Returns the first offset at or after `offset` that is in data if `data`
+/// is `true`, or in a hole otherwise, as with `SEEK_DATA` and `SEEK_HOLE`.
+pub(crate) fn seek(file: &FileNode, offset: u64, data: bool) -> VfsResult<u64> {
+    if let Some(ops) = space_ops(file) {
+        return ops.seek_data(offset, data);
+    }
+    let len = file.len()?;
+    if offset >= len {
+        Err(VfsError::ENXIO)
+    } else if data {
+        Ok(offset)
+    } else {
+        Ok(len)
+    }
+}
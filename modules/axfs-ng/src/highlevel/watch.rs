@@ -0,0 +1,274 @@
+//! Filesystem change notifications, similar to Linux `inotify`.
+//!
+//! A [`Watcher`] watches any number of files and directories, and queues the
+//! events raised on them by the high-level operations of this crate. Events
+//! caused by lower-level [`Location`] operations are not reported.
+
+use alloc::{
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicU32, Ordering},
+    task::Context,
+};
+
+use axfs_ng_vfs::{Location, VfsError, VfsResult};
+use axio::{IoEvents, PollSet, Pollable};
+use spin::Mutex;
+
+bitflags::bitflags! {
+    /// Kinds of watch events, using the values of Linux `IN_*` constants.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct WatchMask: u32 {
+        /// A file was written to.
+        const MODIFY = 0x2;
+        /// The metadata of a file changed, e.g. its link count.
+        const ATTRIB = 0x4;
+        /// A file was renamed out of the watched directory.
+        const MOVED_FROM = 0x40;
+        /// A file was renamed into the watched directory.
+        const MOVED_TO = 0x80;
+        /// A file was created in the watched directory.
+        const CREATE = 0x100;
+        /// A file was removed from the watched directory.
+        const DELETE = 0x200;
+        /// The watched file itself was removed.
+        const DELETE_SELF = 0x400;
+        /// The watched file itself was renamed.
+        const MOVE_SELF = 0x800;
+        /// Events were dropped because the queue was full.
+        const Q_OVERFLOW = 0x4000;
+        /// The subject of the event is a directory.
+        const ISDIR = 0x4000_0000;
+    }
+}
+
+/// An event queued by a [`Watcher`].
+#[derive(Debug, Clone)]
+pub struct WatchEvent {
+    /// The watch descriptor returned by [`Watcher::add_watch`].
+    pub wd: u32,
+    pub mask: WatchMask,
+    /// Identifies the [`MOVED_FROM`] and [`MOVED_TO`] events of the same
+    /// rename, `0` for other events.
+    ///
+    /// [`MOVED_FROM`]: WatchMask::MOVED_FROM
+    /// [`MOVED_TO`]: WatchMask::MOVED_TO
+    pub cookie: u32,
+    /// The name of the file within the watched directory, or `None` if the
+    /// event concerns the watched file itself.
+    pub name: Option<String>,
+}
+
+/// Maximum number of events queued by a watcher before events are dropped.
+const MAX_QUEUED_EVENTS: usize = 16384;
+
+struct WatcherInner {
+    events: Mutex<VecDeque<WatchEvent>>,
+    poll: PollSet,
+}
+
+impl WatcherInner {
+    fn push(&self, event: WatchEvent) {
+        let mut events = self.events.lock();
+        if events.len() >= MAX_QUEUED_EVENTS {
+            if events
+                .back()
+                .is_some_and(|it| it.mask == WatchMask::Q_OVERFLOW)
+            {
+                return;
+            }
+            events.push_back(WatchEvent {
+                wd: u32::MAX,
+                mask: WatchMask::Q_OVERFLOW,
+                cookie: 0,
+                name: None,
+            });
+        } else {
+            events.push_back(event);
+        }
+        drop(events);
+        self.poll.wake();
+    }
+}
+
+struct Watch {
+    watcher: Weak<WatcherInner>,
+    wd: u32,
+    mask: WatchMask,
+}
+
+/// The watches on a file, kept in the user data of its location.
+#[derive(Default)]
+struct WatchList(Mutex<Vec<Watch>>);
+
+/// A queue of events raised on the watched files.
+pub struct Watcher {
+    inner: Arc<WatcherInner>,
+    watches: Mutex<BTreeMap<u32, Location>>,
+    next_wd: AtomicU32,
+}
+
+impl Default for Watcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Watcher {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(WatcherInner {
+                events: Mutex::new(VecDeque::new()),
+                poll: PollSet::new(),
+            }),
+            watches: Mutex::new(BTreeMap::new()),
+            next_wd: AtomicU32::new(1),
+        }
+    }
+
+    /// Watches `loc` for the events in `mask`, returning a watch descriptor
+    /// that identifies the watch in the queued events.
+    ///
+    /// If `loc` is already watched, its mask is replaced and the existing
+    /// descriptor is returned.
+    pub fn add_watch(&self, loc: &Location, mask: WatchMask) -> u32 {
+        let list = loc.user_data().get_or_insert_with(WatchList::default);
+        let mut list = list.0.lock();
+        list.retain(|watch| watch.watcher.strong_count() > 0);
+        if let Some(watch) = list
+            .iter_mut()
+            .find(|watch| watch.watcher.as_ptr() == Arc::as_ptr(&self.inner))
+        {
+            watch.mask = mask;
+            return watch.wd;
+        }
+        let wd = self.next_wd.fetch_add(1, Ordering::Relaxed);
+        list.push(Watch {
+            watcher: Arc::downgrade(&self.inner),
+            wd,
+            mask,
+        });
+        self.watches.lock().insert(wd, loc.clone());
+        wd
+    }
+
+    /// Removes a watch added by [`Watcher::add_watch`].
+    pub fn remove_watch(&self, wd: u32) -> VfsResult<()> {
+        let loc = self.watches.lock().remove(&wd).ok_or(VfsError::EINVAL)?;
+        if let Some(list) = loc.user_data().get::<WatchList>() {
+            list.0.lock().retain(|watch| watch.wd != wd);
+        }
+        Ok(())
+    }
+
+    /// Removes the oldest queued event.
+    pub fn read_event(&self) -> Option<WatchEvent> {
+        self.inner.events.lock().pop_front()
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let wds: Vec<u32> = self.watches.get_mut().keys().copied().collect();
+        for wd in wds {
+            let _ = self.remove_watch(wd);
+        }
+    }
+}
+
+impl Pollable for Watcher {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, !self.inner.events.lock().is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.inner.poll.register(context.waker());
+        }
+    }
+}
+
+/// Returns whether any watcher watches `loc`.
+pub(crate) fn is_watched(loc: &Location) -> bool {
+    loc.user_data()
+        .get::<WatchList>()
+        .is_some_and(|list| !list.0.lock().is_empty())
+}
+
+fn notify(loc: &Location, mask: WatchMask, cookie: u32, name: Option<&str>) {
+    let Some(list) = loc.user_data().get::<WatchList>() else {
+        return;
+    };
+    let mut subject = mask;
+    subject.remove(WatchMask::ISDIR);
+    for watch in list.0.lock().iter() {
+        if !watch.mask.intersects(subject) {
+            continue;
+        }
+        if let Some(watcher) = watch.watcher.upgrade() {
+            watcher.push(WatchEvent {
+                wd: watch.wd,
+                mask,
+                cookie,
+                name: name.map(ToString::to_string),
+            });
+        }
+    }
+}
+
+fn dir_flag(loc: &Location) -> WatchMask {
+    if loc.is_dir() {
+        WatchMask::ISDIR
+    } else {
+        WatchMask::empty()
+    }
+}
+
+/// Raises an event on `loc` itself and on its parent directory.
+pub(crate) fn notify_entry(loc: &Location, mask: WatchMask) {
+    let mask = mask | dir_flag(loc);
+    notify(loc, mask, 0, None);
+    if let Some(parent) = loc.parent() {
+        notify(&parent, mask, 0, Some(loc.name()));
+    }
+}
+
+/// Raises the events of `loc` being created in its parent directory.
+pub(crate) fn notify_create(loc: &Location) {
+    if let Some(parent) = loc.parent() {
+        notify(
+            &parent,
+            WatchMask::CREATE | dir_flag(loc),
+            0,
+            Some(loc.name()),
+        );
+    }
+}
+
+/// Raises the events of `loc` being removed from its parent directory.
+pub(crate) fn notify_delete(loc: &Location) {
+    let dir = dir_flag(loc);
+    notify(loc, WatchMask::DELETE_SELF | dir, 0, None);
+    if let Some(parent) = loc.parent() {
+        notify(&parent, WatchMask::DELETE | dir, 0, Some(loc.name()));
+    }
+}
+
+/// Raises the events of `src_dir/src_name` being renamed to `loc`.
+pub(crate) fn notify_rename(src_dir: &Location, src_name: &str, loc: &Location) {
+    static NEXT_COOKIE: AtomicU32 = AtomicU32::new(1);
+
+    let cookie = NEXT_COOKIE.fetch_add(1, Ordering::Relaxed);
+    let dir = dir_flag(loc);
+    notify(src_dir, WatchMask::MOVED_FROM | dir, cookie, Some(src_name));
+    if let Some(parent) = loc.parent() {
+        notify(&parent, WatchMask::MOVED_TO | dir, cookie, Some(loc.name()));
+    }
+    notify(loc, WatchMask::MOVE_SELF | dir, 0, None);
+}
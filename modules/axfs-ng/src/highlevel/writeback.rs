@@ -0,0 +1,174 @@
+//! Background writeback of dirty pages in the page cache.
+//!
+//! Dirty pages are written back once they have been dirty for longer than
+//! [`WritebackConfig::dirty_expire`], or when too much of the memory is
+//! dirty. Writers that dirty pages faster than they can be written back are
+//! throttled by writing back pages themselves.
+
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use axalloc::{UsageKind, global_allocator};
+use axfs_ng_vfs::VfsResult;
+use axhal::time::monotonic_time;
+use log::warn;
+use spin::Mutex;
+
+use super::{cached_files, flush_mounts};
+use crate::bcache::flush_buffer_cache;
+
+const PAGE_SIZE: usize = 4096;
+
+/// The number of dirty pages in the page cache.
+pub(crate) static DIRTY_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Parameters of the writeback of dirty pages.
+#[derive(Debug, Clone, Copy)]
+pub struct WritebackConfig {
+    /// How often the writeback daemon wakes up.
+    pub interval: Duration,
+    /// How long a page may stay dirty before the daemon writes it back.
+    pub dirty_expire: Duration,
+    /// The percentage of the memory available to the page cache that may be
+    /// dirty before the daemon writes back pages regardless of their age.
+    pub background_ratio: usize,
+    /// The percentage of the memory available to the page cache that may be
+    /// dirty before writers have to write back pages themselves.
+    pub dirty_ratio: usize,
+}
+
+impl Default for WritebackConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            dirty_expire: Duration::from_secs(30),
+            background_ratio: 10,
+            dirty_ratio: 20,
+        }
+    }
+}
+
+static CONFIG: Mutex<Option<WritebackConfig>> = Mutex::new(None);
+
+/// Returns the current writeback parameters.
+pub fn writeback_config() -> WritebackConfig {
+    CONFIG.lock().unwrap_or_default()
+}
+
+/// Sets the writeback parameters, which take effect on the next wakeup of the
+/// daemon.
+pub fn set_writeback_config(config: WritebackConfig) {
+    *CONFIG.lock() = Some(config);
+}
+
+/// Returns the number of dirty pages in the page cache.
+pub fn dirty_pages() -> usize {
+    DIRTY_PAGES.load(Ordering::Relaxed)
+}
+
+/// Returns the number of dirty pages allowed by `ratio`, as a percentage of
+/// the free memory and the memory used by the page cache.
+fn dirty_threshold(ratio: usize) -> usize {
+    let allocator = global_allocator();
+    let cached = allocator.usage_stats().get(UsageKind::PageCache) / PAGE_SIZE;
+    (allocator.available_pages() + cached) * ratio / 100
+}
+
+/// Records the outcome of a step of writeback in `first`, which keeps the
+/// first error, so that a failing file does not hold up the others.
+fn keep_first(first: &mut VfsResult<()>, result: VfsResult<()>) {
+    if let Err(err) = result {
+        if first.is_ok() {
+            *first = Err(err);
+        } else {
+            warn!("Failed to write back: {err:?}");
+        }
+    }
+}
+
+/// Writes back dirty pages of all files, starting with the oldest files,
+/// until at most `target` pages are dirty.
+///
+/// Files that fail to be written back are skipped, and the first error is
+/// returned at the end.
+fn write_back_until(target: usize) -> VfsResult<()> {
+    let mut result = Ok(());
+    for file in cached_files() {
+        if dirty_pages() <= target {
+            break;
+        }
+        keep_first(
+            &mut result,
+            file.write_back_dirty(|_| dirty_pages() > target),
+        );
+    }
+    result
+}
+
+/// Runs one round of background writeback: writes back the pages that have
+/// been dirty for too long, then more pages if the number of dirty pages is
+/// above the background threshold, and finally flushes the metadata of the
+/// mounted filesystems and the buffer cache to the disks.
+///
+/// The round goes on past failures, which are logged, and the first error is
+/// returned at the end.
+pub fn writeback_once() -> VfsResult<()> {
+    let config = writeback_config();
+    let now = monotonic_time();
+    let mut result = Ok(());
+    for file in cached_files() {
+        let written = file.write_back_dirty(|page| {
+            page.dirtied_at()
+                .is_some_and(|at| now.saturating_sub(at) >= config.dirty_expire)
+        });
+        keep_first(&mut result, written);
+    }
+    keep_first(
+        &mut result,
+        write_back_until(dirty_threshold(config.background_ratio)),
+    );
+    keep_first(&mut result, flush_mounts());
+    keep_first(&mut result, flush_buffer_cache());
+    result
+}
+
+/// Throttles a writer that has just dirtied pages.
+///
+/// If the number of dirty pages is above the dirty threshold, the writer
+/// writes back pages until it drops below the background threshold. Must be
+/// called without holding the lock of any page cache.
+pub(crate) fn balance_dirty_pages() -> VfsResult<()> {
+    let config = writeback_config();
+    if dirty_pages() <= dirty_threshold(config.dirty_ratio) {
+        return Ok(());
+    }
+    write_back_until(dirty_threshold(config.background_ratio))
+}
+
+/// Writes back all dirty pages and buffers and flushes all mounted
+/// filesystems, e.g. before shutting down.
+pub fn sync_all() -> VfsResult<()> {
+    write_back_until(0)?;
+    flush_mounts()?;
+    flush_buffer_cache()
+}
+
+/// Spawns the task that periodically writes back dirty pages.
+#[cfg(feature = "multitask")]
+pub fn start_writeback_daemon() {
+    use alloc::string::ToString;
+
+    axtask::spawn(
+        || {
+            loop {
+                axtask::future::block_on(axtask::future::sleep(writeback_config().interval));
+                if let Err(err) = writeback_once() {
+                    warn!("Failed to write back dirty pages: {err:?}");
+                }
+            }
+        },
+        "writeback".to_string(),
+    );
+}
@@ -0,0 +1,138 @@
+//! Extended attributes.
+//!
+//! Attribute names must be prefixed with one of the `user.`, `trusted.`,
+//! `security.` or `system.` namespaces. As on Linux:
+//!
+//! - `user.` attributes require read access to the file to be read, and
+//!   write access to be changed;
+//! - `trusted.` attributes are only visible to and changeable by root;
+//! - `security.` attributes can be read by anyone but only changed by root;
+//! - `system.` attributes, such as ACLs, can be read by anyone but only
+//!   changed by root or the owner of the file.
+//!
+//! tmpfs keeps attributes in memory and ext4 stores them on disk. Other
+//! filesystems fail with `EOPNOTSUPP`, e.g. FAT has no place to store them.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use axfs_ng_vfs::{Location, NodeType, VfsError, VfsResult};
+
+use super::{AccessMode, Credentials, WatchMask, check_writable, watch};
+use crate::fs::{XattrOps, xattr_ops};
+
+/// Maximum length of an attribute name, as with Linux `XATTR_NAME_MAX`.
+const XATTR_NAME_MAX: usize = 255;
+
+/// Maximum length of an attribute value, as with Linux `XATTR_SIZE_MAX`.
+const XATTR_SIZE_MAX: usize = 65536;
+
+const NAMESPACES: [&str; 4] = ["user.", "trusted.", "security.", "system."];
+
+bitflags::bitflags! {
+    /// Flags of [`set_xattr`], using the values of Linux `XATTR_*` constants.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct XattrFlags: u32 {
+        /// Fails with `EEXIST` if the attribute already exists.
+        const CREATE = 0x1;
+        /// Fails with `ENODATA` if the attribute does not exist.
+        const REPLACE = 0x2;
+    }
+}
+
+/// Returns whether `cred` may see the attribute `name` at all.
+fn is_visible(cred: &Credentials, name: &str) -> bool {
+    !name.starts_with("trusted.") || cred.is_root()
+}
+
+/// Checks that `cred` may access the attribute `name` of `loc`, reading it
+/// unless `write` is `true`.
+fn check_name(cred: &Credentials, loc: &Location, name: &str, write: bool) -> VfsResult<()> {
+    if name.is_empty() || name.len() > XATTR_NAME_MAX {
+        return Err(VfsError::ERANGE);
+    }
+    let Some(namespace) = NAMESPACES.iter().find(|it| name.starts_with(**it)) else {
+        return Err(VfsError::EOPNOTSUPP);
+    };
+    match *namespace {
+        // User attributes are only allowed on regular files and directories,
+        // whose access permissions also govern the attributes.
+        "user." => {
+            if !matches!(loc.node_type(), NodeType::RegularFile | NodeType::Directory) {
+                return Err(if write {
+                    VfsError::EPERM
+                } else {
+                    VfsError::ENODATA
+                });
+            }
+            let mode = if write {
+                AccessMode::WRITE
+            } else {
+                AccessMode::READ
+            };
+            cred.check_access(loc, mode)?;
+        }
+        "trusted." if !cred.is_root() => return Err(VfsError::EPERM),
+        "security." if write && !cred.is_root() => return Err(VfsError::EPERM),
+        "system." if write && !cred.is_root() => {
+            if loc.metadata()?.uid != cred.uid {
+                return Err(VfsError::EPERM);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn node(loc: &Location) -> VfsResult<Arc<dyn XattrOps>> {
+    xattr_ops(loc).ok_or(VfsError::EOPNOTSUPP)
+}
+
+/// Returns the value of the extended attribute `name` of `loc`, or fails
+/// with `ENODATA` if it does not exist.
+pub fn get_xattr(cred: &Credentials, loc: &Location, name: &str) -> VfsResult<Vec<u8>> {
+    check_name(cred, loc, name, false)?;
+    node(loc)?.get_xattr(name)
+}
+
+/// Sets the extended attribute `name` of `loc` to `value`.
+pub fn set_xattr(
+    cred: &Credentials,
+    loc: &Location,
+    name: &str,
+    value: &[u8],
+    flags: XattrFlags,
+) -> VfsResult<()> {
+    if flags.contains(XattrFlags::CREATE | XattrFlags::REPLACE) {
+        return Err(VfsError::EINVAL);
+    }
+    check_name(cred, loc, name, true)?;
+    if value.len() > XATTR_SIZE_MAX {
+        return Err(VfsError::E2BIG);
+    }
+    check_writable(loc)?;
+    node(loc)?.set_xattr(
+        name,
+        value,
+        flags.contains(XattrFlags::CREATE),
+        flags.contains(XattrFlags::REPLACE),
+    )?;
+    watch::notify_entry(loc, WatchMask::ATTRIB);
+    Ok(())
+}
+
+/// Returns the names of the extended attributes of `loc` visible to `cred`.
+pub fn list_xattr(cred: &Credentials, loc: &Location) -> VfsResult<Vec<String>> {
+    let mut names = node(loc)?.list_xattr()?;
+    names.retain(|name| is_visible(cred, name));
+    Ok(names)
+}
+
+/// Removes the extended attribute `name` of `loc`, or fails with `ENODATA`
+/// if it does not exist.
+pub fn remove_xattr(cred: &Credentials, loc: &Location, name: &str) -> VfsResult<()> {
+    check_name(cred, loc, name, true)?;
+    check_writable(loc)?;
+    node(loc)?.remove_xattr(name)?;
+    watch::notify_entry(loc, WatchMask::ATTRIB);
+    Ok(())
+}
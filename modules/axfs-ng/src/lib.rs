@@ -4,6 +4,7 @@
 
 extern crate alloc;
 
+pub mod bcache;
 mod disk;
 pub mod fs;
 mod highlevel;
@@ -0,0 +1,182 @@
+//! Helpers shared by the tests.
+
+#![allow(dead_code)]
+
+use std::{alloc::Layout, sync::Once};
+
+use axdriver::prelude::{
+    AxBlockDevice, BaseDriverOps, BlockDriverOps, DevError, DevResult, DeviceType,
+};
+use axfs_ng::{FsContext, MountFlags, OpenOptions, bcache::CachedDisk, fs::tmpfs::TmpFilesystem};
+use axfs_ng_vfs::VfsResult;
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// Memory given to the allocator of `axfs-ng`, which holds the page cache
+/// and the buffer cache.
+const HEAP_SIZE: usize = 64 << 20;
+
+/// Initializes the allocator once for the whole test binary.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let layout = Layout::from_size_align(HEAP_SIZE, 4096).unwrap();
+        // SAFETY: the layout has a non-zero size.
+        let heap = unsafe { std::alloc::alloc(layout) };
+        assert!(!heap.is_null(), "failed to allocate {HEAP_SIZE} bytes");
+        axalloc::global_init(heap as usize, HEAP_SIZE);
+    });
+}
+
+/// A disk kept in memory.
+pub struct MemDisk {
+    data: Vec<u8>,
+}
+
+impl MemDisk {
+    /// Creates a zeroed disk of `num_blocks` blocks.
+    pub fn new(num_blocks: usize) -> Self {
+        Self {
+            data: vec![0; num_blocks * BLOCK_SIZE],
+        }
+    }
+
+    /// Creates a disk holding a copy of the image at `path`.
+    pub fn from_image(path: &str) -> Self {
+        let data = std::fs::read(path)
+            .unwrap_or_else(|err| panic!("{path}: {err}, see resources/make_fs.sh"));
+        Self { data }
+    }
+
+    /// Returns the disk as a block device with a buffer cache.
+    pub fn into_disk(self) -> CachedDisk {
+        CachedDisk::new(AxBlockDevice::new(Box::new(self)))
+    }
+
+    /// Returns the contents of the block `block_id`.
+    pub fn block(&mut self, block_id: u64) -> &mut [u8] {
+        let start = block_id as usize * BLOCK_SIZE;
+        &mut self.data[start..start + BLOCK_SIZE]
+    }
+
+    /// Returns the contents of the disk from byte `offset`.
+    pub fn bytes(&mut self, offset: usize) -> &mut [u8] {
+        &mut self.data[offset..]
+    }
+}
+
+impl BaseDriverOps for MemDisk {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+
+    fn device_name(&self) -> &str {
+        "memdisk"
+    }
+}
+
+impl BlockDriverOps for MemDisk {
+    fn num_blocks(&self) -> u64 {
+        (self.data.len() / BLOCK_SIZE) as u64
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        let start = block_id as usize * BLOCK_SIZE;
+        let src = self
+            .data
+            .get(start..start + buf.len())
+            .ok_or(DevError::InvalidParam)?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        let start = block_id as usize * BLOCK_SIZE;
+        self.data
+            .get_mut(start..start + buf.len())
+            .ok_or(DevError::InvalidParam)?
+            .copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> DevResult {
+        Ok(())
+    }
+}
+
+/// Returns a disk holding an empty FAT12 filesystem of 2 MiB.
+pub fn fat_disk() -> MemDisk {
+    const SECTORS_PER_FAT: u16 = 12;
+    let mut disk = MemDisk::new(4096);
+    let boot = disk.block(0);
+    boot[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    boot[11..13].copy_from_slice(&512u16.to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+    boot[16] = 2;
+    boot[17..19].copy_from_slice(&512u16.to_le_bytes());
+    boot[19..21].copy_from_slice(&4096u16.to_le_bytes());
+    boot[21] = 0xf8;
+    boot[22..24].copy_from_slice(&SECTORS_PER_FAT.to_le_bytes());
+    boot[24..26].copy_from_slice(&32u16.to_le_bytes());
+    boot[26..28].copy_from_slice(&2u16.to_le_bytes());
+    boot[36] = 0x80;
+    boot[38] = 0x29;
+    boot[39..43].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    boot[43..54].copy_from_slice(b"NO NAME    ");
+    boot[54..62].copy_from_slice(b"FAT12   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+    for fat in 0..2 {
+        let sector = 1 + fat * SECTORS_PER_FAT as u64;
+        disk.block(sector)[0..3].copy_from_slice(&[0xf8, 0xff, 0xff]);
+    }
+    disk
+}
+
+/// Returns a root context on a new, empty tmpfs.
+pub fn tmpfs_context(size_limit: Option<u64>) -> FsContext {
+    init();
+    let fs = TmpFilesystem::new(size_limit);
+    FsContext::new(axfs_ng::mount_root("tmpfs", &fs, MountFlags::empty()))
+}
+
+/// Replaces the contents of the file at `path` with `data`, creating it if
+/// needed.
+pub fn write(cx: &FsContext, path: &str, data: &[u8]) -> VfsResult<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(cx, path)?
+        .into_file()?;
+    let mut buf = data;
+    let mut offset = 0;
+    while !buf.is_empty() {
+        offset += file.write_at(&mut buf, offset)? as u64;
+    }
+    Ok(())
+}
+
+/// Reads the whole file at `path`.
+pub fn read(cx: &FsContext, path: &str) -> VfsResult<Vec<u8>> {
+    let file = OpenOptions::new().read(true).open(cx, path)?.into_file()?;
+    let mut data = Vec::new();
+    let mut buf = vec![0; 4096];
+    loop {
+        let n = file.read_at(&mut buf.as_mut_slice(), data.len() as u64)?;
+        if n == 0 {
+            return Ok(data);
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Reads the whole file at `path` as UTF-8.
+pub fn read_to_string(cx: &FsContext, path: &str) -> VfsResult<String> {
+    Ok(String::from_utf8(read(cx, path)?).unwrap())
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use axfs_ng::{MountFlags, OpenOptions, mount_flags};
+use axfs_ng_vfs::{NodePermission, NodeType, VfsError, VfsResult};
+use common::{read_to_string, tmpfs_context, write};
+
+#[test]
+fn test_negative_entries() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    // Looking a missing name up twice caches it as missing.
+    for _ in 0..2 {
+        assert!(matches!(cx.resolve("/file"), Err(VfsError::ENOENT)));
+    }
+    write(&cx, "/file", b"data")?;
+    assert_eq!(read_to_string(&cx, "/file")?, "data");
+
+    cx.rename("/file", "/renamed")?;
+    assert!(matches!(cx.resolve("/file"), Err(VfsError::ENOENT)));
+    assert_eq!(read_to_string(&cx, "/renamed")?, "data");
+
+    cx.remove_file("/renamed")?;
+    assert!(matches!(cx.resolve("/renamed"), Err(VfsError::ENOENT)));
+    Ok(())
+}
+
+#[test]
+fn test_location_changes() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    cx.create_dir("/dir", NodePermission::from_bits_truncate(0o755))?;
+    assert!(matches!(cx.resolve("/dir/a"), Err(VfsError::ENOENT)));
+
+    // Changes made through locations directly, bypassing the context, are
+    // seen by later lookups.
+    let dir = cx.resolve("/dir")?;
+    dir.create("a", NodeType::RegularFile, NodePermission::default())?;
+    assert!(cx.resolve("/dir/a")?.is_file());
+
+    dir.rename("a", &dir, "b")?;
+    assert!(matches!(cx.resolve("/dir/a"), Err(VfsError::ENOENT)));
+    assert!(cx.resolve("/dir/b")?.is_file());
+
+    dir.unlink("b", false)?;
+    assert!(matches!(cx.resolve("/dir/b"), Err(VfsError::ENOENT)));
+    Ok(())
+}
+
+#[test]
+fn test_read_only_bind_mount() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    let mode = NodePermission::from_bits_truncate(0o755);
+    cx.create_dir("/src", mode)?;
+    cx.create_dir("/dst", mode)?;
+    write(&cx, "/src/file", b"data")?;
+    cx.bind_mount("/src", "/dst", MountFlags::RDONLY)?;
+
+    // The lookup through the writable mount must not be reused for the
+    // read-only one, which shares its directory entries.
+    assert!(!mount_flags(&cx.resolve("/src/file")?).contains(MountFlags::RDONLY));
+    assert!(mount_flags(&cx.resolve("/dst/file")?).contains(MountFlags::RDONLY));
+    assert_eq!(read_to_string(&cx, "/dst/file")?, "data");
+    assert!(matches!(
+        OpenOptions::new().write(true).open(&cx, "/dst/file"),
+        Err(VfsError::EROFS)
+    ));
+    write(&cx, "/src/file", b"changed")?;
+    assert_eq!(read_to_string(&cx, "/dst/file")?, "changed");
+    Ok(())
+}
+
+#[cfg(feature = "fat")]
+#[test]
+fn test_case_insensitive() -> VfsResult<()> {
+    common::init();
+    let fs = axfs_ng::fs::fat::FatFilesystem::new(common::fat_disk().into_disk())?;
+    let cx = axfs_ng::FsContext::new(axfs_ng::mount_root("vfat", &fs, MountFlags::empty()));
+
+    // A name cached as missing in one case is found in any case once
+    // created.
+    for _ in 0..2 {
+        assert!(matches!(cx.resolve("/FOO"), Err(VfsError::ENOENT)));
+    }
+    write(&cx, "/foo", b"data")?;
+    assert_eq!(read_to_string(&cx, "/FOO")?, "data");
+    assert_eq!(read_to_string(&cx, "/Foo")?, "data");
+
+    // Removing it under one case removes it under all.
+    cx.remove_file("/foo")?;
+    assert!(matches!(cx.resolve("/FOO"), Err(VfsError::ENOENT)));
+    assert!(matches!(cx.resolve("/Foo"), Err(VfsError::ENOENT)));
+    Ok(())
+}
@@ -1,29 +1,22 @@
-// FIXME: The test is broken after we changed the ramdisk implementation!
+//! Tests on the images made by `resources/make_fs.sh`, which are ignored by
+//! default. Run them with `cargo test -- --ignored` after making the images.
 
-#![allow(unused)]
+mod common;
 
 use std::collections::HashSet;
 
-use axdriver_block::ramdisk::RamDisk;
-use axfs_ng::{File, FsContext, fs};
-use axfs_ng_vfs::{
-    Filesystem, Location, Mountpoint, NodePermission, NodeType, VfsError, VfsResult, path::Path,
-};
-use axio::Read;
+use axfs_ng::{FsContext, MountFlags, bcache::CachedDisk, fs, mount_root};
+use axfs_ng_vfs::{Filesystem, NodePermission, NodeType, VfsError, VfsResult, path::Path};
+use common::{MemDisk, init, read_to_string, write};
 
-type RawMutex = spin::Mutex<()>;
-
-fn list_files(cx: &FsContext<RawMutex>, path: impl AsRef<Path>) -> VfsResult<HashSet<String>> {
+fn list_files(cx: &FsContext, path: impl AsRef<Path>) -> VfsResult<HashSet<String>> {
     cx.read_dir(path)?
         .map(|it| it.map(|entry| entry.name.to_owned()))
         .collect()
 }
 
-fn test_fs_read(fs: &Filesystem<RawMutex>) -> VfsResult<()> {
-    let mount = Mountpoint::new_root(fs);
-    let cx: FsContext<spin::mutex::Mutex<()>> = FsContext::new(mount.root_location());
-
-    let names = list_files(&cx, "/").unwrap();
+fn test_fs_read(cx: &FsContext) -> VfsResult<()> {
+    let names = list_files(cx, "/").unwrap();
     assert!(
         ["short.txt", "long.txt", "a", "very-long-dir-name"]
             .into_iter()
@@ -38,14 +31,14 @@ fn test_fs_read(fs: &Filesystem<RawMutex>) -> VfsResult<()> {
     }
 
     assert_eq!(
-        list_files(&cx, "/a/long/path")?,
+        list_files(cx, "/a/long/path")?,
         ["test.txt", ".", ".."]
             .into_iter()
             .map(str::to_owned)
             .collect()
     );
     assert_eq!(
-        cx.read_to_string("/a/long/path/test.txt")?,
+        read_to_string(cx, "/a/long/path/test.txt")?,
         "Rust is cool!\n"
     );
 
@@ -60,19 +53,15 @@ fn test_fs_read(fs: &Filesystem<RawMutex>) -> VfsResult<()> {
         cx.resolve("/very-long-dir-name/very-long-file-name.txt")?
             .is_file()
     );
-    let mut file = File::open(&cx, "/very-long-dir-name/very-long-file-name.txt")?;
-    let mut buf = vec![];
-    file.read_to_end(&mut buf)?;
-    drop(file);
-    assert_eq!(core::str::from_utf8(&buf).unwrap(), "Rust is cool!\n");
+    assert_eq!(
+        read_to_string(cx, "/very-long-dir-name/very-long-file-name.txt")?,
+        "Rust is cool!\n"
+    );
 
     Ok(())
 }
 
-fn test_fs_write(fs: &Filesystem<RawMutex>) -> VfsResult<()> {
-    let mount = Mountpoint::new_root(fs);
-    let cx = FsContext::new(mount.root_location());
-
+fn test_fs_write(cx: &FsContext) -> VfsResult<()> {
     let mode = NodePermission::from_bits(0o766).unwrap();
     cx.create_dir("temp", mode)?;
     cx.create_dir("temp2", mode)?;
@@ -88,79 +77,80 @@ fn test_fs_write(fs: &Filesystem<RawMutex>) -> VfsResult<()> {
         Err(VfsError::ENOTEMPTY)
     ));
 
-    cx.write("/test.txt", "hello world".as_bytes())?;
-    assert_eq!(cx.read_to_string("/test.txt")?, "hello world");
+    write(cx, "/test.txt", b"hello world")?;
+    assert_eq!(read_to_string(cx, "/test.txt")?, "hello world");
 
     cx.create_dir("test_dir", NodePermission::from_bits_truncate(0o755))?;
     cx.rename("test_dir", "test")?;
     cx.remove_dir("test")?;
 
-    println!("---------------------");
-
     if cx.link("/test.txt", "/test_link").is_ok() {
-        assert_eq!(cx.read_to_string("/test_link")?, "hello world");
+        assert_eq!(read_to_string(cx, "/test_link")?, "hello world");
     }
     if cx.symlink("/test.txt", "/test_symlink").is_ok() {
-        assert_eq!(cx.read_to_string("/test_symlink")?, "hello world");
+        assert_eq!(read_to_string(cx, "/test_symlink")?, "hello world");
     }
 
     // FAT has errornous rename implementation
-    if fs.name() != "vfat" {
-        cx.write("rename1", "hello world".as_bytes())?;
-        cx.write("rename2", "hello world2".as_bytes())?;
+    if cx.root_dir().filesystem().name() != "vfat" {
+        write(cx, "rename1", b"hello world")?;
+        write(cx, "rename2", b"hello world2")?;
         cx.rename("rename1", "rename2")?;
-        assert_eq!(cx.read_to_string("rename2")?, "hello world");
+        assert_eq!(read_to_string(cx, "rename2")?, "hello world");
     }
 
     Ok(())
 }
 
-fn test_fs_full(fs: Filesystem<RawMutex>) -> VfsResult<()> {
+fn context(fs: &Filesystem) -> FsContext {
+    FsContext::new(mount_root("test", fs, MountFlags::empty()))
+}
+
+fn test_fs_full(fs: Filesystem) -> VfsResult<()> {
     let mut thrds = vec![];
     for _ in 0..1 {
         let fs = fs.clone();
-        thrds.push(std::thread::spawn(move || test_fs_read(&fs)));
+        thrds.push(std::thread::spawn(move || test_fs_read(&context(&fs))));
     }
     for th in thrds {
         th.join().unwrap()?;
     }
-    test_fs_write(&fs)?;
+    test_fs_write(&context(&fs))?;
     Ok(())
 }
 
+fn image(path: &str) -> CachedDisk {
+    init();
+    MemDisk::from_image(path).into_disk()
+}
+
 #[test]
+#[ignore = "needs the images made by resources/make_fs.sh"]
 #[cfg(feature = "fat")]
 fn test_fatfs() {
     for path in ["resources/fat16.img", "resources/fat32.img"] {
-        let data = std::fs::read(path).unwrap();
-        let disk = RamDisk::from(&data);
-        let fs = fs::fat::FatFilesystem::<RawMutex>::new(disk);
-        test_fs_full(fs).unwrap();
+        test_fs_full(fs::fat::FatFilesystem::new(image(path)).unwrap()).unwrap();
     }
 }
 
 #[test]
+#[ignore = "needs the images made by resources/make_fs.sh"]
 #[cfg(feature = "ext4")]
 fn test_ext4() {
-    let data = std::fs::read("resources/ext4.img").unwrap();
-    let disk = RamDisk::from(&data);
-    let fs = fs::ext4::Ext4Filesystem::<RawMutex>::new(disk).unwrap();
+    let fs = fs::ext4::Ext4Filesystem::new(image("resources/ext4.img")).unwrap();
     test_fs_full(fs).unwrap();
 }
 
 #[test]
+#[ignore = "needs the images made by resources/make_fs.sh"]
 #[cfg(all(feature = "ext4", feature = "fat"))]
 fn test_mount() {
-    env_logger::init();
-    let disk = RamDisk::from(&std::fs::read("resources/ext4.img").unwrap());
-    let fs = fs::ext4::Ext4Filesystem::<RawMutex>::new(disk).unwrap();
-
-    let disk = RamDisk::from(&std::fs::read("resources/fat16.img").unwrap());
-    let sub_fs = fs::fat::FatFilesystem::<RawMutex>::new(disk);
+    let _ = env_logger::try_init();
+    let fs = fs::ext4::Ext4Filesystem::new(image("resources/ext4.img")).unwrap();
+    let sub_fs = fs::fat::FatFilesystem::new(image("resources/fat16.img")).unwrap();
 
-    let mount = Mountpoint::new(&fs, None);
-    let cx = FsContext::new(mount.root_location());
-    cx.resolve("a").unwrap().mount(&sub_fs);
+    let cx = context(&fs);
+    cx.mount("test", "a", &sub_fs, MountFlags::empty()).unwrap();
 
     let mt = cx.resolve("a").unwrap();
     assert!(!mt.is_mountpoint() && mt.is_root_of_mount());
@@ -168,8 +158,7 @@ fn test_mount() {
     assert_eq!(mt.absolute_path().unwrap().to_string(), "/a");
 
     assert_eq!(
-        cx.read_to_string("/a/../a/very-long-dir-name/very-long-file-name.txt")
-            .unwrap(),
+        read_to_string(&cx, "/a/../a/very-long-dir-name/very-long-file-name.txt").unwrap(),
         "Rust is cool!\n"
     );
 }
@@ -0,0 +1,114 @@
+mod common;
+
+use std::ops::Range;
+
+use axfs_ng::{FileLocks, LockType, RangeLock};
+use axfs_ng_vfs::{VfsError, VfsResult};
+use common::{tmpfs_context, write};
+
+/// Returns the locks of a new, empty file.
+fn file_locks() -> VfsResult<std::sync::Arc<FileLocks>> {
+    let cx = tmpfs_context(None);
+    write(&cx, "/file", b"")?;
+    Ok(FileLocks::of(&cx.resolve("/file")?))
+}
+
+fn lock(owner: u64, lock_type: LockType, range: Range<u64>) -> Option<RangeLock> {
+    Some(RangeLock {
+        owner,
+        lock_type,
+        range,
+    })
+}
+
+#[test]
+fn test_split() -> VfsResult<()> {
+    let locks = file_locks()?;
+    locks.lock_range(1, Some(LockType::Write), 0..100, false)?;
+    assert!(matches!(
+        locks.lock_range(2, Some(LockType::Read), 50..60, false),
+        Err(VfsError::EAGAIN)
+    ));
+
+    // Unlocking the middle leaves the two ends locked.
+    locks.lock_range(1, None, 40..60, false)?;
+    assert_eq!(locks.test_range(2, LockType::Write, 40..60), None);
+    assert_eq!(
+        locks.test_range(2, LockType::Write, 0..50),
+        lock(1, LockType::Write, 0..40)
+    );
+    assert_eq!(
+        locks.test_range(2, LockType::Write, 50..200),
+        lock(1, LockType::Write, 60..100)
+    );
+
+    // Converting the middle of a lock splits it too.
+    locks.lock_range(1, Some(LockType::Write), 0..100, false)?;
+    locks.lock_range(1, Some(LockType::Read), 40..60, false)?;
+    assert_eq!(
+        locks.test_range(2, LockType::Read, 0..100),
+        lock(1, LockType::Write, 0..40)
+    );
+    assert_eq!(locks.test_range(2, LockType::Read, 40..60), None);
+    assert_eq!(
+        locks.test_range(2, LockType::Write, 40..60),
+        lock(1, LockType::Read, 40..60)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_merge() -> VfsResult<()> {
+    let locks = file_locks()?;
+    locks.lock_range(1, Some(LockType::Read), 0..10, false)?;
+    locks.lock_range(1, Some(LockType::Read), 20..30, false)?;
+    // Filling the gap merges all three locks into one.
+    locks.lock_range(1, Some(LockType::Read), 10..20, false)?;
+    assert_eq!(
+        locks.test_range(2, LockType::Write, 0..u64::MAX),
+        lock(1, LockType::Read, 0..30)
+    );
+
+    // Locks of another type or owner are kept apart.
+    locks.lock_range(1, Some(LockType::Write), 30..40, false)?;
+    locks.lock_range(2, Some(LockType::Read), 40..50, false)?;
+    assert_eq!(
+        locks.test_range(3, LockType::Write, 25..u64::MAX),
+        lock(1, LockType::Read, 0..30)
+    );
+    assert_eq!(
+        locks.test_range(3, LockType::Write, 30..u64::MAX),
+        lock(1, LockType::Write, 30..40)
+    );
+    assert_eq!(
+        locks.test_range(3, LockType::Write, 40..u64::MAX),
+        lock(2, LockType::Read, 40..50)
+    );
+
+    // Overlapping locks of the same owner replace each other.
+    locks.lock_range(1, Some(LockType::Write), 0..35, false)?;
+    assert_eq!(
+        locks.test_range(3, LockType::Read, 0..u64::MAX),
+        lock(1, LockType::Write, 0..40)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_owners() -> VfsResult<()> {
+    let locks = file_locks()?;
+    // Read locks of different owners coexist.
+    locks.lock_range(1, Some(LockType::Read), 0..10, false)?;
+    locks.lock_range(2, Some(LockType::Read), 5..15, false)?;
+    assert_eq!(locks.test_range(1, LockType::Write, 0..5), None);
+    assert!(matches!(
+        locks.lock_range(1, Some(LockType::Write), 0..10, false),
+        Err(VfsError::EAGAIN)
+    ));
+
+    assert!(matches!(
+        locks.lock_range(1, Some(LockType::Read), 10..10, false),
+        Err(VfsError::EINVAL)
+    ));
+    Ok(())
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use axfs_ng::{AccessMode, Credentials, FsContext, OpenOptions};
+use axfs_ng_vfs::{NodePermission, VfsError, VfsResult};
+use common::{read, tmpfs_context, write};
+
+/// Returns a context on the same tree as `cx` with the credentials `cred`.
+fn as_user(cx: &FsContext, cred: Credentials) -> FsContext {
+    let mut cx = FsContext::new(cx.root_dir().clone());
+    cx.set_credentials(cred);
+    cx
+}
+
+/// Creates the file at `path` owned by `uid:gid` with the mode `mode`.
+fn create(cx: &FsContext, path: &str, uid: u32, gid: u32, mode: u32) -> VfsResult<()> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .user(uid, gid)
+        .mode(mode)
+        .open(cx, path)?;
+    Ok(())
+}
+
+#[test]
+fn test_owner_group_other() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    create(&cx, "/file", 1000, 100, 0o640)?;
+    let loc = cx.resolve("/file")?;
+
+    let owner = Credentials::new(1000, 1000);
+    owner.check_access(&loc, AccessMode::READ | AccessMode::WRITE)?;
+    assert!(matches!(
+        owner.check_access(&loc, AccessMode::EXECUTE),
+        Err(VfsError::EACCES)
+    ));
+
+    let member = Credentials::new(1001, 1001).with_groups(vec![100]);
+    member.check_access(&loc, AccessMode::READ)?;
+    assert!(matches!(
+        member.check_access(&loc, AccessMode::WRITE),
+        Err(VfsError::EACCES)
+    ));
+
+    let other = Credentials::new(1002, 1002);
+    assert!(matches!(
+        other.check_access(&loc, AccessMode::READ),
+        Err(VfsError::EACCES)
+    ));
+    let other_cx = as_user(&cx, other);
+    assert!(matches!(read(&other_cx, "/file"), Err(VfsError::EACCES)));
+    assert!(matches!(
+        write(&other_cx, "/file", b"data"),
+        Err(VfsError::EACCES)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_root() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    create(&cx, "/file", 1000, 1000, 0o000)?;
+    let loc = cx.resolve("/file")?;
+
+    // Root bypasses the permission bits, but executes only executables.
+    let root = Credentials::root();
+    root.check_access(&loc, AccessMode::READ | AccessMode::WRITE)?;
+    assert!(matches!(
+        root.check_access(&loc, AccessMode::EXECUTE),
+        Err(VfsError::EACCES)
+    ));
+    create(&cx, "/program", 1000, 1000, 0o100)?;
+    root.check_access(&cx.resolve("/program")?, AccessMode::EXECUTE)?;
+    Ok(())
+}
+
+#[test]
+fn test_search_permission() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    cx.create_dir("/private", NodePermission::from_bits_truncate(0o700))?;
+    write(&cx, "/private/file", b"data")?;
+
+    let user = as_user(&cx, Credentials::new(1000, 1000));
+    assert!(matches!(
+        user.resolve("/private/file"),
+        Err(VfsError::EACCES)
+    ));
+    assert!(matches!(
+        user.create_dir("/private/dir", NodePermission::from_bits_truncate(0o755)),
+        Err(VfsError::EACCES)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_sticky_directory() -> VfsResult<()> {
+    // The root of a tmpfs is world-writable and sticky, like `/tmp`.
+    let cx = tmpfs_context(None);
+    create(&cx, "/theirs", 1001, 1001, 0o666)?;
+
+    let user = as_user(&cx, Credentials::new(1000, 1000));
+    write(&user, "/mine", b"data")?;
+    assert_eq!(user.metadata("/mine")?.uid, 1000);
+    assert!(matches!(user.remove_file("/theirs"), Err(VfsError::EPERM)));
+    assert!(matches!(
+        user.rename("/theirs", "/stolen"),
+        Err(VfsError::EPERM)
+    ));
+    user.remove_file("/mine")?;
+    cx.remove_file("/theirs")?;
+    Ok(())
+}
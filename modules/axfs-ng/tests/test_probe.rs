@@ -0,0 +1,82 @@
+mod common;
+
+use axfs_ng::fs::{
+    FsDriver, PROBE_SIZE, is_ext, is_fat, probe, register_filesystem, tmpfs::TmpFilesystem,
+};
+use axfs_ng_vfs::VfsError;
+use common::{BLOCK_SIZE, MemDisk, init};
+
+/// Writes a FAT boot sector with the OEM name `oem` to `buf`.
+fn write_fat_boot_sector(buf: &mut [u8], oem: &[u8; 8]) {
+    buf[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    buf[3..11].copy_from_slice(oem);
+    buf[11..13].copy_from_slice(&512u16.to_le_bytes());
+    buf[13] = 1;
+    buf[14..16].copy_from_slice(&1u16.to_le_bytes());
+    buf[16] = 2;
+    buf[510..512].copy_from_slice(&[0x55, 0xaa]);
+}
+
+#[test]
+fn test_is_fat() {
+    let mut buf = vec![0; PROBE_SIZE];
+    assert!(!is_fat(&buf));
+    write_fat_boot_sector(&mut buf, b"MSWIN4.1");
+    assert!(is_fat(&buf));
+    assert!(!is_ext(&buf));
+
+    // Each field is checked.
+    for (offset, value) in [(0, 0x00), (11, 0x03), (13, 3), (16, 0), (511, 0)] {
+        let mut broken = buf.clone();
+        broken[offset] = value;
+        assert!(!is_fat(&broken), "byte {offset} set to {value:#x}");
+    }
+}
+
+#[test]
+fn test_is_ext() {
+    let mut buf = vec![0; PROBE_SIZE];
+    assert!(!is_ext(&buf));
+    buf[1080..1082].copy_from_slice(&0xef53u16.to_le_bytes());
+    assert!(is_ext(&buf));
+    assert!(!is_fat(&buf));
+}
+
+#[test]
+fn test_probe_unknown() {
+    init();
+    // Nothing recognizes an empty disk.
+    let disk = MemDisk::new(64).into_disk();
+    assert!(matches!(probe(disk), Err(VfsError::EINVAL)));
+
+    // A disk smaller than the probed area cannot hold a filesystem.
+    let small = MemDisk::new(PROBE_SIZE / BLOCK_SIZE - 1);
+    let disk = small.into_disk();
+    assert!(matches!(probe(disk), Err(VfsError::EINVAL)));
+}
+
+#[test]
+fn test_probe_broken_fat() {
+    init();
+    // A boot sector passing the check but without a valid BPB is rejected
+    // when mounting.
+    let mut disk = MemDisk::new(64);
+    write_fat_boot_sector(disk.block(0), b"MSWIN4.1");
+    assert!(probe(disk.into_disk()).is_err());
+}
+
+#[test]
+fn test_probe_registered() {
+    init();
+    register_filesystem(FsDriver {
+        name: "testfs",
+        probe: |buf| buf[3..11] == *b"AXTESTFS",
+        mount: |_| Ok(TmpFilesystem::new(None)),
+    });
+
+    // Drivers registered later take precedence, even over a FAT boot sector.
+    let mut disk = MemDisk::new(64);
+    write_fat_boot_sector(disk.block(0), b"AXTESTFS");
+    let fs = probe(disk.into_disk()).unwrap();
+    assert_eq!(fs.name(), "tmpfs");
+}
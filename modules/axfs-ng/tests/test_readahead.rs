@@ -0,0 +1,84 @@
+#![cfg(feature = "fat")]
+
+mod common;
+
+use axfs_ng::{
+    CachedFile, File, FileBackend, FsContext, MountFlags, OpenOptions, fs::fat::FatFilesystem,
+    mount_root, set_page_cache_limit,
+};
+use axfs_ng_vfs::VfsResult;
+
+const PAGE_SIZE: usize = 4096;
+
+/// The size of the test file, ending within its last page.
+const FILE_SIZE: usize = 40 * PAGE_SIZE + 100;
+
+/// Returns the pages of `cache` among the first `pages` that are cached.
+fn cached_pages(cache: &CachedFile, pages: u32) -> Vec<u32> {
+    (0..pages)
+        .filter(|pn| cache.with_page(*pn, |page| page.is_some()))
+        .collect()
+}
+
+/// Drops all the pages from the page cache, once written back.
+fn drop_page_cache() -> VfsResult<()> {
+    set_page_cache_limit(Some(0))?;
+    set_page_cache_limit(None)
+}
+
+/// Reads page `pn` of `file`, checking its contents.
+fn read_page(file: &File, pn: usize) -> VfsResult<()> {
+    let mut buf = vec![0; PAGE_SIZE];
+    let n = file.read_at(&mut buf.as_mut_slice(), (pn * PAGE_SIZE) as u64)?;
+    assert_eq!(n, PAGE_SIZE.min(FILE_SIZE - pn * PAGE_SIZE));
+    assert!(buf[..n].iter().all(|&it| it == pn as u8));
+    Ok(())
+}
+
+#[test]
+fn test_readahead() -> VfsResult<()> {
+    common::init();
+    let fs = FatFilesystem::new(common::fat_disk().into_disk())?;
+    let cx = FsContext::new(mount_root("vfat", &fs, MountFlags::empty()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(&cx, "/file")?
+        .into_file()?;
+    let data = (0..FILE_SIZE)
+        .map(|i| (i / PAGE_SIZE) as u8)
+        .collect::<Vec<_>>();
+    let mut buf = data.as_slice();
+    let mut offset = 0;
+    while !buf.is_empty() {
+        offset += file.write_at(&mut buf, offset)? as u64;
+    }
+    let FileBackend::Cached(cache) = file.backend()? else {
+        panic!("the file is not cached");
+    };
+
+    // Sequential reads grow the window from 4 pages, doubling it each time.
+    drop_page_cache()?;
+    assert!(cached_pages(cache, 64).is_empty());
+    read_page(&file, 0)?;
+    assert_eq!(cached_pages(cache, 64), (0..5).collect::<Vec<_>>());
+    read_page(&file, 1)?;
+    assert_eq!(cached_pages(cache, 64), (0..10).collect::<Vec<_>>());
+    read_page(&file, 2)?;
+    assert_eq!(cached_pages(cache, 64), (0..19).collect::<Vec<_>>());
+
+    // Random reads reset it, and only read the pages asked for.
+    drop_page_cache()?;
+    read_page(&file, 30)?;
+    read_page(&file, 10)?;
+    assert_eq!(cached_pages(cache, 64), [10, 30]);
+
+    // Readahead stops at the end of the file.
+    drop_page_cache()?;
+    read_page(&file, 36)?;
+    read_page(&file, 37)?;
+    assert_eq!(cached_pages(cache, 64), (36..41).collect::<Vec<_>>());
+    read_page(&file, 40)?;
+    Ok(())
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use axfs_ng::RenameFlags;
+use axfs_ng_vfs::{NodePermission, VfsError, VfsResult};
+use common::{read_to_string, tmpfs_context, write};
+
+#[test]
+fn test_rename_flags() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    write(&cx, "/a", b"a")?;
+    write(&cx, "/b", b"b")?;
+
+    assert!(matches!(
+        cx.rename_with_flags("/a", "/b", RenameFlags::NOREPLACE),
+        Err(VfsError::EEXIST)
+    ));
+    cx.rename_with_flags("/a", "/b", RenameFlags::EXCHANGE)?;
+    assert_eq!(read_to_string(&cx, "/a")?, "b");
+    assert_eq!(read_to_string(&cx, "/b")?, "a");
+    assert!(matches!(
+        cx.rename_with_flags("/a", "/c", RenameFlags::EXCHANGE),
+        Err(VfsError::ENOENT)
+    ));
+
+    // A directory can be exchanged with a file.
+    cx.create_dir("/dir", NodePermission::from_bits_truncate(0o755))?;
+    write(&cx, "/dir/inner", b"inner")?;
+    cx.rename_with_flags("/dir", "/a", RenameFlags::EXCHANGE)?;
+    assert_eq!(read_to_string(&cx, "/a/inner")?, "inner");
+    assert_eq!(read_to_string(&cx, "/dir")?, "b");
+    Ok(())
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use axfs_ng::OpenOptions;
+use axfs_ng_vfs::{NodePermission, VfsError, VfsResult};
+use common::{read, read_to_string, tmpfs_context, write};
+
+#[test]
+fn test_create_read_write() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    cx.create_dir("/dir", NodePermission::from_bits_truncate(0o755))?;
+    write(&cx, "/dir/file", b"hello world")?;
+    assert_eq!(read_to_string(&cx, "/dir/file")?, "hello world");
+    assert_eq!(cx.metadata("/dir/file")?.size, 11);
+
+    // Writing past the end leaves a hole reading as zeros.
+    let file = OpenOptions::new()
+        .write(true)
+        .open(&cx, "/dir/file")?
+        .into_file()?;
+    file.write_at(&mut &b"!"[..], 8192)?;
+    drop(file);
+    let data = read(&cx, "/dir/file")?;
+    assert_eq!(data.len(), 8193);
+    assert!(data[11..8192].iter().all(|&it| it == 0));
+
+    assert!(matches!(
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&cx, "/dir/file"),
+        Err(VfsError::EEXIST)
+    ));
+    assert!(matches!(read(&cx, "/dir/missing"), Err(VfsError::ENOENT)));
+
+    cx.remove_file("/dir/file")?;
+    assert!(matches!(cx.resolve("/dir/file"), Err(VfsError::ENOENT)));
+    cx.remove_dir("/dir")?;
+    Ok(())
+}
+
+#[test]
+fn test_truncate() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    write(&cx, "/file", &[b'x'; 10000])?;
+    let file = OpenOptions::new()
+        .write(true)
+        .open(&cx, "/file")?
+        .into_file()?;
+
+    file.backend()?.set_len(5)?;
+    assert_eq!(cx.metadata("/file")?.size, 5);
+    // Growing the file again must not bring the old bytes back.
+    file.backend()?.set_len(5000)?;
+    let data = read(&cx, "/file")?;
+    assert_eq!(&data[..5], b"xxxxx");
+    assert!(data[5..].iter().all(|&it| it == 0));
+
+    file.backend()?.set_len(0)?;
+    assert!(read(&cx, "/file")?.is_empty());
+
+    // Truncating on open empties the file as well.
+    write(&cx, "/file", b"abc")?;
+    write(&cx, "/file", b"d")?;
+    assert_eq!(read_to_string(&cx, "/file")?, "d");
+    Ok(())
+}
+
+#[test]
+fn test_size_limit() -> VfsResult<()> {
+    let cx = tmpfs_context(Some(2 * 4096));
+    assert!(matches!(
+        write(&cx, "/big", &[0; 4 * 4096]),
+        Err(VfsError::ENOSPC)
+    ));
+    // The space of removed files is given back.
+    cx.remove_file("/big")?;
+    write(&cx, "/small", &[0; 4096])?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use axfs_ng::{Credentials, OpenOptions, XattrFlags, get_xattr, remove_xattr, set_xattr};
+use axfs_ng_vfs::{VfsError, VfsResult};
+use common::tmpfs_context;
+
+#[test]
+fn test_system_namespace() -> VfsResult<()> {
+    let cx = tmpfs_context(None);
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .user(1000, 1000)
+        .mode(0o666)
+        .open(&cx, "/file")?;
+    let loc = cx.resolve("/file")?;
+    let name = "system.posix_acl_access";
+
+    // Write access to the file is not enough to change its ACLs.
+    let other = Credentials::new(1001, 1001);
+    assert!(matches!(
+        set_xattr(&other, &loc, name, b"acl", XattrFlags::empty()),
+        Err(VfsError::EPERM)
+    ));
+
+    let owner = Credentials::new(1000, 1000);
+    set_xattr(&owner, &loc, name, b"acl", XattrFlags::empty())?;
+    // Anyone may read them.
+    assert_eq!(get_xattr(&other, &loc, name)?, b"acl");
+    assert!(matches!(
+        remove_xattr(&other, &loc, name),
+        Err(VfsError::EPERM)
+    ));
+
+    set_xattr(
+        &Credentials::root(),
+        &loc,
+        name,
+        b"root",
+        XattrFlags::empty(),
+    )?;
+    remove_xattr(&owner, &loc, name)?;
+    assert!(matches!(
+        get_xattr(&owner, &loc, name),
+        Err(VfsError::ENODATA)
+    ));
+    Ok(())
+}
@@ -1,10 +1,76 @@
 //! Interrupt management.
 
-use core::task::Waker;
+use core::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    task::Waker,
+};
 
 use axcpu::trap::{IRQ, register_trap_handler};
 use axio::PollSet;
-pub use axplat::irq::{handle, register, set_enable, unregister};
+pub use axplat::irq::{IrqHandler, handle, set_enable};
+
+/// IRQs with a number below this are counted by [`irq_counts`].
+const MAX_COUNTED_IRQS: usize = 1024;
+
+static IRQ_COUNTS: [AtomicU64; MAX_COUNTED_IRQS] = [const { AtomicU64::new(0) }; MAX_COUNTED_IRQS];
+/// Handlers of counted IRQs as `usize`, or `0` if there is none.
+static IRQ_HANDLERS: [AtomicUsize; MAX_COUNTED_IRQS] =
+    [const { AtomicUsize::new(0) }; MAX_COUNTED_IRQS];
+
+fn counting_handler(irq: usize) {
+    IRQ_COUNTS[irq].fetch_add(1, Ordering::Relaxed);
+    let handler = IRQ_HANDLERS[irq].load(Ordering::Acquire);
+    if handler != 0 {
+        // SAFETY: only `IrqHandler`s are stored in `IRQ_HANDLERS`.
+        let handler: IrqHandler = unsafe { core::mem::transmute(handler) };
+        handler(irq);
+    }
+}
+
+/// Registers a handler for an IRQ and enables it.
+///
+/// Returns `false` if the IRQ already has a handler.
+pub fn register(irq: usize, handler: IrqHandler) -> bool {
+    if irq >= MAX_COUNTED_IRQS {
+        return axplat::irq::register(irq, handler);
+    }
+    if IRQ_HANDLERS[irq]
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return false;
+    }
+    if !axplat::irq::register(irq, counting_handler) {
+        IRQ_HANDLERS[irq].store(0, Ordering::Release);
+        return false;
+    }
+    true
+}
+
+/// Unregisters the handler of an IRQ and disables it.
+///
+/// Returns the handler that was registered.
+pub fn unregister(irq: usize) -> Option<IrqHandler> {
+    let handler = axplat::irq::unregister(irq)?;
+    if irq >= MAX_COUNTED_IRQS {
+        return Some(handler);
+    }
+    match IRQ_HANDLERS[irq].swap(0, Ordering::AcqRel) {
+        0 => None,
+        // SAFETY: only `IrqHandler`s are stored in `IRQ_HANDLERS`.
+        handler => Some(unsafe { core::mem::transmute::<usize, IrqHandler>(handler) }),
+    }
+}
+
+/// Returns the number of times each IRQ was handled, for the IRQs that
+/// were handled at least once.
+pub fn irq_counts() -> impl Iterator<Item = (usize, u64)> {
+    IRQ_COUNTS
+        .iter()
+        .enumerate()
+        .map(|(irq, count)| (irq, count.load(Ordering::Relaxed)))
+        .filter(|(_, count)| *count > 0)
+}
 
 static POLL_TABLE: [PollSet; 0x30] = [const { PollSet::new() }; 0x30];
 fn poll_handler(irq: usize) {
@@ -14,7 +80,7 @@ fn poll_handler(irq: usize) {
 /// Registers a waker for a IRQ interrupt.
 pub fn register_irq_waker(irq: u32, waker: &Waker) {
     POLL_TABLE[irq as usize].register(waker);
-    axplat::irq::register(irq as usize, poll_handler);
+    register(irq as usize, poll_handler);
 }
 
 #[register_trap_handler(IRQ)]
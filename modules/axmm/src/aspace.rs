@@ -1,10 +1,11 @@
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use core::{fmt, ops::DerefMut};
 
 use axerrno::{LinuxError, LinuxResult, bail};
+use axfs_ng::EvictedPage;
 use axhal::{
     mem::phys_to_virt,
-    paging::{MappingFlags, PageTable},
+    paging::{MappingFlags, PageSize, PageTable},
     trap::PageFaultFlags,
 };
 use axsync::Mutex;
@@ -16,13 +17,25 @@ use memory_set::{MemoryArea, MemorySet};
 use crate::{
     backend::{Backend, BackendOps},
     mapping_to_linux_error,
+    stats::{self, FaultStats},
 };
 
+/// Default number of pages mapped around a faulting page of a file-backed
+/// mapping.
+pub const DEFAULT_FAULT_AROUND_PAGES: usize = 16;
+
+/// Default number of pages read ahead on sequential page faults of a
+/// file-backed mapping.
+pub const DEFAULT_READAHEAD_PAGES: usize = 32;
+
 /// The virtual memory address space.
 pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    fault_around_pages: usize,
+    readahead_pages: usize,
+    fault_stats: FaultStats,
 }
 
 impl AddrSpace {
@@ -67,9 +80,46 @@ impl AddrSpace {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| LinuxError::ENOMEM)?,
+            fault_around_pages: DEFAULT_FAULT_AROUND_PAGES,
+            readahead_pages: DEFAULT_READAHEAD_PAGES,
+            fault_stats: FaultStats::default(),
         })
     }
 
+    /// Returns the page fault counters of the address space.
+    pub const fn fault_stats(&self) -> FaultStats {
+        self.fault_stats
+    }
+
+    /// Returns the size (in pages) of the fault-around window.
+    pub const fn fault_around_pages(&self) -> usize {
+        self.fault_around_pages
+    }
+
+    /// Sets the size (in pages) of the fault-around window.
+    ///
+    /// On a read fault of a file-backed mapping, the pages within the aligned
+    /// window around the faulting page that are already in the page cache are
+    /// mapped as well. `0` or `1` disables fault-around.
+    pub fn set_fault_around_pages(&mut self, pages: usize) {
+        self.fault_around_pages = pages;
+    }
+
+    /// Returns the number of pages read ahead on sequential page faults.
+    pub const fn readahead_pages(&self) -> usize {
+        self.readahead_pages
+    }
+
+    /// Sets the number of pages read ahead on sequential page faults of a
+    /// file-backed mapping.
+    ///
+    /// The pages are read into the page cache in a background task, so that
+    /// later faults on them find them by fault-around or without I/O. `0`
+    /// disables readahead. Readahead needs the `multitask` feature.
+    pub fn set_readahead_pages(&mut self, pages: usize) {
+        self.readahead_pages = pages;
+    }
+
     /// Copies page table mappings from another address space.
     ///
     /// It copies the page table entries only rather than the memory regions,
@@ -176,10 +226,19 @@ impl AddrSpace {
         let end = start + size;
 
         let mut modify = self.pt.to_mut();
+        // Populating pages up front does not count as page faults.
+        let mut stats = FaultStats::default();
+        let mut callbacks = Vec::new();
         while let Some(area) = self.areas.find(start) {
             let range = VirtAddrRange::new(start, area.end().min(end));
-            area.backend()
-                .populate(range, area.flags(), access_flags, &mut modify)?;
+            let (_, callback) = area.backend().populate(
+                range,
+                area.flags(),
+                access_flags,
+                &mut modify,
+                &mut stats,
+            )?;
+            callbacks.extend(callback);
             start = area.end();
             assert!(start.is_aligned_4k());
             if start >= end {
@@ -187,6 +246,11 @@ impl AddrSpace {
             }
         }
 
+        drop(modify);
+        for callback in callbacks {
+            callback(self);
+        }
+
         if start < end {
             // If the area is not fully mapped, we return ENOMEM.
             bail!(ENOMEM);
@@ -195,6 +259,76 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Unmaps the pages evicted from the page cache while this address space
+    /// was locked, then frees them.
+    pub(crate) fn unmap_evicted(&mut self, evicted: Vec<EvictedPage>) {
+        let mut modify = self.pt.to_mut();
+        for area in self.areas.iter() {
+            for page in &evicted {
+                let result = match area.backend() {
+                    Backend::File(file) => file.unmap_evicted(area.va_range(), page, &mut modify),
+                    Backend::Cow(cow) => cow.unmap_evicted(area.va_range(), page, &mut modify),
+                    _ => continue,
+                };
+                if let Err(err) = result {
+                    warn!("Failed to unmap evicted page {}: {err:?}", page.pn());
+                }
+            }
+        }
+    }
+
+    /// Synchronizes the file-backed mappings within the given range with the
+    /// underlying files, like `msync`.
+    ///
+    /// Mapped pages of shared file mappings are write-protected in every
+    /// address space and cleaned, so that later writes to them are tracked
+    /// again, and their contents are flushed through the page cache. Unless
+    /// `asynchronous` is `true`, it also waits for the data to reach the
+    /// storage.
+    ///
+    /// Returns `ENOMEM` if the range contains unmapped addresses.
+    pub fn sync_range(&mut self, range: VirtAddrRange, asynchronous: bool) -> LinuxResult {
+        self.validate_region(range.start, range.size())?;
+
+        let mut to_sync = Vec::new();
+        let mut start = range.start;
+        while let Some(area) = self.areas.find(start) {
+            if let Backend::File(file) = area.backend() {
+                let pages = file.pages(VirtAddrRange::new(start, area.end().min(range.end)));
+                to_sync.push((file.clone(), pages));
+            }
+            start = area.end();
+            if start >= range.end {
+                break;
+            }
+        }
+
+        // The page cache write-protects the pages in the other address
+        // spaces, but leaves this one to us as it is locked. The pages may be
+        // mapped by other mappings of the same file than the synced ones.
+        let mut modify = self.pt.to_mut();
+        for area in self.areas.iter() {
+            let Backend::File(file) = area.backend() else {
+                continue;
+            };
+            for (synced, pages) in &to_sync {
+                if file.same_file(synced) {
+                    file.write_protect(area.va_range(), pages.clone(), area.flags(), &mut modify)?;
+                }
+            }
+        }
+        drop(modify);
+
+        for (file, pages) in to_sync {
+            file.sync_pages(pages, !asynchronous)?;
+        }
+
+        if start < range.end {
+            bail!(ENOMEM);
+        }
+        Ok(())
+    }
+
     /// Removes mappings within the specified virtual address range.
     ///
     /// Returns an error if the address range is out of the address space or not
@@ -327,39 +461,106 @@ impl AddrSpace {
     /// Returns `true` if the page fault is handled successfully (not a real
     /// fault).
     pub fn handle_page_fault(&mut self, vaddr: VirtAddr, access_flags: PageFaultFlags) -> bool {
-        if !self.va_range.contains(vaddr) {
-            return false;
+        let mut stats = FaultStats::default();
+        let (handled, backend) = self.populate_fault(vaddr, access_flags, &mut stats);
+        if !handled {
+            // Pages populated before the failure are not counted.
+            stats = FaultStats {
+                failed: 1,
+                ..Default::default()
+            };
         }
-        if let Some(area) = self.areas.find(vaddr) {
-            let flags = area.flags();
-            if flags.contains(access_flags) {
-                let page_size = area.backend().page_size();
-                let populate_result = area.backend().populate(
-                    VirtAddrRange::from_start_size(vaddr.align_down(page_size), page_size as _),
-                    flags,
-                    access_flags,
-                    &mut self.pt.to_mut(),
+        if stats::fault_tracing() {
+            if let Some(kind) = stats.kind() {
+                info!(
+                    "Page fault at {vaddr:?} ({access_flags:?}) on {} backend: {kind}",
+                    backend.unwrap_or("no")
                 );
-                return match populate_result {
-                    Ok((n, callback)) => {
-                        if let Some(cb) = callback {
-                            cb(self);
-                        }
-                        if n == 0 {
-                            warn!("No pages populated for {vaddr:?} ({flags:?})");
-                            false
-                        } else {
-                            true
-                        }
-                    }
-                    Err(err) => {
-                        warn!("Failed to populate pages for {vaddr:?} ({flags:?}): {err}");
-                        false
-                    }
-                };
             }
         }
-        false
+        self.fault_stats.add(&stats);
+        stats::add_global(&stats);
+        handled
+    }
+
+    /// Populates the faulting page, recording the kind of the fault in
+    /// `stats`.
+    ///
+    /// Returns whether the fault is handled, and the name of the backend of
+    /// the faulting area.
+    fn populate_fault(
+        &mut self,
+        vaddr: VirtAddr,
+        access_flags: PageFaultFlags,
+        stats: &mut FaultStats,
+    ) -> (bool, Option<&'static str>) {
+        if !self.va_range.contains(vaddr) {
+            return (false, None);
+        }
+        let Some(area) = self.areas.find(vaddr) else {
+            return (false, None);
+        };
+        let backend = area.backend().name();
+        let flags = area.flags();
+        if !flags.contains(access_flags) {
+            return (false, Some(backend));
+        }
+        let page_size = area.backend().page_size();
+        let populate_result = area.backend().populate(
+            VirtAddrRange::from_start_size(vaddr.align_down(page_size), page_size as _),
+            flags,
+            access_flags,
+            &mut self.pt.to_mut(),
+            stats,
+        );
+        let handled = match populate_result {
+            Ok((n, callback)) => {
+                if let Some(cb) = callback {
+                    cb(self);
+                }
+                if n == 0 {
+                    warn!("No pages populated for {vaddr:?} ({flags:?})");
+                    false
+                } else {
+                    self.fault_around(vaddr, access_flags);
+                    true
+                }
+            }
+            Err(err) => {
+                warn!("Failed to populate pages for {vaddr:?} ({flags:?}): {err}");
+                false
+            }
+        };
+        (handled, Some(backend))
+    }
+
+    /// Maps the neighbors of a handled faulting page that are available
+    /// without I/O, and lets the backend read ahead.
+    fn fault_around(&mut self, vaddr: VirtAddr, access_flags: PageFaultFlags) {
+        let Some(area) = self.areas.find(vaddr) else {
+            return;
+        };
+        let backend = area.backend();
+        if backend.page_size() != PageSize::Size4K {
+            return;
+        }
+        backend.readahead(vaddr, self.readahead_pages);
+
+        // Write faults are not worth it, as the neighbors would have to be
+        // dirtied or copied up front.
+        if self.fault_around_pages <= 1 || access_flags.contains(MappingFlags::WRITE) {
+            return;
+        }
+        let window = self.fault_around_pages * PAGE_SIZE_4K;
+        let window_start = vaddr.as_usize() / window * window;
+        let range = VirtAddrRange::new(
+            area.start().max(window_start.into()),
+            area.end().min(window_start.saturating_add(window).into()),
+        );
+        match backend.fault_around(range, area.flags(), &mut self.pt.to_mut()) {
+            Ok(n) => trace!("Mapped {n} pages around {vaddr:?}"),
+            Err(err) => warn!("Failed to map pages around {vaddr:?}: {err}"),
+        }
     }
 
     /// Attempts to clone the current address space into a new one.
@@ -368,7 +569,10 @@ impl AddrSpace {
     /// size, then iterates over all memory areas in the original address
     /// space to copy or share their mappings into the new one.
     pub fn try_clone(&mut self) -> LinuxResult<Arc<Mutex<Self>>> {
-        let new_aspace = Arc::new(Mutex::new(Self::new_empty(self.base(), self.size())?));
+        let mut new_aspace = Self::new_empty(self.base(), self.size())?;
+        new_aspace.fault_around_pages = self.fault_around_pages;
+        new_aspace.readahead_pages = self.readahead_pages;
+        let new_aspace = Arc::new(Mutex::new(new_aspace));
         let new_aspace_clone = new_aspace.clone();
 
         let mut guard = new_aspace.lock();
@@ -1,19 +1,27 @@
 use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc};
-use core::slice;
+use core::{
+    ops::Range,
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::FileBackend;
+use axfs_ng::{CachedFile, EvictedPage, FileBackend, PageAction};
 use axhal::{
     mem::phys_to_virt,
     paging::{MappingFlags, PageSize, PageTableMut, PagingError},
 };
 use axsync::Mutex;
 use kspin::SpinNoIrq;
-use memory_addr::{PhysAddr, VirtAddr, VirtAddrRange};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr, VirtAddrRange};
 
 use crate::{
     AddrSpace,
-    backend::{Backend, BackendOps, alloc_frame, dealloc_frame, pages_in, paging_to_linux_error},
+    backend::{
+        Backend, BackendOps, alloc_frame, dealloc_frame, pages_in, paging_to_linux_error,
+        readahead::Readahead,
+    },
+    stats::{FaultKind, FaultStats},
 };
 
 static FRAME_TABLE: SpinNoIrq<BTreeMap<PhysAddr, u8>> = SpinNoIrq::new(BTreeMap::new());
@@ -38,17 +46,229 @@ fn dec_frame_ref(paddr: PhysAddr) -> usize {
     }
 }
 
+/// Returns whether `paddr` is a frame of a private mapping, counted in
+/// [`FRAME_TABLE`], rather than a page of the page cache.
+fn is_private(paddr: PhysAddr) -> bool {
+    FRAME_TABLE.lock().contains_key(&paddr)
+}
+
+/// Unmaps the page at `vaddr` if it is a page of the page cache.
+fn unmap_cached(pt: &mut PageTableMut, vaddr: VirtAddr) -> LinuxResult<()> {
+    match pt.query(vaddr) {
+        Ok((paddr, ..)) if !is_private(paddr) => {
+            pt.unmap(vaddr).map_err(paging_to_linux_error)?;
+            Ok(())
+        }
+        Ok(_) | Err(PagingError::NotMapped) => Ok(()),
+        Err(_) => Err(LinuxError::EFAULT),
+    }
+}
+
+/// The page cache pages of a file mapped read-only by a private mapping
+/// until they are first written to.
+///
+/// The pages are unmapped when they leave the page cache.
+struct CachedPages {
+    start: VirtAddr,
+    cache: CachedFile,
+    file_start: u64,
+    handle: AtomicUsize,
+}
+
+impl Drop for CachedPages {
+    fn drop(&mut self) {
+        let handle = self.handle.load(Ordering::Acquire);
+        if handle != 0 {
+            unsafe {
+                self.cache.remove_mapping_listener(handle);
+            }
+        }
+    }
+}
+
+impl CachedPages {
+    fn new(
+        start: VirtAddr,
+        cache: &CachedFile,
+        file_start: u64,
+        aspace: &Arc<Mutex<AddrSpace>>,
+    ) -> Arc<Self> {
+        let this = Arc::new(Self {
+            start,
+            cache: cache.clone(),
+            file_start,
+            handle: AtomicUsize::new(0),
+        });
+        let handle = cache.add_mapping_listener(Arc::as_ptr(aspace) as usize, {
+            let this = Arc::downgrade(&this);
+            let aspace = Arc::downgrade(aspace);
+            move |pn, action| {
+                // The pages are mapped read-only already.
+                if action != PageAction::Unmap {
+                    return true;
+                }
+                let (Some(this), Some(aspace)) = (this.upgrade(), aspace.upgrade()) else {
+                    return true;
+                };
+                let Some(mut aspace) = aspace.try_lock() else {
+                    // The page cache retries or gives up on the page.
+                    return false;
+                };
+                this.on_evict(pn, &mut aspace);
+                true
+            }
+        });
+        this.handle.store(handle, Ordering::Release);
+        this
+    }
+
+    /// Returns the address file page `pn` would be mapped at.
+    fn vaddr(&self, pn: u32) -> Option<VirtAddr> {
+        let offset = (pn as u64 * PAGE_SIZE_4K as u64).checked_sub(self.file_start)?;
+        Some(self.start + offset as usize)
+    }
+
+    fn on_evict(self: &Arc<Self>, pn: u32, aspace: &mut AddrSpace) {
+        let Some(vaddr) = self.vaddr(pn) else {
+            return;
+        };
+        let Some(area) = aspace.find_area(vaddr) else {
+            return;
+        };
+        // Ignore if the page is not controlled by this mapping.
+        if !matches!(area.backend(), Backend::Cow(cow) if cow.maps(self)) {
+            return;
+        }
+        if let Err(err) = unmap_cached(&mut aspace.page_table_mut().to_mut(), vaddr) {
+            warn!("Failed to unmap page {:?}: {:?}", vaddr, err);
+        }
+    }
+}
+
 /// Copy-on-write mapping backend.
 ///
-/// This corresponds to the `MAP_PRIVATE` flag.
+/// This corresponds to the `MAP_PRIVATE` flag. Pages of the page cache
+/// mapped around a fault are shared read-only with the file until they are
+/// written to, while the pages allocated on faults are private.
 #[derive(Clone)]
 pub struct CowBackend {
     start: VirtAddr,
     size: PageSize,
     file: Option<(FileBackend, u64, Option<u64>)>,
+    cached: Option<Arc<CachedPages>>,
+    readahead: Arc<Readahead>,
 }
 
 impl CowBackend {
+    /// Returns the part of the page at `vaddr` that is backed by the file, as
+    /// the offset within the page and the corresponding file range.
+    fn file_range(
+        &self,
+        vaddr: VirtAddr,
+        file_start: u64,
+        file_end: Option<u64>,
+    ) -> (usize, Range<u64>) {
+        // vaddr can be smaller than self.start (at most 1 page) due to
+        // non-aligned mappings, we need to keep the gap clean.
+        let start = self.start.as_usize().saturating_sub(vaddr.as_usize());
+        assert!(start < self.size as _);
+
+        let file_start = file_start + vaddr.as_usize().saturating_sub(self.start.as_usize()) as u64;
+        let max_read = file_end
+            .map_or(u64::MAX, |end| end.saturating_sub(file_start))
+            .min((self.size as usize - start) as u64);
+        (start, file_start..file_start + max_read)
+    }
+
+    /// Returns whether the page cache pages mapped by this backend are
+    /// tracked by `cached`.
+    fn maps(&self, cached: &Arc<CachedPages>) -> bool {
+        self.cached
+            .as_ref()
+            .is_some_and(|it| Arc::ptr_eq(it, cached))
+    }
+
+    /// Returns the file page that can be mapped at `vaddr` as is, i.e. that
+    /// backs the whole page.
+    fn cached_page(&self, vaddr: VirtAddr) -> Option<u32> {
+        let (_, file_start, file_end) = self.file.as_ref()?;
+        let (start, range) = self.file_range(vaddr, *file_start, *file_end);
+        (start == 0
+            && range.start % PAGE_SIZE_4K as u64 == 0
+            && range.end - range.start == PAGE_SIZE_4K as u64)
+            .then(|| (range.start / PAGE_SIZE_4K as u64) as u32)
+    }
+
+    /// Unmaps a page evicted from the page cache if this mapping, which
+    /// spans `range`, maps it.
+    pub(crate) fn unmap_evicted(
+        &self,
+        range: VirtAddrRange,
+        page: &EvictedPage,
+        pt: &mut PageTableMut,
+    ) -> LinuxResult<()> {
+        let Some(cached) = &self.cached else {
+            return Ok(());
+        };
+        if !page.is_of(&cached.cache) {
+            return Ok(());
+        }
+        match cached.vaddr(page.pn()) {
+            Some(vaddr) if range.contains(vaddr) => unmap_cached(pt, vaddr),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the write permission `flags` gives from the pages in `range`
+    /// that are shared, with the page cache or other address spaces, so that
+    /// writes to them are still copied.
+    pub(crate) fn protect_shared(
+        &self,
+        range: VirtAddrRange,
+        flags: MappingFlags,
+        pt: &mut PageTableMut,
+    ) -> LinuxResult<()> {
+        if !flags.contains(MappingFlags::WRITE) {
+            return Ok(());
+        }
+        for addr in pages_in(range, self.size)? {
+            let Ok((paddr, ..)) = pt.query(addr) else {
+                continue;
+            };
+            let shared = FRAME_TABLE
+                .lock()
+                .get(&paddr)
+                .is_none_or(|count| *count > 1);
+            if shared {
+                pt.protect(addr, flags - MappingFlags::WRITE)
+                    .map_err(paging_to_linux_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether allocating the page at `vaddr` reads the file from the
+    /// storage, i.e. the file contents backing it are not all cached.
+    fn needs_io(&self, vaddr: VirtAddr) -> bool {
+        let Some((file, file_start, file_end)) = &self.file else {
+            return false;
+        };
+        let (_, range) = self.file_range(vaddr, *file_start, *file_end);
+        if range.is_empty() {
+            return false;
+        }
+        match file {
+            FileBackend::Cached(cache) => {
+                let first_page = (range.start / PAGE_SIZE_4K as u64) as u32;
+                let last_page = ((range.end - 1) / PAGE_SIZE_4K as u64) as u32;
+                !cache.in_memory()
+                    && !(first_page..=last_page)
+                        .all(|pn| cache.with_page(pn, |page| page.is_some()))
+            }
+            FileBackend::Direct(_) | FileBackend::Fifo(..) => true,
+        }
+    }
+
     fn alloc_new_at(
         &self,
         vaddr: VirtAddr,
@@ -62,18 +282,9 @@ impl CowBackend {
             let buf = unsafe {
                 slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), self.size as _)
             };
-            // vaddr can be smaller than self.start (at most 1 page) due to
-            // non-aligned mappings, we need to keep the gap clean.
-            let start = self.start.as_usize().saturating_sub(vaddr.as_usize());
-            assert!(start < self.size as _);
-
-            let file_start =
-                *file_start + vaddr.as_usize().saturating_sub(self.start.as_usize()) as u64;
-            let max_read = file_end
-                .map_or(u64::MAX, |end| end.saturating_sub(file_start))
-                .min((buf.len() - start) as u64) as usize;
-
-            file.read_at(&mut &mut buf[start..start + max_read], file_start)?;
+            let (start, range) = self.file_range(vaddr, *file_start, *file_end);
+            let max_read = (range.end - range.start) as usize;
+            file.read_at(&mut &mut buf[start..start + max_read], range.start)?;
         }
         pt.map(vaddr, frame, self.size, flags)
             .map_err(paging_to_linux_error)?;
@@ -86,18 +297,19 @@ impl CowBackend {
         paddr: PhysAddr,
         flags: MappingFlags,
         pt: &mut PageTableMut,
-    ) -> LinuxResult<()> {
+    ) -> LinuxResult<FaultKind> {
         match dec_frame_ref(paddr) {
-            0 => unreachable!(),
             // There is only one AddrSpace reference to the page,
             // so there is no need to copy it.
             1 => {
                 inc_frame_ref(paddr);
                 pt.protect(vaddr, flags).map_err(paging_to_linux_error)?;
+                Ok(FaultKind::CowReuse)
             }
             // Allocates the new page and copies the contents of the original page,
             // remapping the virtual address to the physical address of the new page.
-            2.. => {
+            // Pages of the page cache are not counted, and are always copied.
+            0 | 2.. => {
                 let new_frame = alloc_frame(false, self.size)?;
                 inc_frame_ref(new_frame);
                 unsafe {
@@ -110,10 +322,9 @@ impl CowBackend {
 
                 pt.remap(vaddr, new_frame, flags)
                     .map_err(paging_to_linux_error)?;
+                Ok(FaultKind::CowCopy)
             }
         }
-
-        Ok(())
     }
 }
 
@@ -153,6 +364,7 @@ impl BackendOps for CowBackend {
         flags: MappingFlags,
         access_flags: MappingFlags,
         pt: &mut PageTableMut,
+        stats: &mut FaultStats,
     ) -> LinuxResult<(usize, Option<Box<dyn FnOnce(&mut AddrSpace)>>)> {
         let mut pages = 0;
         for addr in pages_in(range, self.size)? {
@@ -162,14 +374,20 @@ impl BackendOps for CowBackend {
                     if access_flags.contains(MappingFlags::WRITE)
                         && !page_flags.contains(MappingFlags::WRITE)
                     {
-                        self.handle_cow_fault(addr, paddr, flags, pt)?;
+                        stats.record(self.handle_cow_fault(addr, paddr, flags, pt)?);
                         pages += 1;
                     }
                 }
                 // If the page is not mapped, try map it.
                 Err(PagingError::NotMapped) => {
+                    let kind = if self.needs_io(addr) {
+                        FaultKind::Major
+                    } else {
+                        FaultKind::Minor
+                    };
                     self.alloc_new_at(addr, flags, pt)?;
                     pages += 1;
+                    stats.record(kind);
                 }
                 Err(_) => return Err(LinuxError::EFAULT),
             }
@@ -177,13 +395,73 @@ impl BackendOps for CowBackend {
         Ok((pages, None))
     }
 
+    fn fault_around(
+        &self,
+        range: VirtAddrRange,
+        flags: MappingFlags,
+        pt: &mut PageTableMut,
+    ) -> LinuxResult<usize> {
+        let Some((FileBackend::Cached(cache), ..)) = &self.file else {
+            return Ok(0);
+        };
+        if self.size != PageSize::Size4K || self.cached.is_none() {
+            return Ok(0);
+        }
+        // The cached pages are mapped read-only, to be copied on the first
+        // write, while the pages not fully backed by the file are left to be
+        // allocated on demand.
+        let map_flags = flags - MappingFlags::WRITE;
+        let mut pages = 0;
+        for addr in pages_in(range, self.size)? {
+            if !matches!(pt.query(addr), Err(PagingError::NotMapped)) {
+                continue;
+            }
+            let Some(pn) = self.cached_page(addr) else {
+                continue;
+            };
+            cache.with_page(pn, |page| {
+                if let Some(page) = page {
+                    pt.map(addr, page.paddr(), PageSize::Size4K, map_flags)
+                        .map_err(paging_to_linux_error)?;
+                    pages += 1;
+                }
+                LinuxResult::Ok(())
+            })?;
+        }
+        Ok(pages)
+    }
+
+    fn readahead(&self, vaddr: VirtAddr, pages: usize) {
+        let Some((FileBackend::Cached(cache), file_start, file_end)) = &self.file else {
+            return;
+        };
+        let (_, range) = self.file_range(vaddr.align_down(self.size), *file_start, *file_end);
+        if range.is_empty() || cache.in_memory() {
+            return;
+        }
+        let limit = file_end.map_or(u32::MAX, |end| {
+            end.div_ceil(PAGE_SIZE_4K as u64).min(u32::MAX as u64) as u32
+        });
+        let cache = cache.clone();
+        self.readahead.on_fault(
+            (range.start / PAGE_SIZE_4K as u64) as u32,
+            pages.min(u32::MAX as usize) as u32,
+            limit,
+            move |pages| {
+                if let Err(err) = cache.prefetch(pages) {
+                    warn!("Failed to read ahead file pages: {err:?}");
+                }
+            },
+        );
+    }
+
     fn clone_map(
         &self,
         range: VirtAddrRange,
         flags: MappingFlags,
         old_pt: &mut PageTableMut,
         new_pt: &mut PageTableMut,
-        _new_aspace: &Arc<Mutex<AddrSpace>>,
+        new_aspace: &Arc<Mutex<AddrSpace>>,
     ) -> LinuxResult<Backend> {
         let cow_flags = flags - MappingFlags::WRITE;
 
@@ -196,11 +474,14 @@ impl BackendOps for CowBackend {
                     // - Update its permissions in the old page table using `flags`.
                     // - Map the same physical page into the new page table at the same
                     // virtual address, with the same page size and `flags`.
-                    inc_frame_ref(paddr);
-
-                    old_pt
-                        .protect(vaddr, cow_flags)
-                        .map_err(paging_to_linux_error)?;
+                    // Pages of the page cache are read-only already, and are
+                    // not counted.
+                    if is_private(paddr) {
+                        inc_frame_ref(paddr);
+                        old_pt
+                            .protect(vaddr, cow_flags)
+                            .map_err(paging_to_linux_error)?;
+                    }
                     new_pt
                         .map(vaddr, paddr, self.size, cow_flags)
                         .map_err(paging_to_linux_error)?;
@@ -211,7 +492,15 @@ impl BackendOps for CowBackend {
             };
         }
 
-        Ok(Backend::Cow(self.clone()))
+        let cached = self
+            .cached
+            .as_ref()
+            .map(|it| CachedPages::new(it.start, &it.cache, it.file_start, new_aspace));
+        Ok(Backend::Cow(Self {
+            cached,
+            readahead: Arc::default(),
+            ..self.clone()
+        }))
     }
 }
 
@@ -222,11 +511,20 @@ impl Backend {
         file: FileBackend,
         file_start: u64,
         file_end: Option<u64>,
+        aspace: &Arc<Mutex<AddrSpace>>,
     ) -> Self {
+        let cached = match &file {
+            FileBackend::Cached(cache) if size == PageSize::Size4K => {
+                Some(CachedPages::new(start, cache, file_start, aspace))
+            }
+            _ => None,
+        };
         Self::Cow(CowBackend {
             start,
             size,
             file: Some((file, file_start, file_end)),
+            cached,
+            readahead: Arc::default(),
         })
     }
 
@@ -235,6 +533,8 @@ impl Backend {
             start,
             size,
             file: None,
+            cached: None,
+            readahead: Arc::default(),
         })
     }
 }
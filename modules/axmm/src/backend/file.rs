@@ -3,17 +3,21 @@ use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
 };
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{CachedFile, FileFlags};
+use axfs_ng::{CachedFile, EvictedPage, FileFlags, PageAction};
 use axhal::paging::{MappingFlags, PageSize, PageTableMut, PagingError};
 use axsync::Mutex;
 use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 
 use crate::{
     AddrSpace,
-    backend::{Backend, BackendOps, pages_in, paging_to_linux_error},
+    backend::{Backend, BackendOps, pages_in, paging_to_linux_error, readahead::Readahead},
+    stats::{FaultKind, FaultStats},
 };
 
 #[doc(hidden)]
@@ -24,53 +28,65 @@ pub struct FileBackendInner {
     offset_page: u32,
     handle: AtomicUsize,
     futex_handle: Arc<()>,
+    aspace: Weak<Mutex<AddrSpace>>,
+    readahead: Arc<Readahead>,
 }
 impl Drop for FileBackendInner {
     fn drop(&mut self) {
         let handle = self.handle.load(Ordering::Acquire);
         if handle != 0 {
             unsafe {
-                self.cache.remove_evict_listener(handle);
+                self.cache.remove_mapping_listener(handle);
             }
         }
     }
 }
 impl FileBackendInner {
     pub fn register_listener(self: &Arc<Self>, aspace: &Arc<Mutex<AddrSpace>>) -> usize {
+        let key = Arc::as_ptr(aspace) as usize;
         let aspace = Arc::downgrade(aspace);
-        self.cache.add_evict_listener({
+        self.cache.add_mapping_listener(key, {
             let this = Arc::downgrade(self);
-            move |pn, _page| {
+            move |pn, action| {
                 let Some(this) = this.upgrade() else {
-                    return;
+                    return true;
                 };
                 let Some(aspace) = aspace.upgrade() else {
                     // The address space has been dropped, nothing to do.
-                    return;
+                    return true;
                 };
                 let Some(mut aspace) = aspace.try_lock() else {
-                    // This can happen during the populate process, when new pages
-                    // are being populated and old pages are being evicted. In this
-                    // case, we delegate the unmapping to the populate process.
-                    return;
+                    // The page cache retries or gives up on the page.
+                    return false;
                 };
-                this.on_evict(pn, &mut aspace);
+                match action {
+                    PageAction::Unmap => this.on_evict(pn, &mut aspace),
+                    PageAction::WriteProtect => this.on_write_protect(pn, &mut aspace),
+                }
+                true
             }
         })
     }
 
+    /// Returns the address file page `pn` is mapped at by this mapping, and
+    /// the flags of the mapping.
+    fn find_page(
+        self: &Arc<Self>,
+        pn: u32,
+        aspace: &AddrSpace,
+    ) -> Option<(VirtAddr, MappingFlags)> {
+        let pn = pn.checked_sub(self.offset_page)?;
+        let vaddr = self.start + pn as usize * PageSize::Size4K as usize;
+        let area = aspace.find_area(vaddr)?;
+        // Ignore if the page is not controlled by this file mapping.
+        matches!(area.backend(), Backend::File(file) if Arc::ptr_eq(&file.0, self))
+            .then_some((vaddr, area.flags()))
+    }
+
     fn on_evict(self: &Arc<Self>, pn: u32, aspace: &mut AddrSpace) {
-        let Some(pn) = pn.checked_sub(self.offset_page) else {
+        let Some((vaddr, _)) = self.find_page(pn, aspace) else {
             return;
         };
-        let vaddr = self.start + pn as usize * PageSize::Size4K as usize;
-        if !aspace.find_area(vaddr).is_some_and(
-            |it| matches!(it.backend(), Backend::File(file) if Arc::ptr_eq(&file.0, self)),
-        ) {
-            // Ignore if the page is not controlled by this file mapping.
-            return;
-        }
-
         let pt = aspace.page_table_mut();
         match pt.to_mut().unmap(vaddr) {
             Ok(_) | Err(PagingError::NotMapped) => {}
@@ -79,6 +95,30 @@ impl FileBackendInner {
             }
         }
     }
+
+    fn on_write_protect(self: &Arc<Self>, pn: u32, aspace: &mut AddrSpace) {
+        let Some((vaddr, flags)) = self.find_page(pn, aspace) else {
+            return;
+        };
+        if let Err(err) = write_protect_page(&mut aspace.page_table_mut().to_mut(), vaddr, flags) {
+            warn!("Failed to write-protect page {:?}: {:?}", vaddr, err);
+        }
+    }
+}
+
+/// Removes the write permission of the page at `vaddr` if it is mapped.
+fn write_protect_page(pt: &mut PageTableMut, vaddr: VirtAddr, flags: MappingFlags) -> LinuxResult {
+    match pt.query(vaddr) {
+        Ok((_, page_flags, _)) => {
+            if page_flags.contains(MappingFlags::WRITE) {
+                pt.protect(vaddr, flags - MappingFlags::WRITE)
+                    .map_err(paging_to_linux_error)?;
+            }
+            Ok(())
+        }
+        Err(PagingError::NotMapped) => Ok(()),
+        Err(_) => Err(LinuxError::EFAULT),
+    }
 }
 
 /// File-backed mapping backend.
@@ -103,6 +143,88 @@ impl FileBackend {
     pub fn futex_handle(&self) -> Weak<()> {
         Arc::downgrade(&self.0.futex_handle)
     }
+
+    fn page_number(&self, vaddr: VirtAddr) -> u32 {
+        ((vaddr - self.0.start) / PAGE_SIZE_4K) as u32 + self.0.offset_page
+    }
+
+    /// Returns the key identifying the address space of the mapping in the
+    /// page cache.
+    fn aspace_key(&self) -> usize {
+        self.0.aspace.as_ptr() as usize
+    }
+
+    /// Returns whether the two mappings are of the same file.
+    pub(crate) fn same_file(&self, other: &FileBackend) -> bool {
+        self.0.cache.ptr_eq(&other.0.cache)
+    }
+
+    /// Returns the file page numbers mapped within `range`.
+    pub(crate) fn pages(&self, range: VirtAddrRange) -> Range<u32> {
+        self.page_number(range.start)..self.page_number(range.end)
+    }
+
+    /// Write-protects the file pages in `pages` mapped by this mapping,
+    /// which spans `range`, so that later writes to them fault and mark the
+    /// pages dirty again.
+    pub(crate) fn write_protect(
+        &self,
+        range: VirtAddrRange,
+        pages: Range<u32>,
+        flags: MappingFlags,
+        pt: &mut PageTableMut,
+    ) -> LinuxResult<()> {
+        if self.0.cache.in_memory() {
+            // In memory files are never marked dirty.
+            return Ok(());
+        }
+        let mapped = self.pages(range);
+        for pn in pages.start.max(mapped.start)..pages.end.min(mapped.end) {
+            let vaddr = self.0.start + (pn - self.0.offset_page) as usize * PAGE_SIZE_4K;
+            write_protect_page(pt, vaddr, flags)?;
+        }
+        Ok(())
+    }
+
+    /// Writes back the dirty pages within the given file page range, after
+    /// write-protecting them in the other address spaces.
+    ///
+    /// The mappings of the pages in the address space of this mapping must
+    /// have been write-protected by [`write_protect`].
+    ///
+    /// [`write_protect`]: FileBackend::write_protect
+    pub(crate) fn sync_pages(&self, pages: Range<u32>, wait: bool) -> LinuxResult<()> {
+        self.0.cache.sync_pages(pages, wait, self.aspace_key())?;
+        Ok(())
+    }
+
+    /// Unmaps a page evicted from the page cache if this mapping, which
+    /// spans `range`, maps it.
+    pub(crate) fn unmap_evicted(
+        &self,
+        range: VirtAddrRange,
+        page: &EvictedPage,
+        pt: &mut PageTableMut,
+    ) -> LinuxResult<()> {
+        if !page.is_of(&self.0.cache) || !self.pages(range).contains(&page.pn()) {
+            return Ok(());
+        }
+        let vaddr = self.0.start + (page.pn() - self.0.offset_page) as usize * PAGE_SIZE_4K;
+        match pt.unmap(vaddr) {
+            Ok(_) | Err(PagingError::NotMapped) => Ok(()),
+            Err(err) => Err(paging_to_linux_error(err)),
+        }
+    }
+
+    fn map_flags(&self, flags: MappingFlags) -> MappingFlags {
+        if self.0.cache.in_memory() {
+            // For in memory files, we don't need to (and also musn't) mark
+            // them dirty, so we can use the original flags.
+            flags
+        } else {
+            flags - MappingFlags::WRITE
+        }
+    }
 }
 
 impl BackendOps for FileBackend {
@@ -147,67 +269,123 @@ impl BackendOps for FileBackend {
         flags: MappingFlags,
         access_flags: MappingFlags,
         pt: &mut PageTableMut,
+        stats: &mut FaultStats,
     ) -> LinuxResult<(usize, Option<Box<dyn FnOnce(&mut AddrSpace)>>)> {
         let mut pages = 0;
         let mut to_be_evicted = Vec::new();
-        let start_page = ((range.start - self.0.start) / PAGE_SIZE_4K) as u32 + self.0.offset_page;
-        for (i, addr) in pages_in(range, PageSize::Size4K)?.enumerate() {
-            let pn = start_page + i as u32;
-            match pt.query(addr) {
-                Ok((paddr, page_flags, _)) => {
-                    if access_flags.contains(MappingFlags::WRITE)
-                        && !page_flags.contains(MappingFlags::WRITE)
-                    {
-                        let in_memory = self.0.cache.in_memory();
-                        self.0.cache.with_page(pn, |page| {
-                            if !in_memory {
-                                page.expect("page should be present").mark_dirty();
-                            }
-                            pt.remap(addr, paddr, flags)
-                                .map_err(paging_to_linux_error)?;
-                            pages += 1;
-                            Ok(())
-                        })?;
-                    }
-                }
-                // If the page is not mapped, try map it.
-                Err(PagingError::NotMapped) => {
-                    let map_flags = if self.0.cache.in_memory() {
-                        // For in memory files, we don't need to (and also
-                        // musn't) mark them dirty, so we can use the original
-                        // flags.
-                        flags
-                    } else {
-                        flags - MappingFlags::WRITE
-                    };
-                    self.0.cache.with_page_or_insert(pn, |page, evicted| {
-                        if let Some((pn, _)) = evicted {
-                            to_be_evicted.push(pn);
+        let start_page = self.page_number(range.start);
+        let mut populate = || {
+            for (i, addr) in pages_in(range, PageSize::Size4K)?.enumerate() {
+                let pn = start_page + i as u32;
+                match pt.query(addr) {
+                    Ok((paddr, page_flags, _)) => {
+                        if access_flags.contains(MappingFlags::WRITE)
+                            && !page_flags.contains(MappingFlags::WRITE)
+                        {
+                            let in_memory = self.0.cache.in_memory();
+                            self.0.cache.with_page(pn, |page| {
+                                if !in_memory {
+                                    page.expect("page should be present").mark_dirty();
+                                }
+                                pt.remap(addr, paddr, flags)
+                                    .map_err(paging_to_linux_error)?;
+                                pages += 1;
+                                stats.record(FaultKind::Minor);
+                                Ok(())
+                            })?;
                         }
-                        pt.map(addr, page.paddr(), PageSize::Size4K, map_flags)
-                            .map_err(paging_to_linux_error)?;
-                        pages += 1;
-                        Ok(())
-                    })?;
+                    }
+                    // If the page is not mapped, try map it.
+                    Err(PagingError::NotMapped) => {
+                        let map_flags = self.map_flags(flags);
+                        // Pages of in memory files are created without I/O.
+                        let kind = if self.0.cache.in_memory()
+                            || self.0.cache.with_page(pn, |page| page.is_some())
+                        {
+                            FaultKind::Minor
+                        } else {
+                            FaultKind::Major
+                        };
+                        let aspace = self.aspace_key();
+                        self.0
+                            .cache
+                            .with_page_or_insert(pn, aspace, |page, evicted| {
+                                to_be_evicted.extend(evicted);
+                                pt.map(addr, page.paddr(), PageSize::Size4K, map_flags)
+                                    .map_err(paging_to_linux_error)?;
+                                pages += 1;
+                                stats.record(kind);
+                                Ok(())
+                            })?;
+                    }
+                    Err(_) => return Err(LinuxError::EFAULT),
                 }
-                Err(_) => return Err(LinuxError::EFAULT),
             }
+            LinuxResult::Ok(())
+        };
+        if let Err(err) = populate() {
+            // The evicted pages may still be mapped in this address space,
+            // which is only reachable through the callback, so they are
+            // leaked rather than freed while mapped.
+            core::mem::forget(to_be_evicted);
+            return Err(err);
         }
         Ok((
             pages,
             if to_be_evicted.is_empty() {
                 None
             } else {
-                let inner = self.0.clone();
                 Some(Box::new(move |aspace: &mut AddrSpace| {
-                    for pn in to_be_evicted {
-                        inner.on_evict(pn, aspace);
-                    }
+                    aspace.unmap_evicted(to_be_evicted)
                 }))
             },
         ))
     }
 
+    fn fault_around(
+        &self,
+        range: VirtAddrRange,
+        flags: MappingFlags,
+        pt: &mut PageTableMut,
+    ) -> LinuxResult<usize> {
+        let mut pages = 0;
+        let map_flags = self.map_flags(flags);
+        let start_page = self.page_number(range.start);
+        for (i, addr) in pages_in(range, PageSize::Size4K)?.enumerate() {
+            if !matches!(pt.query(addr), Err(PagingError::NotMapped)) {
+                continue;
+            }
+            self.0.cache.with_page(start_page + i as u32, |page| {
+                if let Some(page) = page {
+                    pt.map(addr, page.paddr(), PageSize::Size4K, map_flags)
+                        .map_err(paging_to_linux_error)?;
+                    pages += 1;
+                }
+                LinuxResult::Ok(())
+            })?;
+        }
+        Ok(pages)
+    }
+
+    fn readahead(&self, vaddr: VirtAddr, pages: usize) {
+        if self.0.cache.in_memory() {
+            return;
+        }
+        let cache = self.0.cache.clone();
+        self.0.readahead.on_fault(
+            self.page_number(vaddr),
+            pages.min(u32::MAX as usize) as u32,
+            u32::MAX,
+            move |pages| {
+                // The I/O is done without the address space lock, so that
+                // faults are not held up by it.
+                if let Err(err) = cache.prefetch(pages) {
+                    warn!("Failed to read ahead file pages: {err:?}");
+                }
+            },
+        );
+    }
+
     fn clone_map(
         &self,
         _range: VirtAddrRange,
@@ -223,8 +401,11 @@ impl BackendOps for FileBackend {
             offset_page: self.0.offset_page,
             handle: AtomicUsize::new(0),
             futex_handle: self.0.futex_handle.clone(),
+            aspace: Arc::downgrade(new_aspace),
+            readahead: Arc::default(),
         });
-        inner.register_listener(new_aspace);
+        let handle = inner.register_listener(new_aspace);
+        inner.handle.store(handle, Ordering::Release);
         Ok(Backend::File(FileBackend(inner)))
     }
 }
@@ -245,8 +426,11 @@ impl Backend {
             offset_page,
             handle: AtomicUsize::new(0),
             futex_handle: Arc::new(()),
+            aspace: Arc::downgrade(aspace),
+            readahead: Arc::default(),
         });
-        inner.register_listener(aspace);
+        let handle = inner.register_listener(aspace);
+        inner.handle.store(handle, Ordering::Release);
         Self::File(FileBackend(inner))
     }
 }
@@ -16,11 +16,12 @@ use memory_set::MappingBackend;
 pub mod cow;
 pub mod file;
 pub mod linear;
+mod readahead;
 pub mod shared;
 
 pub use shared::SharedPages;
 
-use crate::{AddrSpace, page_iter::PageIterWrapper};
+use crate::{AddrSpace, page_iter::PageIterWrapper, stats::FaultStats};
 
 fn divide_page(size: usize, page_size: PageSize) -> usize {
     assert!(page_size.is_aligned(size), "unaligned");
@@ -58,7 +59,7 @@ fn dealloc_frame(frame: PhysAddr, align: PageSize) {
     global_allocator().dealloc_pages(vaddr.as_usize(), num_pages, UsageKind::UserMem);
 }
 
-fn paging_to_linux_error(err: PagingError) -> LinuxError {
+pub(crate) fn paging_to_linux_error(err: PagingError) -> LinuxError {
     warn!("Paging error: {:?}", err);
     match err {
         PagingError::NoMemory => LinuxError::ENOMEM,
@@ -97,16 +98,39 @@ pub trait BackendOps {
     }
 
     /// Populate a memory region. Returns number of pages populated.
+    ///
+    /// The kind of fault each populated page counts as is recorded in
+    /// `stats`.
     fn populate(
         &self,
         _range: VirtAddrRange,
         _flags: MappingFlags,
         _access_flags: MappingFlags,
         _pt: &mut PageTableMut,
+        _stats: &mut FaultStats,
     ) -> LinuxResult<(usize, Option<Box<dyn FnOnce(&mut AddrSpace)>>)> {
         Ok((0, None))
     }
 
+    /// Maps the pages within `range` that are not mapped yet but are available
+    /// without I/O, e.g. already in the page cache. Returns number of pages
+    /// mapped.
+    ///
+    /// This is called around a faulting page to save the faults on its
+    /// neighbors.
+    fn fault_around(
+        &self,
+        _range: VirtAddrRange,
+        _flags: MappingFlags,
+        _pt: &mut PageTableMut,
+    ) -> LinuxResult<usize> {
+        Ok(0)
+    }
+
+    /// Called on a page fault at `vaddr`, so that the backend can read up to
+    /// `pages` pages ahead if the faults look sequential.
+    fn readahead(&self, _vaddr: VirtAddr, _pages: usize) {}
+
     /// Duplicates this mapping for use in a different page table.
     ///
     /// This differs from `clone`, which is designed for splitting a mapping
@@ -133,6 +157,18 @@ pub enum Backend {
     File(file::FileBackend),
 }
 
+impl Backend {
+    /// Returns the name of the backend, for diagnostics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Linear(_) => "linear",
+            Backend::Cow(_) => "cow",
+            Backend::Shared(_) => "shared",
+            Backend::File(_) => "file",
+        }
+    }
+}
+
 impl MappingBackend for Backend {
     type Addr = VirtAddr;
     type Flags = MappingFlags;
@@ -165,6 +201,19 @@ impl MappingBackend for Backend {
         new_flags: Self::Flags,
         pt: &mut Self::PageTable,
     ) -> bool {
-        pt.to_mut().protect_region(start, size, new_flags).is_ok()
+        let mut pt = pt.to_mut();
+        if pt.protect_region(start, size, new_flags).is_err() {
+            return false;
+        }
+        let Backend::Cow(cow) = self else {
+            return true;
+        };
+        let range = VirtAddrRange::from_start_size(start, size);
+        if let Err(err) = cow.protect_shared(range, new_flags, &mut pt) {
+            warn!("Failed to write-protect shared pages: {:?}", err);
+            false
+        } else {
+            true
+        }
     }
 }
@@ -0,0 +1,153 @@
+//! Readahead for file-backed mappings.
+
+use alloc::sync::Arc;
+#[cfg(feature = "multitask")]
+use alloc::{boxed::Box, collections::VecDeque};
+#[cfg(feature = "multitask")]
+use core::{
+    mem,
+    task::{Poll, Waker},
+};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use kspin::SpinNoIrq;
+
+#[derive(Default)]
+struct Window {
+    /// The file page of the last fault.
+    prev: Option<u32>,
+    /// The first file page not covered by previous readahead.
+    next: u32,
+}
+
+/// Marks the readahead of a mapping as done when dropped, even if it
+/// panics.
+#[cfg(feature = "multitask")]
+struct Running(Arc<Readahead>);
+
+#[cfg(feature = "multitask")]
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::Release);
+    }
+}
+
+/// A readahead request, reading the pages of a mapping.
+#[cfg(feature = "multitask")]
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The readahead requests of all mappings, served in order by a single
+/// worker task.
+#[cfg(feature = "multitask")]
+struct Queue {
+    jobs: VecDeque<Job>,
+    /// Wakes the worker once a request is queued, `None` while it is busy.
+    waker: Option<Waker>,
+    started: bool,
+}
+
+#[cfg(feature = "multitask")]
+static QUEUE: SpinNoIrq<Queue> = SpinNoIrq::new(Queue {
+    jobs: VecDeque::new(),
+    waker: None,
+    started: false,
+});
+
+/// Serves the queued readahead requests one at a time.
+#[cfg(feature = "multitask")]
+fn worker() {
+    loop {
+        let job = axtask::future::block_on(core::future::poll_fn(|cx| {
+            let mut queue = QUEUE.lock();
+            match queue.jobs.pop_front() {
+                Some(job) => Poll::Ready(job),
+                None => {
+                    queue.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }));
+        job();
+    }
+}
+
+/// Tracks the page faults on a file-backed mapping to detect sequential
+/// access.
+#[derive(Default)]
+pub(crate) struct Readahead {
+    window: SpinNoIrq<Window>,
+    running: AtomicBool,
+}
+
+impl Readahead {
+    /// Records a page fault on file page `pn`.
+    ///
+    /// If the faults look sequential, i.e. each one is at most `pages` pages
+    /// after the previous one, `f` is queued for the readahead worker to read
+    /// up to `pages` pages after `pn`, but not beyond `limit`. Readahead for a
+    /// mapping is issued once the faults get close to the end of the previous
+    /// readahead window, and only one is in flight at a time.
+    pub fn on_fault(
+        self: &Arc<Self>,
+        pn: u32,
+        pages: u32,
+        limit: u32,
+        f: impl FnOnce(Range<u32>) + Send + 'static,
+    ) {
+        if pages == 0 {
+            return;
+        }
+        let mut window = self.window.lock();
+        let sequential = window
+            .prev
+            .is_some_and(|prev| pn > prev && pn - prev <= pages);
+        window.prev = Some(pn);
+        if !sequential {
+            window.next = 0;
+            return;
+        }
+        if pn.saturating_add(pages / 2) < window.next || self.running.load(Ordering::Acquire) {
+            return;
+        }
+        let start = window.next.max(pn + 1);
+        let end = start.saturating_add(pages).min(limit);
+        if start >= end {
+            return;
+        }
+        window.next = end;
+        self.running.store(true, Ordering::Release);
+        drop(window);
+
+        self.queue(start..end, f);
+    }
+
+    #[cfg(feature = "multitask")]
+    fn queue(self: &Arc<Self>, pages: Range<u32>, f: impl FnOnce(Range<u32>) + Send + 'static) {
+        let running = Running(self.clone());
+        let job = Box::new(move || {
+            let _running = running;
+            f(pages);
+        });
+        let mut queue = QUEUE.lock();
+        queue.jobs.push_back(job);
+        let waker = queue.waker.take();
+        let start = !mem::replace(&mut queue.started, true);
+        drop(queue);
+
+        if start {
+            axtask::spawn(worker, "readahead".into());
+        }
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    #[cfg(not(feature = "multitask"))]
+    fn queue(self: &Arc<Self>, _pages: Range<u32>, _f: impl FnOnce(Range<u32>) + Send + 'static) {
+        // There is no worker task to read ahead in.
+        self.running.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,146 @@
+//! Mapping MMIO regions into the kernel address space.
+
+use core::fmt;
+
+use axerrno::{LinuxResult, bail};
+use axhal::paging::MappingFlags;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange};
+
+use crate::{
+    backend::Backend,
+    vmalloc::{map_kernel_area, unmap_kernel_area},
+};
+
+/// Memory attributes of an MMIO mapping created by [`ioremap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoRemapAttr {
+    /// Strongly-ordered device memory, for device registers.
+    Device,
+    /// Normal memory with caching disabled.
+    Uncached,
+    /// Write-combining memory, for framebuffers and other regions whose
+    /// driver allows writes to be merged.
+    ///
+    /// The page table layer does not expose a dedicated write-combining
+    /// memory type, so this uses the normal non-cacheable memory type, which
+    /// allows write combining on architectures that support it.
+    WriteCombine,
+}
+
+impl IoRemapAttr {
+    fn mapping_flags(self) -> MappingFlags {
+        MappingFlags::READ
+            | MappingFlags::WRITE
+            | match self {
+                IoRemapAttr::Device => MappingFlags::DEVICE,
+                IoRemapAttr::Uncached | IoRemapAttr::WriteCombine => MappingFlags::UNCACHED,
+            }
+    }
+}
+
+/// An MMIO region mapped into the kernel address space by [`ioremap`].
+///
+/// The mapping is removed when it is dropped.
+pub struct IoMapping {
+    /// Start of the page-aligned mapped range.
+    start: VirtAddr,
+    /// Size of the page-aligned mapped range.
+    size: usize,
+    paddr: PhysAddr,
+    len: usize,
+    attr: IoRemapAttr,
+}
+
+impl IoMapping {
+    /// Returns the virtual address corresponding to the requested physical
+    /// address.
+    pub fn vaddr(&self) -> VirtAddr {
+        self.start + self.paddr.align_offset_4k()
+    }
+
+    /// Returns the physical address the mapping was requested for.
+    pub fn paddr(&self) -> PhysAddr {
+        self.paddr
+    }
+
+    /// Returns the size (in bytes) the mapping was requested for.
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the memory attributes of the mapping.
+    pub fn attr(&self) -> IoRemapAttr {
+        self.attr
+    }
+
+    /// Convert to a mutable raw pointer.
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.vaddr().as_mut_ptr()
+    }
+
+    /// Consumes the mapping without unmapping it, returning its virtual
+    /// address.
+    ///
+    /// This is used for regions that stay mapped for the lifetime of the
+    /// kernel, e.g. PCI BARs of probed devices.
+    pub fn leak(self) -> VirtAddr {
+        let vaddr = self.vaddr();
+        core::mem::forget(self);
+        vaddr
+    }
+
+    /// Removes the mapping.
+    pub fn unmap(self) {
+        drop(self);
+    }
+}
+
+impl fmt::Debug for IoMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IoMapping")
+            .field("vaddr", &self.vaddr())
+            .field("paddr", &self.paddr)
+            .field("size", &self.len)
+            .field("attr", &self.attr)
+            .finish()
+    }
+}
+
+impl Drop for IoMapping {
+    fn drop(&mut self) {
+        unmap_kernel_area(self.start, self.size);
+    }
+}
+
+/// Maps the MMIO region `[paddr, paddr + size)` into the kernel address space
+/// with the given memory attributes.
+///
+/// Unlike [`phys_to_virt`], the region does not need to be covered by the
+/// linear mapping set up from the platform config. The region does not need
+/// to be page-aligned.
+///
+/// [`phys_to_virt`]: axhal::mem::phys_to_virt
+pub fn ioremap(paddr: PhysAddr, size: usize, attr: IoRemapAttr) -> LinuxResult<IoMapping> {
+    if size == 0 {
+        bail!(EINVAL, "zero-sized MMIO region");
+    }
+    let start_paddr = paddr.align_down_4k();
+    let map_size = (paddr + size).align_up_4k() - start_paddr;
+    let start = map_kernel_area(
+        map_size,
+        attr.mapping_flags(),
+        |start| Backend::new_linear(start.as_usize() as isize - start_paddr.as_usize() as isize),
+        false,
+    )?;
+    debug!(
+        "ioremap: {:?} -> {paddr:?} ({attr:?})",
+        VirtAddrRange::from_start_size(start, map_size),
+    );
+    Ok(IoMapping {
+        start,
+        size: map_size,
+        paddr,
+        len: size,
+        attr,
+    })
+}
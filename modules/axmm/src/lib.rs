@@ -9,7 +9,10 @@ extern crate alloc;
 
 mod aspace;
 pub mod backend;
+mod ioremap;
 mod page_iter;
+mod stats;
+mod vmalloc;
 
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{
@@ -21,7 +24,12 @@ use lazyinit::LazyInit;
 use memory_addr::{MemoryAddr, PhysAddr, va};
 use memory_set::MappingError;
 
-pub use self::aspace::AddrSpace;
+pub use self::{
+    aspace::{AddrSpace, DEFAULT_FAULT_AROUND_PAGES, DEFAULT_READAHEAD_PAGES},
+    ioremap::{IoMapping, IoRemapAttr, ioremap},
+    stats::{FaultStats, fault_tracing, global_fault_stats, set_fault_tracing},
+    vmalloc::{VMALLOC_GUARD_SIZE, VmallocArea, vmalloc, vmalloc_range},
+};
 
 static KERNEL_ASPACE: LazyInit<SpinNoIrq<AddrSpace>> = LazyInit::new();
 
@@ -71,6 +79,7 @@ pub fn new_kernel_aspace() -> LinuxResult<AddrSpace> {
             reg_flag_to_map_flag(r.flags),
         )?;
     }
+    vmalloc::populate_top_level_entries(&mut aspace)?;
     Ok(aspace)
 }
 
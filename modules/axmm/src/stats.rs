@@ -0,0 +1,139 @@
+//! Page fault statistics.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+/// Counters of page faults, either of an address space or of the whole
+/// system.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FaultStats {
+    /// Faults handled without I/O, e.g. by zero-filling a page or mapping a
+    /// page already in the page cache.
+    pub minor: u64,
+    /// Faults that needed I/O through a file backend.
+    pub major: u64,
+    /// Write faults on copy-on-write pages that copied the page.
+    pub cow_copy: u64,
+    /// Write faults on copy-on-write pages that reused the page, as it was
+    /// not shared any more.
+    pub cow_reuse: u64,
+    /// Faults that could not be handled.
+    pub failed: u64,
+}
+
+impl FaultStats {
+    /// Returns the total number of faults.
+    pub fn total(&self) -> u64 {
+        self.minor + self.major + self.cow_copy + self.cow_reuse + self.failed
+    }
+
+    pub(crate) fn record(&mut self, kind: FaultKind) {
+        *match kind {
+            FaultKind::Minor => &mut self.minor,
+            FaultKind::Major => &mut self.major,
+            FaultKind::CowCopy => &mut self.cow_copy,
+            FaultKind::CowReuse => &mut self.cow_reuse,
+            FaultKind::Failed => &mut self.failed,
+        } += 1;
+    }
+
+    pub(crate) fn add(&mut self, other: &FaultStats) {
+        self.minor += other.minor;
+        self.major += other.major;
+        self.cow_copy += other.cow_copy;
+        self.cow_reuse += other.cow_reuse;
+        self.failed += other.failed;
+    }
+
+    /// Returns the kind of the single fault recorded in `self`.
+    pub(crate) fn kind(&self) -> Option<FaultKind> {
+        [
+            (self.failed, FaultKind::Failed),
+            (self.major, FaultKind::Major),
+            (self.cow_copy, FaultKind::CowCopy),
+            (self.cow_reuse, FaultKind::CowReuse),
+            (self.minor, FaultKind::Minor),
+        ]
+        .into_iter()
+        .find_map(|(count, kind)| (count > 0).then_some(kind))
+    }
+}
+
+/// The kind of a page fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FaultKind {
+    Minor,
+    Major,
+    CowCopy,
+    CowReuse,
+    Failed,
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FaultKind::Minor => "minor",
+            FaultKind::Major => "major",
+            FaultKind::CowCopy => "cow copy",
+            FaultKind::CowReuse => "cow reuse",
+            FaultKind::Failed => "failed",
+        })
+    }
+}
+
+struct GlobalFaultStats {
+    minor: AtomicU64,
+    major: AtomicU64,
+    cow_copy: AtomicU64,
+    cow_reuse: AtomicU64,
+    failed: AtomicU64,
+}
+
+static GLOBAL_STATS: GlobalFaultStats = GlobalFaultStats {
+    minor: AtomicU64::new(0),
+    major: AtomicU64::new(0),
+    cow_copy: AtomicU64::new(0),
+    cow_reuse: AtomicU64::new(0),
+    failed: AtomicU64::new(0),
+};
+
+static FAULT_TRACING: AtomicBool = AtomicBool::new(false);
+
+/// Adds the counters of a handled fault to the global statistics.
+pub(crate) fn add_global(stats: &FaultStats) {
+    GLOBAL_STATS.minor.fetch_add(stats.minor, Ordering::Relaxed);
+    GLOBAL_STATS.major.fetch_add(stats.major, Ordering::Relaxed);
+    GLOBAL_STATS
+        .cow_copy
+        .fetch_add(stats.cow_copy, Ordering::Relaxed);
+    GLOBAL_STATS
+        .cow_reuse
+        .fetch_add(stats.cow_reuse, Ordering::Relaxed);
+    GLOBAL_STATS
+        .failed
+        .fetch_add(stats.failed, Ordering::Relaxed);
+}
+
+/// Returns the page fault counters of all address spaces since boot.
+pub fn global_fault_stats() -> FaultStats {
+    FaultStats {
+        minor: GLOBAL_STATS.minor.load(Ordering::Relaxed),
+        major: GLOBAL_STATS.major.load(Ordering::Relaxed),
+        cow_copy: GLOBAL_STATS.cow_copy.load(Ordering::Relaxed),
+        cow_reuse: GLOBAL_STATS.cow_reuse.load(Ordering::Relaxed),
+        failed: GLOBAL_STATS.failed.load(Ordering::Relaxed),
+    }
+}
+
+/// Enables or disables logging every page fault with its address, backend
+/// and kind.
+pub fn set_fault_tracing(enabled: bool) {
+    FAULT_TRACING.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether page fault tracing is enabled.
+pub fn fault_tracing() -> bool {
+    FAULT_TRACING.load(Ordering::Relaxed)
+}
@@ -0,0 +1,172 @@
+//! Virtually contiguous kernel allocations.
+
+use core::{fmt, slice};
+
+use axerrno::{LinuxResult, bail};
+use axhal::paging::{MappingFlags, PageSize, PagingError};
+use memory_addr::{
+    MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr, VirtAddrRange, align_down_4k, align_up_4k,
+};
+
+use crate::{
+    AddrSpace,
+    backend::{Backend, paging_to_linux_error},
+    kernel_aspace,
+};
+
+/// Size of the unmapped gap kept on both sides of every vmalloc area.
+pub const VMALLOC_GUARD_SIZE: usize = PAGE_SIZE_4K;
+
+/// Size of the range covered by one top-level page table entry, and the size
+/// of the pages mapped by a second-level entry.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+const TOP_LEVEL_SPAN: (usize, PageSize) = (1 << 30, PageSize::Size2M);
+#[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+const TOP_LEVEL_SPAN: (usize, PageSize) = (1 << 39, PageSize::Size1G);
+
+/// Returns the kernel virtual range used for vmalloc and ioremap areas.
+///
+/// The upper half of the kernel address space is reserved for it, so that it
+/// does not collide with the linear mapping of physical memory.
+pub fn vmalloc_range() -> VirtAddrRange {
+    let base = axconfig::plat::KERNEL_ASPACE_BASE;
+    let size = axconfig::plat::KERNEL_ASPACE_SIZE;
+    VirtAddrRange::from_start_size(
+        VirtAddr::from(base + size / 2).align_up_4k(),
+        align_down_4k(size / 2),
+    )
+}
+
+/// Creates the top-level page table entries of the whole vmalloc range in the
+/// kernel address space.
+///
+/// User address spaces copy the top-level entries of the kernel when they are
+/// created, so the entries must exist before any of them is, for areas
+/// created later to be visible in every address space.
+pub(crate) fn populate_top_level_entries(aspace: &mut AddrSpace) -> LinuxResult {
+    let range = vmalloc_range();
+    // Mapping a page of the size mapped by a second-level entry creates the
+    // top-level entry and the second-level table only, and unmapping it keeps
+    // them.
+    let (span, page_size) = TOP_LEVEL_SPAN;
+    let mut pt = aspace.page_table_mut().to_mut();
+    let mut vaddr = range.start.align_down(span);
+    while vaddr < range.end {
+        match pt.map(vaddr, PhysAddr::from(0), page_size, MappingFlags::READ) {
+            Ok(_) => {
+                pt.unmap(vaddr).map_err(paging_to_linux_error)?;
+            }
+            // Something is mapped there, so the top-level entry exists.
+            Err(PagingError::AlreadyMapped | PagingError::MappedToHugePage) => {}
+            Err(err) => return Err(paging_to_linux_error(err)),
+        }
+        vaddr = match vaddr.as_usize().checked_add(span) {
+            Some(next) => VirtAddr::from(next),
+            None => break,
+        };
+    }
+    Ok(())
+}
+
+/// Reserves a kernel virtual range of `size` bytes, surrounded by guard gaps,
+/// and maps it with `flags` using the backend built by `backend`.
+///
+/// Returns the start of the mapped (non-guard) part.
+pub(crate) fn map_kernel_area(
+    size: usize,
+    flags: MappingFlags,
+    backend: impl FnOnce(VirtAddr) -> Backend,
+    populate: bool,
+) -> LinuxResult<VirtAddr> {
+    if size == 0 {
+        bail!(EINVAL, "zero-sized kernel area");
+    }
+    let limit = vmalloc_range();
+    let mut aspace = kernel_aspace().lock();
+    let Some(start) = aspace.find_free_area(limit.start, size + 2 * VMALLOC_GUARD_SIZE, limit)
+    else {
+        bail!(ENOMEM, "no free kernel virtual space");
+    };
+    let start = start + VMALLOC_GUARD_SIZE;
+    aspace.map(start, size, flags, populate, backend(start))?;
+    Ok(start)
+}
+
+/// Unmaps a kernel area previously mapped by [`map_kernel_area`].
+pub(crate) fn unmap_kernel_area(start: VirtAddr, size: usize) {
+    if let Err(err) = kernel_aspace().lock().unmap(start, size) {
+        warn!("Failed to unmap kernel area at {start:?}: {err:?}");
+    }
+}
+
+/// A virtually contiguous kernel memory region allocated by [`vmalloc`].
+///
+/// The backing frames are unmapped and freed when it is dropped.
+pub struct VmallocArea {
+    start: VirtAddr,
+    size: usize,
+}
+
+impl VmallocArea {
+    /// Returns the start virtual address of the area.
+    pub fn start_vaddr(&self) -> VirtAddr {
+        self.start
+    }
+
+    /// Returns the size (in bytes) of the area.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Convert to a raw pointer.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.start.as_ptr()
+    }
+
+    /// Convert to a mutable raw pointer.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.start.as_mut_ptr()
+    }
+
+    /// Forms a slice that can read data.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.size) }
+    }
+
+    /// Forms a mutable slice that can write data.
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.size) }
+    }
+}
+
+impl fmt::Debug for VmallocArea {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VmallocArea")
+            .field("start", &self.start)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl Drop for VmallocArea {
+    fn drop(&mut self) {
+        unmap_kernel_area(self.start, self.size);
+    }
+}
+
+/// Allocates a virtually contiguous, zero-filled kernel memory region.
+///
+/// Unlike [`axalloc::GlobalPage::alloc_contiguous`], the region is backed by
+/// frames that need not be physically contiguous, so it still succeeds when
+/// physical memory is fragmented. `size` is rounded up to 4K pages.
+pub fn vmalloc(size: usize) -> LinuxResult<VmallocArea> {
+    let size = align_up_4k(size);
+    let start = map_kernel_area(
+        size,
+        MappingFlags::READ | MappingFlags::WRITE,
+        |start| Backend::new_alloc(start, PageSize::Size4K),
+        true,
+    )?;
+    debug!("vmalloc: {:?}", VirtAddrRange::from_start_size(start, size));
+    Ok(VmallocArea { start, size })
+}
@@ -16,7 +16,7 @@ use smoltcp::{
 
 use crate::{
     consts::{ETHERNET_MAX_PENDING_PACKETS, STANDARD_MTU},
-    device::Device,
+    device::{Device, InterfaceStats},
 };
 
 const EMPTY_MAC: EthernetAddress = EthernetAddress([0; 6]);
@@ -31,6 +31,7 @@ pub struct EthernetDevice {
     inner: AxNetDevice,
     neighbors: HashMap<IpAddress, Option<Neighbor>>,
     ip: Ipv4Cidr,
+    stats: InterfaceStats,
 
     pending_packets: PacketBuffer<'static, IpAddress>,
 }
@@ -51,6 +52,7 @@ impl EthernetDevice {
             inner,
             neighbors: HashMap::new(),
             ip,
+            stats: InterfaceStats::default(),
 
             pending_packets,
         }
@@ -63,6 +65,7 @@ impl EthernetDevice {
 
     fn send_to<F>(
         inner: &mut AxNetDevice,
+        stats: &mut InterfaceStats,
         dst: EthernetAddress,
         size: usize,
         f: F,
@@ -96,8 +99,10 @@ impl EthernetDevice {
             tx_buf.packet_len(),
             tx_buf.packet()
         );
-        if let Err(err) = inner.transmit(tx_buf) {
-            warn!("transmit failed: {:?}", err);
+        let len = tx_buf.packet_len();
+        match inner.transmit(tx_buf) {
+            Ok(()) => stats.record_tx(len),
+            Err(err) => warn!("transmit failed: {:?}", err),
         }
     }
 
@@ -152,6 +157,7 @@ impl EthernetDevice {
 
         Self::send_to(
             &mut self.inner,
+            &mut self.stats,
             EthernetAddress::BROADCAST,
             arp_repr.buffer_len(),
             |buf| arp_repr.emit(&mut ArpPacket::new_unchecked(buf)),
@@ -218,6 +224,7 @@ impl EthernetDevice {
 
                 Self::send_to(
                     &mut self.inner,
+                    &mut self.stats,
                     source_hardware_addr,
                     response.buffer_len(),
                     |buf| response.emit(&mut ArpPacket::new_unchecked(buf)),
@@ -245,6 +252,7 @@ impl EthernetDevice {
 
                     Self::send_to(
                         &mut self.inner,
+                        &mut self.stats,
                         neighbor.hardware_address,
                         buf.len(),
                         |b| b.copy_from_slice(buf),
@@ -262,6 +270,10 @@ impl Device for EthernetDevice {
         &self.name
     }
 
+    fn stats(&self) -> InterfaceStats {
+        self.stats
+    }
+
     fn recv(&mut self, buffer: &mut PacketBuffer<()>, timestamp: Instant) -> bool {
         loop {
             let rx_buf = match self.inner.receive() {
@@ -279,6 +291,7 @@ impl Device for EthernetDevice {
                 rx_buf.packet()
             );
 
+            self.stats.record_rx(rx_buf.packet_len());
             let result = self.handle_frame(rx_buf.packet(), buffer, timestamp);
             self.inner.recycle_rx_buffer(rx_buf).unwrap();
             if result {
@@ -291,6 +304,7 @@ impl Device for EthernetDevice {
         if next_hop.is_broadcast() || self.ip.broadcast().map(IpAddress::Ipv4) == Some(next_hop) {
             Self::send_to(
                 &mut self.inner,
+                &mut self.stats,
                 EthernetAddress::BROADCAST,
                 packet.len(),
                 |buf| buf.copy_from_slice(packet),
@@ -304,6 +318,7 @@ impl Device for EthernetDevice {
                 if neighbor.expires_at > timestamp {
                     Self::send_to(
                         &mut self.inner,
+                        &mut self.stats,
                         neighbor.hardware_address,
                         packet.len(),
                         |buf| buf.copy_from_slice(packet),
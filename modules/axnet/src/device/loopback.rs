@@ -10,12 +10,13 @@ use smoltcp::{
 
 use crate::{
     consts::{SOCKET_BUFFER_SIZE, STANDARD_MTU},
-    device::Device,
+    device::{Device, InterfaceStats},
 };
 
 pub struct LoopbackDevice {
     buffer: PacketBuffer<'static, ()>,
     poll: PollSet,
+    stats: InterfaceStats,
 }
 impl LoopbackDevice {
     pub fn new() -> Self {
@@ -23,7 +24,11 @@ impl LoopbackDevice {
             vec![PacketMetadata::EMPTY; SOCKET_BUFFER_SIZE],
             vec![0u8; STANDARD_MTU * SOCKET_BUFFER_SIZE],
         );
-        Self { buffer, poll: PollSet::new() }
+        Self {
+            buffer,
+            poll: PollSet::new(),
+            stats: InterfaceStats::default(),
+        }
     }
 }
 
@@ -32,12 +37,17 @@ impl Device for LoopbackDevice {
         "lo"
     }
 
+    fn stats(&self) -> InterfaceStats {
+        self.stats
+    }
+
     fn recv(&mut self, buffer: &mut PacketBuffer<()>, _timestamp: Instant) -> bool {
         self.buffer.dequeue().ok().is_some_and(|(_, rx_buf)| {
             buffer
                 .enqueue(rx_buf.len(), ())
                 .unwrap()
                 .copy_from_slice(rx_buf);
+            self.stats.record_rx(rx_buf.len());
             true
         })
     }
@@ -46,6 +56,7 @@ impl Device for LoopbackDevice {
         match self.buffer.enqueue(packet.len(), ()) {
             Ok(tx_buf) => {
                 tx_buf.copy_from_slice(packet);
+                self.stats.record_tx(packet.len());
                 self.poll.wake();
                 true
             }
@@ -8,10 +8,36 @@ mod loopback;
 pub use ethernet::*;
 pub use loopback::*;
 
+/// Traffic counters of a network interface.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterfaceStats {
+    /// Number of packets received.
+    pub rx_packets: u64,
+    /// Number of bytes received.
+    pub rx_bytes: u64,
+    /// Number of packets sent.
+    pub tx_packets: u64,
+    /// Number of bytes sent.
+    pub tx_bytes: u64,
+}
+
+impl InterfaceStats {
+    pub(crate) fn record_rx(&mut self, len: usize) {
+        self.rx_packets += 1;
+        self.rx_bytes += len as u64;
+    }
+
+    pub(crate) fn record_tx(&mut self, len: usize) {
+        self.tx_packets += 1;
+        self.tx_bytes += len as u64;
+    }
+}
+
 pub trait Device: Send + Sync {
-    #[allow(unused)]
     fn name(&self) -> &str;
 
+    fn stats(&self) -> InterfaceStats;
+
     fn recv(&mut self, buffer: &mut PacketBuffer<()>, timestamp: Instant) -> bool;
     /// Sends a packet to the next hop.
     ///
@@ -33,10 +33,11 @@ pub mod udp;
 pub mod unix;
 mod wrapper;
 
-use alloc::{borrow::ToOwned, boxed::Box};
+use alloc::{borrow::ToOwned, boxed::Box, string::String, vec::Vec};
 
 use axdriver::{AxDeviceContainer, prelude::*};
 use axsync::Mutex;
+pub use device::InterfaceStats;
 use lazyinit::LazyInit;
 use smoltcp::wire::{EthernetAddress, Ipv4Address, Ipv4Cidr};
 pub use socket::*;
@@ -112,6 +113,16 @@ pub fn init_network(mut net_devs: AxDeviceContainer<AxNetDevice>) {
     LISTEN_TABLE.init_once(ListenTable::new());
 }
 
+/// Returns the names and traffic counters of all network interfaces.
+///
+/// Returns an empty list if the network subsystem is not initialized.
+pub fn interface_stats() -> Vec<(String, InterfaceStats)> {
+    if !SERVICE.is_inited() {
+        return Vec::new();
+    }
+    SERVICE.lock().interface_stats()
+}
+
 pub fn poll_interfaces() {
     while SERVICE.lock().poll(&mut SOCKET_SET.inner.lock()) {}
 }
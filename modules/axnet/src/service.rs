@@ -1,3 +1,4 @@
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
 use core::{
     pin::pin,
     task::{Context, Waker},
@@ -12,7 +13,7 @@ use smoltcp::{
     wire::{HardwareAddress, IpAddress, IpListenEndpoint},
 };
 
-use crate::{SOCKET_SET, router::Router};
+use crate::{SOCKET_SET, device::InterfaceStats, router::Router};
 
 fn now() -> Instant {
     Instant::from_micros_const((wall_time_nanos() / NANOS_PER_MICROS) as i64)
@@ -38,6 +39,14 @@ impl Service {
         self.router.dispatch(timestamp)
     }
 
+    pub fn interface_stats(&self) -> Vec<(String, InterfaceStats)> {
+        self.router
+            .devices
+            .iter()
+            .map(|dev| (dev.name().to_owned(), dev.stats()))
+            .collect()
+    }
+
     pub fn get_source_address(&self, dst_addr: &IpAddress) -> IpAddress {
         let Some(rule) = self.router.table.lookup(dst_addr) else {
             panic!("no route to destination: {dst_addr}");
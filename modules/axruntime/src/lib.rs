@@ -21,6 +21,8 @@
 
 #[macro_use]
 extern crate axlog;
+#[cfg(feature = "fs")]
+extern crate alloc;
 
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
@@ -28,6 +30,9 @@ mod lang_items;
 #[cfg(feature = "smp")]
 mod mp;
 
+#[cfg(feature = "fs")]
+mod procfs;
+
 #[cfg(feature = "smp")]
 pub use self::mp::rust_main_secondary;
 
@@ -82,6 +87,43 @@ impl axlog::LogIf for LogIfImpl {
     }
 }
 
+#[cfg(any(
+    feature = "fs",
+    feature = "net",
+    feature = "display",
+    feature = "input"
+))]
+struct DriverIfImpl;
+
+#[cfg(any(
+    feature = "fs",
+    feature = "net",
+    feature = "display",
+    feature = "input"
+))]
+#[crate_interface::impl_interface]
+impl axdriver::DriverIf for DriverIfImpl {
+    fn ioremap(paddr: usize, size: usize, write_combine: bool) -> Option<usize> {
+        #[cfg(feature = "paging")]
+        {
+            let attr = if write_combine {
+                axmm::IoRemapAttr::WriteCombine
+            } else {
+                axmm::IoRemapAttr::Device
+            };
+            axmm::ioremap(paddr.into(), size, attr)
+                .inspect_err(|err| warn!("Failed to ioremap {paddr:#x} ({size:#x}): {err:?}"))
+                .ok()
+                .map(|mapping| mapping.leak().as_usize())
+        }
+        #[cfg(not(feature = "paging"))]
+        {
+            let _ = (size, write_combine);
+            Some(axhal::mem::phys_to_virt(paddr.into()).as_usize())
+        }
+    }
+}
+
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 static INITED_CPUS: AtomicUsize = AtomicUsize::new(0);
@@ -195,19 +237,9 @@ pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
 
         #[cfg(feature = "fs")]
         {
-            #[allow(unused_imports)]
-            use axdriver::prelude::BaseDriverOps;
-
-            axfs_ng::ROOT_FS_CONTEXT.call_once(|| {
-                let dev = all_devices
-                    .block
-                    .take_one()
-                    .expect("No block device found!");
-                info!("Block device: {}", dev.device_name());
-                let fs = axfs_ng::fs::new_default(dev).expect("Failed to initialize filesystem");
-                let mount = axfs_ng_vfs::Mountpoint::new_root(&fs);
-                axfs_ng::FsContext::new(mount.root_location())
-            });
+            axfs_ng::ROOT_FS_CONTEXT.call_once(|| init_filesystems(&mut all_devices.block));
+            #[cfg(feature = "multitask")]
+            axfs_ng::start_writeback_daemon();
         }
 
         #[cfg(feature = "net")]
@@ -244,6 +276,11 @@ pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
 
     unsafe { main() };
 
+    #[cfg(feature = "fs")]
+    if let Err(err) = axfs_ng::sync_all() {
+        warn!("Failed to sync filesystems: {err:?}");
+    }
+
     #[cfg(feature = "multitask")]
     axtask::exit(0);
     #[cfg(not(feature = "multitask"))]
@@ -253,6 +290,81 @@ pub fn rust_main(cpu_id: usize, arg: usize) -> ! {
     }
 }
 
+#[cfg(feature = "fs")]
+fn init_filesystems(
+    block: &mut axdriver::AxDeviceContainer<axdriver::AxBlockDevice>,
+) -> axfs_ng::FsContext {
+    use alloc::vec::Vec;
+
+    use axdriver::prelude::BaseDriverOps;
+    use axfs_ng::{
+        FsContext, MountFlags,
+        bcache::CachedDisk,
+        fs::{devfs, procfs::ProcFilesystem, tmpfs::TmpFilesystem},
+    };
+
+    let mut devs = core::iter::from_fn(|| block.take_one()).collect::<Vec<_>>();
+    devs.reverse();
+    let root_index = root_device(&devs).expect("No root block device found!");
+    info!("Block device: {}", devs[root_index].device_name());
+    let disks = devs.into_iter().map(CachedDisk::new).collect::<Vec<_>>();
+    let disk = disks[root_index].clone();
+    let fs = axfs_ng::fs::probe(disk).expect("Failed to initialize filesystem");
+    let root = axfs_ng::mount_root("/dev/root", &fs, MountFlags::empty());
+    let cx = FsContext::new(root);
+
+    if cx.resolve("/dev").is_ok() {
+        // The root device shares its buffers with the root filesystem.
+        let mut devices = devfs::standard_devices();
+        devices.extend(devfs::block_devices(disks));
+        let devfs = devfs::DevFilesystem::new(devices);
+        if let Err(err) = cx.mount("devfs", "/dev", &devfs, MountFlags::NOEXEC) {
+            warn!("Failed to mount devfs on /dev: {err:?}");
+        }
+    }
+    if cx.resolve("/proc").is_ok() {
+        let procfs = ProcFilesystem::new(procfs::root_entries);
+        let flags = MountFlags::NOSUID | MountFlags::NOEXEC;
+        if let Err(err) = cx.mount("proc", "/proc", &procfs, flags) {
+            warn!("Failed to mount procfs on /proc: {err:?}");
+        }
+    }
+    if cx.resolve("/tmp").is_ok() {
+        let tmpfs = TmpFilesystem::new(None);
+        if let Err(err) = cx.mount("tmpfs", "/tmp", &tmpfs, MountFlags::NOSUID) {
+            warn!("Failed to mount tmpfs on /tmp: {err:?}");
+        }
+    }
+    cx
+}
+
+/// Selects the root device among `devs`, the whole disks in probe order,
+/// each followed by its partitions.
+///
+/// The root is the partition given by `AX_ROOT` at build time
+/// (`PARTLABEL=<name>` or `PARTUUID=<uuid>`) if set. Otherwise, it is the
+/// partition labeled `root`, or else the first partition of the first disk,
+/// or the first disk itself if it has no partition table.
+#[cfg(feature = "fs")]
+fn root_device(devs: &[axdriver::AxBlockDevice]) -> Option<usize> {
+    use axdriver::partition::PartitionSelector;
+
+    let find = |selector: PartitionSelector| {
+        devs.iter()
+            .position(|dev| dev.info().is_some_and(|info| selector.matches(info)))
+    };
+    if let Some(root) = option_env!("AX_ROOT") {
+        match PartitionSelector::parse(root) {
+            Some(selector) => return find(selector),
+            None => warn!("invalid AX_ROOT {root:?}, expected PARTLABEL=<name> or PARTUUID=<uuid>"),
+        }
+    }
+    find(PartitionSelector::Label("root")).or_else(|| {
+        let partitioned = devs.get(1).is_some_and(|dev| dev.info().is_some());
+        (!devs.is_empty()).then_some(partitioned as usize)
+    })
+}
+
 #[cfg(feature = "alloc")]
 fn init_allocator() {
     use axhal::mem::{MemRegionFlags, memory_regions, phys_to_virt};
@@ -0,0 +1,86 @@
+//! Entries of `/proc` that need the state of other modules.
+
+#[cfg(any(feature = "multitask", feature = "net"))]
+use alloc::{format, string::ToString};
+use alloc::{string::String, vec::Vec};
+#[cfg(any(feature = "multitask", feature = "net"))]
+use core::fmt::Write;
+
+use axfs_ng::fs::procfs::{self, ProcEntry};
+
+/// Lists the root directory of the procfs mounted on `/proc`.
+pub fn root_entries() -> Vec<(String, ProcEntry)> {
+    #[allow(unused_mut)]
+    let mut entries = procfs::default_entries();
+    #[cfg(feature = "net")]
+    entries.push((
+        "net".to_string(),
+        ProcEntry::fixed_dir([("dev".to_string(), ProcEntry::file(net_dev))].into()),
+    ));
+    #[cfg(feature = "multitask")]
+    entries.extend(axtask::tasks().iter().map(|task| {
+        (
+            task.id().as_u64().to_string(),
+            task_dir(alloc::sync::Arc::downgrade(task)),
+        )
+    }));
+    entries
+}
+
+/// Generates `/proc/net/dev` from the counters of the network interfaces.
+#[cfg(feature = "net")]
+fn net_dev() -> String {
+    let mut out = format!(
+        "{:>10} {:>12} {:>10} {:>12} {:>10}\n",
+        "Interface", "RX bytes", "RX packets", "TX bytes", "TX packets"
+    );
+    for (name, stats) in axnet::interface_stats() {
+        let _ = writeln!(
+            out,
+            "{:>10} {:>12} {:>10} {:>12} {:>10}",
+            format!("{name}:"),
+            stats.rx_bytes,
+            stats.rx_packets,
+            stats.tx_bytes,
+            stats.tx_packets
+        );
+    }
+    out
+}
+
+/// The `/proc/<id>` directory of a task, which is empty once the task is
+/// dropped.
+#[cfg(feature = "multitask")]
+fn task_dir(task: axtask::WeakAxTaskRef) -> ProcEntry {
+    let status = {
+        let task = task.clone();
+        move || {
+            let Some(task) = task.upgrade() else {
+                return String::new();
+            };
+            let cpus = task.cpumask();
+            let cpus = (0..axconfig::plat::CPU_NUM)
+                .filter(|&cpu| cpus.get(cpu))
+                .map(|cpu| cpu.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let mut out = String::new();
+            let _ = writeln!(out, "Name:\t{}", task.name());
+            let _ = writeln!(out, "Pid:\t{}", task.id().as_u64());
+            let _ = writeln!(out, "State:\t{:?}", task.state());
+            let _ = writeln!(out, "Cpus_allowed_list:\t{cpus}");
+            out
+        }
+    };
+    let comm = move || {
+        task.upgrade()
+            .map_or_else(String::new, |task| format!("{}\n", task.name()))
+    };
+    ProcEntry::fixed_dir(
+        [
+            ("comm".to_string(), ProcEntry::file(comm)),
+            ("status".to_string(), ProcEntry::file(status)),
+        ]
+        .into(),
+    )
+}
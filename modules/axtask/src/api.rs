@@ -3,6 +3,7 @@
 use alloc::{
     string::String,
     sync::{Arc, Weak},
+    vec::Vec,
 };
 
 use kernel_guard::NoPreemptIrqSave;
@@ -72,6 +73,11 @@ pub fn current() -> CurrentTask {
     CurrentTask::get()
 }
 
+/// Returns all tasks in the system, ordered by their IDs.
+pub fn tasks() -> Vec<AxTaskRef> {
+    crate::task::all_tasks()
+}
+
 /// Initializes the task scheduler (for the primary CPU).
 pub fn init_scheduler() {
     info!("Initialize scheduling...");
@@ -1,4 +1,4 @@
-use alloc::{boxed::Box, string::String, sync::Arc};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, string::String, sync::Arc, vec::Vec};
 #[cfg(feature = "preempt")]
 use core::sync::atomic::AtomicUsize;
 use core::{
@@ -19,7 +19,10 @@ use futures::task::AtomicWaker;
 use kspin::SpinNoIrq;
 use memory_addr::{VirtAddr, align_up_4k};
 
-use crate::{AxCpuMask, AxTask, AxTaskRef, future::block_on};
+use crate::{AxCpuMask, AxTask, AxTaskRef, WeakAxTaskRef, future::block_on};
+
+/// All tasks that have not been dropped, indexed by their IDs.
+static TASKS: SpinNoIrq<BTreeMap<u64, WeakAxTaskRef>> = SpinNoIrq::new(BTreeMap::new());
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -278,7 +281,10 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let id = self.id.as_u64();
+        let task = Arc::new(AxTask::new(self));
+        TASKS.lock().insert(id, Arc::downgrade(&task));
+        task
     }
 
     #[inline]
@@ -438,9 +444,21 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        TASKS.lock().remove(&self.id.as_u64());
     }
 }
 
+/// Returns all tasks that have not been dropped yet, ordered by their IDs.
+///
+/// Exited tasks are included until their last reference is dropped.
+pub(crate) fn all_tasks() -> Vec<AxTaskRef> {
+    TASKS
+        .lock()
+        .values()
+        .filter_map(|task| task.upgrade())
+        .collect()
+}
+
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
@@ -0,0 +1,174 @@
+//! Consistency check of an image.
+//!
+//! The check walks the tree through the same code the kernel uses, so it
+//! reports the inconsistencies visible from the VFS rather than repairing
+//! on-disk structures: entries that cannot be looked up or read, directory
+//! listings that disagree with lookups, sizes that disagree with the
+//! contents, and (on ext4) link counts that disagree with the entries.
+
+use std::collections::BTreeMap;
+
+use axfs_ng::{FsContext, OpenOptions};
+use axfs_ng_vfs::{Location, NodeType};
+
+#[derive(Default)]
+pub struct Report {
+    pub errors: Vec<String>,
+    pub files: u64,
+    pub dirs: u64,
+}
+
+struct Checker<'a> {
+    cx: &'a FsContext,
+    /// Whether link counts are meaningful, which is not the case on FAT.
+    check_links: bool,
+    /// For every inode other than directories, its link count and the number
+    /// of entries found.
+    links: BTreeMap<u64, (u64, u64)>,
+    report: Report,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, path: &str, message: impl AsRef<str>) {
+        self.report
+            .errors
+            .push(format!("{path}: {}", message.as_ref()));
+    }
+
+    fn check_file(&mut self, path: &str, size: u64) {
+        let file = match OpenOptions::new()
+            .read(true)
+            .open(self.cx, path)
+            .and_then(|it| it.into_file())
+        {
+            Ok(file) => file,
+            Err(err) => return self.error(path, format!("cannot open: {err:?}")),
+        };
+        let mut buf = vec![0; 64 * 1024];
+        let mut len = 0;
+        loop {
+            match file.read_at(&mut buf.as_mut_slice(), len) {
+                Ok(0) => break,
+                Ok(n) => len += n as u64,
+                Err(err) => return self.error(path, format!("cannot read at {len}: {err:?}")),
+            }
+        }
+        if len != size {
+            self.error(path, format!("size is {size} but {len} bytes were read"));
+        }
+    }
+
+    fn check_dir(&mut self, path: &str, dir: &Location) {
+        self.report.dirs += 1;
+        let entries = match self.cx.read_dir(path) {
+            Ok(entries) => entries,
+            Err(err) => return self.error(path, format!("cannot list: {err:?}")),
+        };
+        let mut subdirs = Vec::new();
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    self.error(path, format!("cannot list: {err:?}"));
+                    break;
+                }
+            };
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let child_path = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+            let child = match dir.lookup_no_follow(&entry.name) {
+                Ok(child) => child,
+                Err(err) => {
+                    self.error(
+                        &child_path,
+                        format!("listed but cannot be looked up: {err:?}"),
+                    );
+                    continue;
+                }
+            };
+            let meta = match child.metadata() {
+                Ok(meta) => meta,
+                Err(err) => {
+                    self.error(&child_path, format!("cannot stat: {err:?}"));
+                    continue;
+                }
+            };
+            if meta.inode != entry.ino {
+                self.error(
+                    &child_path,
+                    format!("listed as inode {} but is inode {}", entry.ino, meta.inode),
+                );
+            }
+            if meta.node_type != entry.node_type {
+                self.error(
+                    &child_path,
+                    format!(
+                        "listed as {:?} but is {:?}",
+                        entry.node_type, meta.node_type
+                    ),
+                );
+            }
+            match meta.node_type {
+                NodeType::Directory => subdirs.push((child_path, child)),
+                NodeType::RegularFile => {
+                    self.report.files += 1;
+                    self.check_file(&child_path, meta.size);
+                }
+                NodeType::Symlink => {
+                    self.report.files += 1;
+                    if let Err(err) = child.read_link() {
+                        self.error(&child_path, format!("cannot read link: {err:?}"));
+                    }
+                }
+                _ => self.report.files += 1,
+            }
+            if self.check_links && meta.node_type != NodeType::Directory {
+                self.links.entry(meta.inode).or_insert((meta.nlink, 0)).1 += 1;
+            }
+        }
+
+        if self.check_links {
+            if let Ok(meta) = dir.metadata() {
+                let expected = 2 + subdirs.len() as u64;
+                if meta.nlink != expected {
+                    self.error(
+                        path,
+                        format!(
+                            "link count is {} but {} subdirectories were found",
+                            meta.nlink,
+                            subdirs.len()
+                        ),
+                    );
+                }
+            }
+        }
+        for (child_path, child) in subdirs {
+            self.check_dir(&child_path, &child);
+        }
+    }
+}
+
+/// Checks the whole tree of `cx`.
+pub fn check(cx: &FsContext) -> Report {
+    let root = cx.root_dir().clone();
+    let mut checker = Checker {
+        cx,
+        check_links: root.filesystem().name() == "ext4",
+        links: BTreeMap::new(),
+        report: Report::default(),
+    };
+    checker.check_dir("/", &root);
+    if checker.check_links {
+        let links = std::mem::take(&mut checker.links);
+        for (inode, (nlink, found)) in links {
+            if found != nlink {
+                checker.error(
+                    &format!("inode {inode}"),
+                    format!("link count is {nlink} but {found} entries were found"),
+                );
+            }
+        }
+    }
+    checker.report
+}
@@ -0,0 +1,72 @@
+//! A raw disk image file as a block device.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::unix::fs::FileExt,
+    path::Path,
+};
+
+use axdriver::prelude::{BaseDriverOps, BlockDriverOps, DevError, DevResult, DeviceType};
+
+const BLOCK_SIZE: usize = 512;
+
+pub struct ImageDisk {
+    file: File,
+    num_blocks: u64,
+}
+
+impl ImageDisk {
+    /// Opens the image at `path`, for writing too if `writable`. A trailing
+    /// partial block is ignored.
+    pub fn open(path: &Path, writable: bool) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(writable).open(path)?;
+        let num_blocks = file.metadata()?.len() / BLOCK_SIZE as u64;
+        Ok(Self { file, num_blocks })
+    }
+
+    fn offset(&self, block_id: u64, len: usize) -> DevResult<u64> {
+        if len % BLOCK_SIZE != 0 || block_id + (len / BLOCK_SIZE) as u64 > self.num_blocks {
+            return Err(DevError::InvalidParam);
+        }
+        Ok(block_id * BLOCK_SIZE as u64)
+    }
+}
+
+impl BaseDriverOps for ImageDisk {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+
+    fn device_name(&self) -> &str {
+        "image"
+    }
+}
+
+impl BlockDriverOps for ImageDisk {
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        let offset = self.offset(block_id, buf.len())?;
+        self.file
+            .read_exact_at(buf, offset)
+            .map_err(|_| DevError::Io)
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        let offset = self.offset(block_id, buf.len())?;
+        self.file
+            .write_all_at(buf, offset)
+            .map_err(|_| DevError::Io)
+    }
+
+    fn flush(&mut self) -> DevResult {
+        self.file.sync_data().map_err(|_| DevError::Io)
+    }
+}
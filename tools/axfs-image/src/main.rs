@@ -0,0 +1,388 @@
+//! Inspects and modifies FAT and ext4 disk images from the host, through the
+//! same filesystem code the kernel uses, without loop mounts or root
+//! privileges.
+
+mod check;
+mod disk;
+
+use std::{
+    alloc::Layout,
+    fs,
+    io::{Read, Write},
+    os::unix::fs::{PermissionsExt, symlink},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use axdriver::AxBlockDevice;
+use axfs_ng::{FsContext, MountFlags, OpenOptions};
+use axfs_ng_vfs::{NodePermission, NodeType, VfsError};
+use clap::{Parser, Subcommand};
+
+use crate::disk::ImageDisk;
+
+/// Memory given to the allocator of `axfs-ng`, which holds the page cache
+/// and the buffer cache.
+const HEAP_SIZE: usize = 256 << 20;
+
+const COPY_BUF_SIZE: usize = 64 * 1024;
+
+type Result<T = ()> = std::result::Result<T, String>;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// The raw disk image.
+    image: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lists a directory of the image.
+    Ls {
+        #[arg(default_value = "/")]
+        path: String,
+        /// Lists subdirectories too.
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Copies a host file or directory into the image.
+    CopyIn { src: PathBuf, dst: String },
+    /// Copies a file or directory of the image to the host.
+    CopyOut { src: String, dst: PathBuf },
+    /// Creates a directory in the image.
+    Mkdir {
+        path: String,
+        /// Creates missing parent directories too, and succeeds if the
+        /// directory exists.
+        #[arg(short, long)]
+        parents: bool,
+    },
+    /// Removes a file or directory from the image.
+    Rm {
+        path: String,
+        /// Removes directories and their contents.
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Checks the consistency of the filesystem.
+    Check,
+}
+
+impl Command {
+    fn modifies_image(&self) -> bool {
+        matches!(
+            self,
+            Command::CopyIn { .. } | Command::Mkdir { .. } | Command::Rm { .. }
+        )
+    }
+}
+
+fn vfs_err(path: impl AsRef<Path>) -> impl FnOnce(VfsError) -> String {
+    move |err| format!("{}: {err:?}", path.as_ref().display())
+}
+
+fn io_err(path: impl AsRef<Path>) -> impl FnOnce(std::io::Error) -> String {
+    move |err| format!("{}: {err}", path.as_ref().display())
+}
+
+/// Gives `axfs-ng` its own heap, as the kernel would at boot.
+fn init_allocator() {
+    let layout = Layout::from_size_align(HEAP_SIZE, 4096).unwrap();
+    // SAFETY: the layout has a non-zero size.
+    let heap = unsafe { std::alloc::alloc(layout) };
+    assert!(!heap.is_null(), "failed to allocate {HEAP_SIZE} bytes");
+    axalloc::global_init(heap as usize, HEAP_SIZE);
+}
+
+fn join(dir: &str, name: &str) -> String {
+    format!("{}/{name}", dir.trim_end_matches('/'))
+}
+
+fn list(cx: &FsContext, path: &str, recursive: bool) -> Result {
+    let mut entries = cx
+        .read_dir(path)
+        .map_err(vfs_err(path))?
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(vfs_err(path))?;
+    entries.retain(|entry| entry.name != "." && entry.name != "..");
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    if recursive {
+        println!("{path}:");
+    }
+    let dir = cx.resolve(path).map_err(vfs_err(path))?;
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let loc = dir
+            .lookup_no_follow(&entry.name)
+            .map_err(vfs_err(join(path, &entry.name)))?;
+        let meta = loc.metadata().map_err(vfs_err(join(path, &entry.name)))?;
+        let mut line = format!(
+            "{}{:o} {:>4} {:>5} {:>5} {:>10} {}",
+            type_char(meta.node_type),
+            meta.mode.bits() & 0o7777,
+            meta.nlink,
+            meta.uid,
+            meta.gid,
+            meta.size,
+            entry.name
+        );
+        if meta.node_type == NodeType::Symlink {
+            if let Ok(target) = loc.read_link() {
+                line += &format!(" -> {target}");
+            }
+        }
+        println!("{line}");
+        if meta.node_type == NodeType::Directory {
+            subdirs.push(join(path, &entry.name));
+        }
+    }
+    if recursive {
+        for subdir in subdirs {
+            println!();
+            list(cx, &subdir, true)?;
+        }
+    }
+    Ok(())
+}
+
+fn type_char(node_type: NodeType) -> char {
+    match node_type {
+        NodeType::Directory => 'd',
+        NodeType::Symlink => 'l',
+        NodeType::CharacterDevice => 'c',
+        NodeType::BlockDevice => 'b',
+        NodeType::Fifo => 'p',
+        NodeType::Socket => 's',
+        _ => '-',
+    }
+}
+
+fn copy_in(cx: &FsContext, src: &Path, dst: &str) -> Result {
+    let meta = fs::symlink_metadata(src).map_err(io_err(src))?;
+    let mode = meta.permissions().mode() & 0o7777;
+    if meta.is_symlink() {
+        let target = fs::read_link(src).map_err(io_err(src))?;
+        let target = target
+            .to_str()
+            .ok_or_else(|| format!("{}: target is not UTF-8", src.display()))?;
+        cx.symlink(target, dst).map_err(vfs_err(dst))?;
+    } else if meta.is_dir() {
+        match cx.create_dir(dst, NodePermission::from_bits_truncate(mode as _)) {
+            Ok(_) | Err(VfsError::EEXIST) => {}
+            Err(err) => return Err(vfs_err(dst)(err)),
+        }
+        for entry in fs::read_dir(src).map_err(io_err(src))? {
+            let entry = entry.map_err(io_err(src))?;
+            let name = entry.file_name();
+            let name = name
+                .to_str()
+                .ok_or_else(|| format!("{}: name is not UTF-8", entry.path().display()))?;
+            copy_in(cx, &entry.path(), &join(dst, name))?;
+        }
+    } else if meta.is_file() {
+        let mut src_file = fs::File::open(src).map_err(io_err(src))?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(cx, dst)
+            .and_then(|it| it.into_file())
+            .map_err(vfs_err(dst))?;
+        let mut buf = vec![0; COPY_BUF_SIZE];
+        let mut offset = 0;
+        loop {
+            let n = src_file.read(&mut buf).map_err(io_err(src))?;
+            if n == 0 {
+                break;
+            }
+            let mut chunk = &buf[..n];
+            while !chunk.is_empty() {
+                let written = file.write_at(&mut chunk, offset).map_err(vfs_err(dst))?;
+                offset += written as u64;
+            }
+        }
+    } else {
+        eprintln!(
+            "skipping {}: not a file, directory or symlink",
+            src.display()
+        );
+    }
+    Ok(())
+}
+
+fn copy_out(cx: &FsContext, src: &str, dst: &Path) -> Result {
+    let loc = cx.resolve_no_follow(src).map_err(vfs_err(src))?;
+    let meta = loc.metadata().map_err(vfs_err(src))?;
+    let permissions = fs::Permissions::from_mode((meta.mode.bits() & 0o7777) as u32);
+    match meta.node_type {
+        NodeType::Symlink => {
+            let target = loc.read_link().map_err(vfs_err(src))?;
+            symlink(target, dst).map_err(io_err(dst))?;
+        }
+        NodeType::Directory => {
+            match fs::create_dir(dst) {
+                Err(err) if err.kind() != std::io::ErrorKind::AlreadyExists => {
+                    return Err(io_err(dst)(err));
+                }
+                _ => {}
+            }
+            for entry in cx.read_dir(src).map_err(vfs_err(src))? {
+                let entry = entry.map_err(vfs_err(src))?;
+                if entry.name == "." || entry.name == ".." {
+                    continue;
+                }
+                copy_out(cx, &join(src, &entry.name), &dst.join(&entry.name))?;
+            }
+            fs::set_permissions(dst, permissions).map_err(io_err(dst))?;
+        }
+        NodeType::RegularFile => {
+            let file = OpenOptions::new()
+                .read(true)
+                .open(cx, src)
+                .and_then(|it| it.into_file())
+                .map_err(vfs_err(src))?;
+            let mut dst_file = fs::File::create(dst).map_err(io_err(dst))?;
+            let mut buf = vec![0; COPY_BUF_SIZE];
+            let mut offset = 0;
+            loop {
+                let n = file
+                    .read_at(&mut buf.as_mut_slice(), offset)
+                    .map_err(vfs_err(src))?;
+                if n == 0 {
+                    break;
+                }
+                dst_file.write_all(&buf[..n]).map_err(io_err(dst))?;
+                offset += n as u64;
+            }
+            fs::set_permissions(dst, permissions).map_err(io_err(dst))?;
+        }
+        _ => eprintln!("skipping {src}: not a file, directory or symlink"),
+    }
+    Ok(())
+}
+
+fn mkdir(cx: &FsContext, path: &str, parents: bool) -> Result {
+    let mode = NodePermission::from_bits_truncate(0o755);
+    if !parents {
+        return cx.create_dir(path, mode).map(drop).map_err(vfs_err(path));
+    }
+    let mut current = String::new();
+    for component in path.split('/').filter(|it| !it.is_empty()) {
+        current = join(&current, component);
+        match cx.create_dir(&current, mode) {
+            Ok(_) => {}
+            Err(VfsError::EEXIST) if cx.resolve(&current).is_ok_and(|it| it.is_dir()) => {}
+            Err(err) => return Err(vfs_err(&current)(err)),
+        }
+    }
+    Ok(())
+}
+
+fn remove(cx: &FsContext, path: &str, recursive: bool) -> Result {
+    let loc = cx.resolve_no_follow(path).map_err(vfs_err(path))?;
+    if !loc.is_dir() {
+        return cx.remove_file(path).map_err(vfs_err(path));
+    }
+    if !recursive {
+        return Err(format!("{path}: is a directory, use --recursive"));
+    }
+    let names = cx
+        .read_dir(path)
+        .map_err(vfs_err(path))?
+        .map(|entry| entry.map(|it| it.name))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(vfs_err(path))?;
+    for name in names {
+        if name != "." && name != ".." {
+            remove(cx, &join(path, &name), true)?;
+        }
+    }
+    cx.remove_dir(path).map_err(vfs_err(path))
+}
+
+fn open_image(image: &Path, writable: bool) -> Result<FsContext> {
+    let disk = ImageDisk::open(image, writable).map_err(io_err(image))?;
+    let fs =
+        axfs_ng::fs::new_default(AxBlockDevice::new(Box::new(disk))).map_err(vfs_err(image))?;
+    let flags = if writable {
+        MountFlags::empty()
+    } else {
+        MountFlags::RDONLY
+    };
+    let root = axfs_ng::mount_root(image.display().to_string(), &fs, flags);
+    Ok(FsContext::new(root))
+}
+
+fn run(cli: Cli) -> Result<ExitCode> {
+    let cx = open_image(&cli.image, cli.command.modifies_image())?;
+    let result = run_command(&cx, cli.command);
+    // The contents of open files are gone with `cx`, but their dirty pages
+    // and the dirty metadata still need to reach the image, including the
+    // changes made before a command failed halfway.
+    drop(cx);
+    let synced = axfs_ng::sync_all().map_err(vfs_err(&cli.image));
+    let code = result?;
+    synced?;
+    Ok(code)
+}
+
+fn run_command(cx: &FsContext, command: Command) -> Result<ExitCode> {
+    let mut code = ExitCode::SUCCESS;
+    match command {
+        Command::Ls { path, recursive } => list(cx, &path, recursive)?,
+        Command::CopyIn { src, dst } => {
+            // Like `cp`, copying into an existing directory keeps the name.
+            let dst = match cx.resolve(&dst) {
+                Ok(loc) if loc.is_dir() => match src.file_name().and_then(|it| it.to_str()) {
+                    Some(name) => join(&dst, name),
+                    None => dst,
+                },
+                _ => dst,
+            };
+            copy_in(cx, &src, &dst)?;
+        }
+        Command::CopyOut { src, dst } => {
+            let dst = if dst.is_dir() {
+                let name = src.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+                if name.is_empty() { dst } else { dst.join(name) }
+            } else {
+                dst
+            };
+            copy_out(cx, &src, &dst)?;
+        }
+        Command::Mkdir { path, parents } => mkdir(cx, &path, parents)?,
+        Command::Rm { path, recursive } => remove(cx, &path, recursive)?,
+        Command::Check => {
+            let report = check::check(cx);
+            for error in &report.errors {
+                println!("{error}");
+            }
+            println!(
+                "{} directories, {} other files, {} errors",
+                report.dirs,
+                report.files,
+                report.errors.len()
+            );
+            if !report.errors.is_empty() {
+                code = ExitCode::FAILURE;
+            }
+        }
+    }
+    Ok(code)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    init_allocator();
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("axfs-image: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}